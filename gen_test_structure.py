@@ -2,7 +2,4 @@ from os import mkdir
 
 if __name__ == "__main__":
     mkdir("test")
-    mkdir("test/crane")
-    mkdir("test/data")
-    mkdir("test/disk")
-    mkdir("test/partitions")
\ No newline at end of file
+    mkdir("test/disk")
\ No newline at end of file
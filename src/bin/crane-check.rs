@@ -0,0 +1,33 @@
+use std::{env, process};
+
+use crane::check_file;
+
+/// Checks a crane disk for corruption, exits with 1 if it is corrupt and 2 if it can't be checked
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: crane-check <disk file>");
+            process::exit(2);
+        }
+    };
+
+    let report = match check_file(&path) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(2);
+        }
+    };
+
+    for issue in report.issues.iter() {
+        let level = if issue.is_error() { "error" } else { "warning" };
+        println!("{}: {}", level, issue);
+    }
+    let errors = report.issues.iter().filter(|i| i.is_error()).count();
+    println!("{}: {} partitions, {} schemas, {} keys checked, {} errors", path, report.partitions, report.schemas, report.keys, errors);
+
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{io, sync::{Arc, RwLock}};
+
+use super::{CraneDisk, CranePartition, FSError, Reader, Writer, wal::WAL_SECTORS};
+
+/// The longest blob that can be stored inside its row, longer ones are written to an overflow
+/// partition of their own with a `BlobWriter`
+pub const MAX_INLINE_BLOB_LEN: u64 = 1024;
+/// The number of sectors given to a new overflow partition
+const OVERFLOW_SECTORS: u64 = 16;
+
+/// Where a blob stored outside of its row is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlobRef {
+    /// The id of the overflow partition holding the blob's bytes
+    pub partition: u64,
+    /// The byte length of the blob
+    pub len: u64,
+}
+
+/// The bytes of a blob column.
+///
+/// Short blobs are kept inside their row. Long ones live in an overflow partition of their own,
+/// the row only keeps a reference to it and their bytes are streamed with a `BlobReader`.
+/// The two are never equal, even when they hold the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Blob {
+    /// Bytes stored inside the row, at most `MAX_INLINE_BLOB_LEN` of them
+    Inline(Vec<u8>),
+    /// Bytes stored in an overflow partition
+    Overflow(BlobRef),
+}
+
+impl Blob {
+    /// The byte length of the blob.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        match self {
+            Blob::Inline(bytes) => bytes.len() as u64,
+            Blob::Overflow(blob) => blob.len,
+        }
+    }
+}
+
+/// Turns a disk error into an i/o error, unwrapping errors of the file itself.
+fn io_error(err: FSError) -> io::Error {
+    match err {
+        FSError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// Streams a blob into an overflow partition of its own, growing the partition as it fills.
+///
+/// The bytes are committed as they are written so blobs larger than the write ahead log can be
+/// stored. A blob whose writer is dropped without being finished is left for compaction to drop,
+/// checking the disk reports it as an orphaned blob.
+pub struct BlobWriter<'a> {
+    disk: &'a mut CraneDisk,
+    partition: Arc<RwLock<CranePartition>>,
+    len: u64,
+}
+
+impl<'a> BlobWriter<'a> {
+    /// Creates the overflow partition of a new blob. The blob is committed as it is written, so
+    /// this fails with `FSError::UncommittedWrites` if the disk has writes that aren't committed yet.
+    /// # Arguments
+    /// * `disk` - The disk to store the blob on.
+    /// * `partition_type` - The type of the overflow partition.
+    pub fn new(disk: &'a mut CraneDisk, partition_type: u64) -> Result<Self, FSError> {
+        if disk.has_pending() {
+            return Err(FSError::UncommittedWrites);
+        }
+        let id = disk.append_partition(OVERFLOW_SECTORS, partition_type)?;
+        let partition = disk.get_partition_with_id(id)?.clone();
+
+        Ok(BlobWriter {
+            disk,
+            partition,
+            len: 0,
+        })
+    }
+
+    /// Appends bytes to the blob, committing them a part of the write ahead log at a time.
+    /// # Arguments
+    /// * `bytes` - The bytes to append.
+    pub fn append(&mut self, bytes: &[u8]) -> Result<(), FSError> {
+        let (id, sl) = {
+            let p = self.partition.read().unwrap();
+            (p.id(), p.sector_length())
+        };
+
+        for part in bytes.chunks((WAL_SECTORS*sl/4) as usize) {
+            let total_bytes = self.partition.read().unwrap().total_bytes();
+            let end = self.len + part.len() as u64;
+            if end > total_bytes {
+                // Doubling keeps the number of extents low for large blobs
+                let needed = (end - total_bytes).div_ceil(sl);
+                self.disk.grow_partition(id, u64::max(needed, total_bytes/sl))?;
+            }
+
+            self.partition.write().unwrap().write_sectors(0, self.len, part)?;
+            self.len = end;
+            self.disk.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Commits the blob, returning where it is kept to be stored in a row.
+    pub fn finish(self) -> Result<BlobRef, FSError> {
+        self.disk.commit()?;
+        Ok(BlobRef {
+            partition: self.partition.read().unwrap().id(),
+            len: self.len,
+        })
+    }
+}
+
+impl io::Write for BlobWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.append(buf).map_err(io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams the bytes of a blob out of its overflow partition, reading only the sectors asked for.
+pub struct BlobReader {
+    partition: Arc<RwLock<CranePartition>>,
+    len: u64,
+    position: u64,
+}
+
+impl BlobReader {
+    /// Opens a blob for reading.
+    /// # Arguments
+    /// * `partition` - The overflow partition holding the blob.
+    /// * `blob` - Where the blob is kept.
+    pub fn new(partition: Arc<RwLock<CranePartition>>, blob: &BlobRef) -> Result<Self, FSError> {
+        {
+            let p = partition.read().unwrap();
+            if p.id() != blob.partition || p.initialized_len < blob.len {
+                return Err(FSError::CorruptData(format!("partition {} doesn't hold a {} byte blob", p.id(), blob.len)));
+            }
+        }
+
+        Ok(BlobReader {
+            partition,
+            len: blob.len,
+            position: 0,
+        })
+    }
+
+    /// The byte length of the blob.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> u64 {
+        self.len - self.position
+    }
+}
+
+impl io::Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = u64::min(buf.len() as u64, self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let mut partition = self.partition.write().unwrap();
+        let sl = partition.sector_length();
+        let (start, end) = (self.position/sl, (self.position + len).div_ceil(sl));
+        let bytes = partition.read_sectors(start, end).map_err(io_error)?;
+
+        let from = (self.position % sl) as usize;
+        buf[..(len as usize)].copy_from_slice(&bytes[from..(from + len as usize)]);
+        self.position += len;
+        Ok(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use crate::cfs::{PartitionKind, PartitionType};
+
+    use super::*;
+
+    #[test]
+    fn test_stream_blob() {
+        let mut disk = CraneDisk::in_memory();
+        let bytes: Vec<u8> = (0..(300*1024u32)).map(|i| (i % 251) as u8).collect();
+
+        let mut writer = BlobWriter::new(&mut disk, PartitionType::new(PartitionKind::Overflow, 0).raw()).unwrap();
+        for chunk in bytes.chunks(7000) {
+            writer.write_all(chunk).unwrap();
+        }
+        // Larger than the write ahead log in one go
+        writer.write_all(&bytes).unwrap();
+        let blob = writer.finish().unwrap();
+        assert_eq!(blob.len, 2*bytes.len() as u64);
+
+        let partition = disk.get_partition_with_id(blob.partition).unwrap().clone();
+        let mut reader = BlobReader::new(partition.clone(), &blob).unwrap();
+        let mut first = [0u8; 10];
+        reader.read_exact(&mut first).unwrap();
+        assert_eq!(first[..], bytes[..10]);
+        assert_eq!(reader.remaining(), blob.len - 10);
+
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest[..], [&bytes[10..], &bytes[..]].concat()[..]);
+
+        let too_long = BlobRef { len: blob.len + 1, ..blob };
+        assert!(matches!(BlobReader::new(partition, &too_long), Err(FSError::CorruptData(_))));
+    }
+}
@@ -0,0 +1,82 @@
+use super::FSError;
+
+/// The bytes of the storage a partition's reader or writer may touch, the partition can be
+/// made of several extents scattered over the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bounds {
+    /// The id of the partition the bounds belong to
+    pub partition: u64,
+    /// The first byte and the byte after the last one of every extent of the partition
+    ranges: Vec<(u64, u64)>,
+}
+
+impl Bounds {
+    /// Creates the bounds of a partition.
+    /// # Arguments
+    /// * `partition` - The id of the partition.
+    /// * `extents` - The start sector and sector length of every extent of the partition.
+    /// * `sector_length` - The byte length of the sectors.
+    pub fn new(partition: u64, extents: &[(u64, u64)], sector_length: u64) -> Self {
+        Bounds {
+            partition,
+            ranges: extents.iter().map(|(s, l)| (s*sector_length, (s + l)*sector_length)).collect(),
+        }
+    }
+
+    /// The byte length of the partition
+    pub fn len(&self) -> u64 {
+        self.ranges.iter().map(|(s, e)| e - s).sum()
+    }
+
+    /// Checks that an access to the storage stays inside a single extent of the partition.
+    ///
+    /// With the `paranoid` feature every rejected access is also logged to stderr.
+    /// # Arguments
+    /// * `start` - The first byte accessed, from the start of the storage.
+    /// * `end` - The byte after the last one accessed, from the start of the storage.
+    pub fn check(&self, start: u64, end: u64) -> Result<(), FSError> {
+        if start <= end && self.ranges.iter().any(|(s, e)| start >= *s && end <= *e) {
+            return Ok(());
+        }
+
+        let first = self.ranges.first().map(|(s, _)| *s).unwrap_or(0);
+        let err = FSError::OutOfBounds {
+            partition: self.partition,
+            start: start.saturating_sub(first),
+            end: end.saturating_sub(first),
+            len: self.len(),
+        };
+        #[cfg(feature = "paranoid")]
+        eprintln!("crane: rejected access to partition {} at storage bytes {}..{} ({})", self.partition, start, end, err);
+
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bounds_check() {
+        let bounds = Bounds::new(3, &[(2, 2)], 256);
+
+        assert_eq!(bounds.len(), 512);
+        assert!(bounds.check(512, 1024).is_ok());
+        assert!(bounds.check(600, 600).is_ok());
+        assert!(matches!(bounds.check(1000, 1025), Err(FSError::OutOfBounds { partition: 3, start: 488, end: 513, len: 512 })));
+        assert!(matches!(bounds.check(0, 10), Err(FSError::OutOfBounds { partition: 3, .. })));
+        assert!(matches!(bounds.check(700, 600), Err(FSError::OutOfBounds { .. })));
+    }
+
+    #[test]
+    fn test_scattered_bounds() {
+        let bounds = Bounds::new(1, &[(2, 1), (8, 2)], 256);
+
+        assert_eq!(bounds.len(), 768);
+        assert!(bounds.check(512, 768).is_ok());
+        assert!(bounds.check(2048, 2560).is_ok());
+        assert!(bounds.check(768, 1024).is_err());
+        assert!(bounds.check(700, 2100).is_err());
+    }
+}
@@ -1,21 +1,132 @@
+use std::borrow::Cow;
 
-pub struct Buffer {
-    raw: Vec<u8>
+use super::FSError;
+
+/// A read cursor over bytes, either owned or borrowed, that values are parsed from front to back
+/// without copying the bytes they are read out of.
+pub struct Buffer<'a> {
+    raw: Cow<'a, [u8]>,
+    pos: usize,
 }
 
-impl Buffer {
-    pub fn new(raw: Vec<u8>) -> Buffer {
-        
+impl Buffer<'static> {
+    /// Creates a buffer owning its bytes.
+    /// # Arguments
+    /// * `raw` - The bytes to parse.
+    pub fn new(raw: Vec<u8>) -> Self {
         Buffer {
-            raw
+            raw: Cow::Owned(raw),
+            pos: 0,
         }
     }
+}
 
-    pub fn consume(&mut self, bytes: u64) -> Vec<u8> {
-        (0..bytes).map(|_i| self.raw.remove(0)).collect()
+impl<'a> Buffer<'a> {
+    /// Creates a buffer over borrowed bytes.
+    /// # Arguments
+    /// * `raw` - The bytes to parse.
+    pub fn borrowed(raw: &'a [u8]) -> Self {
+        Buffer {
+            raw: Cow::Borrowed(raw),
+            pos: 0,
+        }
     }
 
-    pub fn empty(&self) -> bool {
-        self.raw.is_empty()
+    /// Reads the next bytes, failing without moving the cursor if fewer are left.
+    /// # Arguments
+    /// * `len` - The number of bytes to read.
+    pub fn read_bytes(&mut self, len: u64) -> Result<&[u8], FSError> {
+        let remaining = self.remaining();
+        if len > remaining {
+            return Err(FSError::UnexpectedEnd { needed: len, remaining });
+        }
+
+        let start = self.pos;
+        self.pos += len as usize;
+        Ok(&self.raw[start..self.pos])
+    }
+
+    /// Reads the next fixed number of bytes as an array.
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], FSError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N as u64)?);
+        Ok(array)
     }
-}
\ No newline at end of file
+
+    /// Reads a big endian u8.
+    pub fn read_u8(&mut self) -> Result<u8, FSError> {
+        Ok(u8::from_be_bytes(self.read_array()?))
+    }
+
+    /// Reads a big endian u16.
+    pub fn read_u16(&mut self) -> Result<u16, FSError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    /// Reads a big endian u32.
+    pub fn read_u32(&mut self) -> Result<u32, FSError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    /// Reads a big endian u64.
+    pub fn read_u64(&mut self) -> Result<u64, FSError> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    /// Moves the cursor past bytes without reading them.
+    /// # Arguments
+    /// * `len` - The number of bytes to skip.
+    pub fn skip(&mut self, len: u64) -> Result<(), FSError> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    /// The number of bytes read so far.
+    pub fn position(&self) -> u64 {
+        self.pos as u64
+    }
+
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> u64 {
+        (self.raw.len() - self.pos) as u64
+    }
+
+    /// Whether every byte has been read.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_typed_reads() {
+        let mut bytes = vec![7u8];
+        bytes.extend_from_slice(&513u16.to_be_bytes());
+        bytes.extend_from_slice(&70000u32.to_be_bytes());
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        bytes.extend_from_slice(b"end");
+        let mut buffer = Buffer::new(bytes);
+
+        assert_eq!(buffer.read_u8().unwrap(), 7);
+        assert_eq!(buffer.read_u16().unwrap(), 513);
+        assert_eq!(buffer.read_u32().unwrap(), 70000);
+        assert_eq!(buffer.read_u64().unwrap(), u64::MAX);
+        assert_eq!(buffer.position(), 15);
+        assert_eq!(buffer.read_bytes(3).unwrap(), b"end");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_short_input() {
+        let bytes = [1u8, 2, 3];
+        let mut buffer = Buffer::borrowed(&bytes);
+
+        buffer.skip(1).unwrap();
+        assert!(matches!(buffer.read_u32(), Err(FSError::UnexpectedEnd { needed: 4, remaining: 2 })));
+        assert_eq!(buffer.remaining(), 2);
+        assert_eq!(buffer.read_u16().unwrap(), 0x0203);
+        assert!(matches!(buffer.read_u8(), Err(FSError::UnexpectedEnd { needed: 1, remaining: 0 })));
+    }
+}
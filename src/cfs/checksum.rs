@@ -0,0 +1,48 @@
+/// The checksum a disk keeps for each of its sectors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumKind {
+    /// Sectors aren't checksummed
+    #[default]
+    None,
+    /// CRC-32 (IEEE)
+    Crc32,
+    /// 32 bit xxHash, faster than CRC-32 on most machines
+    XxHash32,
+}
+
+impl ChecksumKind {
+    /// The number of bytes each checksum takes up
+    pub const LEN: u64 = 4;
+
+    /// The id the kind is recorded as in the disk header.
+    pub fn id(&self) -> u32 {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32 => 1,
+            ChecksumKind::XxHash32 => 2,
+        }
+    }
+
+    /// Gets the kind recorded in a disk header, if it is a known one.
+    /// # Arguments
+    /// * `id` - The id from the header.
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(ChecksumKind::None),
+            1 => Some(ChecksumKind::Crc32),
+            2 => Some(ChecksumKind::XxHash32),
+            _ => None,
+        }
+    }
+
+    /// Computes the checksum of some bytes.
+    /// # Arguments
+    /// * `bytes` - The bytes to checksum.
+    pub fn digest(&self, bytes: &[u8]) -> u32 {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32 => crc32fast::hash(bytes),
+            ChecksumKind::XxHash32 => xxhash_rust::xxh32::xxh32(bytes, 0),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::{cmp::min, convert::TryInto, sync::{Arc, RwLock}};
+
+use super::{FSError, checksum::ChecksumKind, storage::Storage};
+
+/// A storage that keeps a checksum for every sector of the storage it wraps and verifies it
+/// on every read.
+///
+/// The first `base` sectors are passed through unchecked. After them the wrapped storage is
+/// split into groups, each starting with a sector holding the checksums of the data sectors
+/// that follow it. Offsets given to this storage only count the data sectors.
+pub struct ChecksumStorage {
+    inner: Arc<RwLock<dyn Storage>>,
+    kind: ChecksumKind,
+    sector_length: u64,
+    base: u64,
+}
+
+impl ChecksumStorage {
+    /// Wraps a storage.
+    /// # Arguments
+    /// * `inner` - The storage to checksum.
+    /// * `kind` - The checksum to keep.
+    /// * `sector_length` - The byte length of the sectors being checksummed.
+    /// * `base` - How many sectors at the start of the storage aren't checksummed.
+    pub fn new(inner: Arc<RwLock<dyn Storage>>, kind: ChecksumKind, sector_length: u64, base: u64) -> Self {
+        ChecksumStorage {
+            inner,
+            kind,
+            sector_length,
+            base,
+        }
+    }
+
+    /// The number of data sectors covered by each checksum sector
+    fn group_len(&self) -> u64 {
+        self.sector_length/ChecksumKind::LEN
+    }
+
+    /// The sector of the wrapped storage a data sector is kept in
+    fn physical_sector(&self, sector: u64) -> u64 {
+        if sector < self.base {
+            return sector;
+        }
+        let j = sector - self.base;
+        self.base + (j/self.group_len())*(self.group_len() + 1) + 1 + j % self.group_len()
+    }
+
+    /// The byte of the wrapped storage a data sector's checksum is kept at
+    fn checksum_byte(&self, sector: u64) -> u64 {
+        let j = sector - self.base;
+        (self.base + (j/self.group_len())*(self.group_len() + 1))*self.sector_length + (j % self.group_len())*ChecksumKind::LEN
+    }
+
+    /// Reads a whole data sector, verifying it against its checksum.
+    fn read_sector(&self, sector: u64) -> Result<Vec<u8>, FSError> {
+        let mut inner = self.inner.write().unwrap();
+        let bytes = inner.read_at(self.physical_sector(sector)*self.sector_length, self.sector_length)?;
+        let stored = inner.read_at(self.checksum_byte(sector), ChecksumKind::LEN)?;
+
+        let stored = u32::from_be_bytes(stored[..].try_into().map_err(|_| FSError::ChecksumMismatch { sector })?);
+        if bytes.len() as u64 != self.sector_length || self.kind.digest(&bytes) != stored {
+            return Err(FSError::ChecksumMismatch { sector });
+        }
+
+        Ok(bytes)
+    }
+
+    /// Writes a whole data sector followed by its checksum.
+    fn write_sector(&self, sector: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let mut inner = self.inner.write().unwrap();
+
+        inner.write_at(self.physical_sector(sector)*self.sector_length, bytes)?;
+        inner.write_at(self.checksum_byte(sector), &self.kind.digest(bytes).to_be_bytes())
+    }
+}
+
+impl Storage for ChecksumStorage {
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        let end = min(byte + len, self.len()?);
+        let sl = self.sector_length;
+        let mut buffer = Vec::with_capacity(end.saturating_sub(byte) as usize);
+
+        let mut curr = byte;
+        while curr < end {
+            let sector = curr / sl;
+            let s = curr % sl;
+            let e = min(end, (sector + 1)*sl) - sector*sl;
+
+            if sector < self.base {
+                buffer.append(&mut self.inner.write().unwrap().read_at(curr, e - s)?);
+            } else {
+                buffer.extend_from_slice(&self.read_sector(sector)?[(s as usize)..(e as usize)]);
+            }
+            curr = sector*sl + e;
+        }
+
+        Ok(buffer)
+    }
+
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let end = byte + bytes.len() as u64;
+        let sl = self.sector_length;
+        let old_len = self.len()?;
+
+        let mut curr = byte;
+        while curr < end {
+            let sector = curr / sl;
+            let s = curr % sl;
+            let e = min(end, (sector + 1)*sl) - sector*sl;
+            let from = (curr - byte) as usize;
+            let part = &bytes[from..(from + (e - s) as usize)];
+
+            if sector < self.base {
+                self.inner.write().unwrap().write_at(curr, part)?;
+            } else {
+                // Only whole sectors can be checksummed, so partial writes are merged into the old bytes
+                let mut whole = if e - s < sl && sector*sl < old_len {
+                    self.read_sector(sector)?
+                } else {
+                    vec![0u8; sl as usize]
+                };
+                whole[(s as usize)..(e as usize)].copy_from_slice(part);
+
+                self.write_sector(sector, &whole)?;
+            }
+            curr = sector*sl + e;
+        }
+
+        Ok(())
+    }
+
+    fn len(&self) -> Result<u64, FSError> {
+        let len = self.inner.read().unwrap().len()?;
+        let sectors = len/self.sector_length;
+        if sectors <= self.base {
+            return Ok(len);
+        }
+
+        let rest = sectors - self.base;
+        let data = (rest/(self.group_len() + 1))*self.group_len() + (rest % (self.group_len() + 1)).saturating_sub(1);
+
+        Ok((self.base + data)*self.sector_length)
+    }
+
+    fn flush(&mut self) -> Result<(), FSError> {
+        self.inner.write().unwrap().flush()
+    }
+
+    fn sync(&mut self) -> Result<(), FSError> {
+        self.inner.write().unwrap().sync()
+    }
+
+    /// Sectors can only be checksummed whole, so the length is rounded up to a whole sector.
+    fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+        let sectors = len.div_ceil(self.sector_length);
+        let physical = match sectors {
+            0 => 0,
+            s => (self.physical_sector(s - 1) + 1)*self.sector_length,
+        };
+
+        self.inner.write().unwrap().truncate(physical)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cfs::memory_storage::MemoryStorage;
+
+    use super::*;
+
+    const SL: u64 = 128;
+
+    fn create_storage(kind: ChecksumKind) -> (Arc<RwLock<MemoryStorage>>, ChecksumStorage) {
+        let inner = Arc::new(RwLock::new(MemoryStorage::new()));
+        let storage = ChecksumStorage::new(inner.clone(), kind, SL, 1);
+
+        (inner, storage)
+    }
+
+    #[test]
+    fn test_checksummed_round_trip() {
+        let (inner, mut storage) = create_storage(ChecksumKind::Crc32);
+
+        // Fill a whole group of 32 data sectors and start the next
+        storage.write_at(0, &vec![0u8; (SL*40) as usize]).unwrap();
+        storage.write_at(SL*33 + 10, &[1, 2, 3]).unwrap();
+
+        assert_eq!(storage.len().unwrap(), SL*40);
+        assert_eq!(inner.read().unwrap().len().unwrap(), SL*42);
+        assert_eq!(storage.read_at(SL*33 + 9, 5).unwrap(), vec![0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_detect_corruption() {
+        for kind in [ChecksumKind::Crc32, ChecksumKind::XxHash32].iter() {
+            let (inner, mut storage) = create_storage(*kind);
+
+            storage.write_at(0, &vec![7u8; (SL*4) as usize]).unwrap();
+            storage.read_at(0, SL*4).unwrap();
+
+            // Sector 2 is kept after the unchecked sector, the checksum sector and sector 1
+            let byte = SL*3 + 5;
+            let old = inner.write().unwrap().read_at(byte, 1).unwrap()[0];
+            inner.write().unwrap().write_at(byte, &[old ^ 1]).unwrap();
+
+            assert_eq!(storage.read_at(0, SL*2).unwrap().len() as u64, SL*2);
+            assert!(matches!(storage.read_at(SL*2, 1), Err(FSError::ChecksumMismatch { sector: 2 })));
+            assert!(matches!(storage.write_at(SL*2, &[1]), Err(FSError::ChecksumMismatch { sector: 2 })));
+        }
+    }
+}
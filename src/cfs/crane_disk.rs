@@ -1,174 +1,1498 @@
-use std::{cell::RefCell, fs::File, io::{Seek, SeekFrom, Write}, rc::Rc};
+use std::{fs::{self, File, TryLockError}, io::Write, path::Path, sync::{Arc, RwLock}, time::{Duration, Instant}};
 
 use crate::SECTOR_LENGTH;
 
-use super::{crane_partition::CranePartition, root_partition::{self, RootPartition}};
+use super::{FSError, checksum::ChecksumKind, checksum_storage::ChecksumStorage, encrypted_storage::EncryptedStorage, encryption::{EncryptionKey, EncryptionKind, SectorCipher}, header::{DiskHeader, HEADER_SECTORS, HeaderError, is_valid_sector_length}, crane_partition::CranePartition, file_storage::FileStorage, memory_storage::MemoryStorage, mmap_storage::MmapStorage, page_cache::{CacheStats, DEFAULT_CACHE_SECTORS, PageCache}, storage::Storage, crane_reader::CraneReader, reader::Reader, root_partition::{FREE_PARTITION_TYPE, RootPartition}, wal::{WAL_SECTORS, WriteAheadLog}, wal_reader::WalReader, wal_writer::WalWriter, writer::Writer};
 
+/// The number of sectors after the header holding the partition map
+const ROOT_SECTORS: u64 = 12;
+/// The sector the partition map starts at
+const ROOT_START: u64 = HEADER_SECTORS;
+/// The sector the write ahead log starts at
+const WAL_START: u64 = ROOT_START + ROOT_SECTORS;
+/// The number of sectors added to the partition map each time it runs out of room
+const ROOT_BLOCK_SECTORS: u64 = 16;
+
+/// How a new disk is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskOptions {
+    /// The byte length of the disk's sectors, a power of two from 128 to 65536
+    pub sector_length: u64,
+    /// The checksum kept for every sector and verified whenever it is read
+    pub checksum: ChecksumKind,
+    /// The encryption of every sector after the header, the disk can only be initialized and
+    /// opened with a key if there is one
+    pub encryption: EncryptionKind,
+}
+
+impl Default for DiskOptions {
+    fn default() -> Self {
+        DiskOptions {
+            sector_length: SECTOR_LENGTH as u64,
+            checksum: ChecksumKind::None,
+            encryption: EncryptionKind::None,
+        }
+    }
+}
+
+/// When a disk forces its writes through to stable storage. Writes that are only handed to the
+/// operating system survive the process crashing but can be lost, or torn, by a power loss.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Never, writes are only handed to the operating system
+    None,
+    /// Whenever the disk is saved explicitly, commands only hand their writes to the operating system
+    OnSave,
+    /// Whenever the disk is saved and after every command
+    #[default]
+    EveryCommand,
+    /// Whenever a save or command comes at least the interval after the last sync, so the
+    /// commands of an interval share one sync, and when the disk is dropped with commands unsynced
+    GroupCommit(Duration),
+}
+
+/// How a disk file is opened by path
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    /// Whether to create the disk if the file doesn't exist or is empty
+    pub create: bool,
+    /// Whether to only read the disk, sharing it with other readers instead of locking it exclusively
+    pub read_only: bool,
+    /// How the disk is laid out if it is created
+    pub layout: DiskOptions,
+    /// When the disk forces its writes through to stable storage
+    pub durability: Durability,
+    /// The key the disk is encrypted with, needed if it is encrypted or created with encryption
+    pub key: Option<EncryptionKey>,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions {
+            create: true,
+            read_only: false,
+            layout: DiskOptions::default(),
+            durability: Durability::default(),
+            key: None,
+        }
+    }
+}
 
 pub struct CraneDisk {
+    header: DiskHeader,
     pub root_partition: RootPartition,
-    pub partitions: Vec<Rc<RefCell<CranePartition>>>,
-    read_file: Rc<RefCell<File>>,
-    write_file: Rc<RefCell<File>>,
+    pub partitions: Vec<Arc<RwLock<CranePartition>>>,
+    backing: Arc<RwLock<dyn Storage>>,
+    storage: Arc<RwLock<dyn Storage>>,
+    cache: Arc<RwLock<PageCache>>,
+    wal: Arc<RwLock<WriteAheadLog>>,
+    durability: Durability,
+    last_sync: Instant,
+    /// Whether a commit was only handed to the operating system since the last sync
+    unsynced: bool,
+    /// Whether the pending writes belong to a command that hasn't been committed or discarded
+    in_command: bool,
+    key: Option<EncryptionKey>,
 }
 
 impl CraneDisk {
-    /// Loads a disk from a file.
+    /// Loads a disk from a file, replaying the last batch of writes if it was committed
+    /// to the write ahead log but not applied before the process stopped.
+    /// Fails if the file isn't a crane disk this version can read.
     /// # Arguments
     /// * `read_file` - The file to read from.
     /// * `write_file` - The file to write to.
-    pub fn from_file(read_file: File, write_file: File) -> Self {
-        let read_rc = Rc::new(RefCell::new(read_file));
-        let write_rc = Rc::new(RefCell::new(write_file));
-
-        let rpartition = CranePartition::new(0, 0, 12, 12, 
-            Rc::downgrade(&read_rc), Rc::downgrade(&write_rc));
-
-        let root_partition = RootPartition::import_from(rpartition);
-        let total_lens = &root_partition.compute_lens();
-        let init_lens = &root_partition.init_lens;
-        let types = &root_partition.partition_types;
-
-        let partition_map: Vec<Rc<RefCell<CranePartition>>> = root_partition.partition_starts.iter()
-            .filter(|x| **x != 0)
-            .enumerate()
-            .map(|(i, v)| CranePartition::with_type((i+1) as u64, *v, total_lens[i], init_lens[i],
-            types[i], Rc::downgrade(&read_rc), Rc::downgrade(&write_rc)))
-            .map(|v| Rc::new(RefCell::new(v)))
-            .collect();
+    pub fn from_file(read_file: File, write_file: File) -> Result<Self, FSError> {
+        Self::from_storage(Arc::new(RwLock::new(FileStorage::new(read_file, write_file))))
+    }
+
+    /// Initializes a new disk to a file.
+    /// # Arguments
+    /// * 'read_file' - The file to read from.
+    /// * `write_file` - The file to write to.
+    pub fn init_file(read_file: File, write_file: File) -> Result<Self, FSError> {
+        Self::init_file_with_options(read_file, write_file, DiskOptions::default())
+    }
+
+    /// Initializes a new disk to a file laid out with the given options, for example with sectors
+    /// matching the block size of the filesystem the file is on.
+    /// # Arguments
+    /// * 'read_file' - The file to read from.
+    /// * `write_file` - The file to write to.
+    /// * `options` - The sector length and checksum of the disk.
+    pub fn init_file_with_options(read_file: File, write_file: File, options: DiskOptions) -> Result<Self, FSError> {
+        Self::init_storage_with_options(Arc::new(RwLock::new(FileStorage::new(read_file, write_file))), options)
+    }
+
+    /// Opens the disk at a path through a single file handle, creating it if the options allow.
+    ///
+    /// The file is locked for as long as the disk is open, exclusively for writers and shared for
+    /// readers, fails with `FSError::Locked` if another handle already holds a conflicting lock.
+    /// A read only disk can't replay a batch left in the write ahead log, it fails with
+    /// `FSError::UnappliedLog` until the disk is opened for writing once.
+    /// # Arguments
+    /// * `path` - The path of the disk file.
+    /// * `options` - Whether to create the disk, whether to only read it, how to lay it out, when to sync it and its key.
+    pub fn open<P: AsRef<Path>>(path: P, options: OpenOptions) -> Result<Self, FSError> {
+        let path = path.as_ref();
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(!options.read_only)
+            .create(options.create && !options.read_only)
+            .truncate(false)
+            .open(path)?;
+
+        let locked = if options.read_only { file.try_lock_shared() } else { file.try_lock() };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(FSError::Locked(path.to_path_buf())),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        let empty = file.metadata()?.len() == 0;
+        let storage = Arc::new(RwLock::new(FileStorage::from_file(file)?));
+        let mut disk = if empty && options.create && !options.read_only {
+            Self::init_storage_with_key(storage, options.layout, options.key.as_ref())?
+        } else {
+            Self::load(storage, options.key.as_ref(), options.read_only)?
+        };
+        disk.set_durability(options.durability);
+        Ok(disk)
+    }
+
+    /// Loads a disk from a memory mapped file, like `from_file` but reading and writing through
+    /// the map instead of seeking, which suits read heavy workloads.
+    /// # Arguments
+    /// * `file` - The file to map, opened for both reading and writing.
+    pub fn from_mmap_file(file: File) -> Result<Self, FSError> {
+        Self::from_storage(Arc::new(RwLock::new(MmapStorage::new(file)?)))
+    }
+
+    /// Initializes a new disk to a memory mapped file.
+    /// # Arguments
+    /// * `file` - The file to map, opened for both reading and writing.
+    pub fn init_mmap_file(file: File) -> Result<Self, FSError> {
+        Self::init_mmap_file_with_options(file, DiskOptions::default())
+    }
+
+    /// Initializes a new disk to a memory mapped file laid out with the given options.
+    /// # Arguments
+    /// * `file` - The file to map, opened for both reading and writing.
+    /// * `options` - The sector length and checksum of the disk.
+    pub fn init_mmap_file_with_options(file: File, options: DiskOptions) -> Result<Self, FSError> {
+        Self::init_storage_with_options(Arc::new(RwLock::new(MmapStorage::new(file)?)), options)
+    }
+
+    /// Initializes a new disk that only lives in memory.
+    pub fn in_memory() -> Self {
+        Self::init_storage(Arc::new(RwLock::new(MemoryStorage::new())))
+            .expect("Memory storage can't fail")
+    }
+
+    /// Loads a disk from a storage, replaying the write ahead log like `from_file`.
+    /// Fails if the storage doesn't start with a valid header or its metadata is corrupt.
+    /// # Arguments
+    /// * `backing` - The storage holding the disk.
+    pub fn from_storage(backing: Arc<RwLock<dyn Storage>>) -> Result<Self, FSError> {
+        Self::from_storage_with_key(backing, None)
+    }
+
+    /// Loads a disk from a storage like `from_storage`, decrypting it with a key.
+    /// Fails with `FSError::KeyRequired` if the disk is encrypted but no key is given and with
+    /// `FSError::WrongKey` if the key isn't the disk's.
+    /// # Arguments
+    /// * `backing` - The storage holding the disk.
+    /// * `key` - The key the disk is encrypted with, if it is.
+    pub fn from_storage_with_key(backing: Arc<RwLock<dyn Storage>>, key: Option<&EncryptionKey>) -> Result<Self, FSError> {
+        Self::load(backing, key, false)
+    }
+
+    /// Loads a disk from a storage, replaying the write ahead log unless the storage is only
+    /// read, in which case a batch left in the log fails with `FSError::UnappliedLog`.
+    /// # Arguments
+    /// * `backing` - The storage holding the disk.
+    /// * `key` - The key the disk is encrypted with, if it is.
+    /// * `read_only` - Whether the storage can only be read.
+    fn load(backing: Arc<RwLock<dyn Storage>>, key: Option<&EncryptionKey>, read_only: bool) -> Result<Self, FSError> {
+        let header = DiskHeader::read_from(&mut *backing.write().unwrap())?;
+        let sector_length = header.sector_length as u64;
+
+        let cache = Self::cache_over(&backing, &header, key)?;
+        let storage: Arc<RwLock<dyn Storage>> = cache.clone();
+        let wal = Arc::new(RwLock::new(WriteAheadLog::new(WAL_START, WAL_SECTORS, sector_length, Arc::downgrade(&storage))));
+
+        if !read_only {
+            // Written back right away, so the replayed batch doesn't wait for the next commit
+            if wal.write().unwrap().recover()? > 0 {
+                cache.write().unwrap().flush()?;
+            }
+        } else if wal.read().unwrap().has_committed_batch()? {
+            return Err(FSError::UnappliedLog);
+        }
+
+        let (root_partition, partitions) = Self::load_partitions(&storage, &wal, &header)?;
+        let partition_map = partitions.into_iter().map(|p| Arc::new(RwLock::new(p))).collect();
 
-        CraneDisk {
+        Ok(CraneDisk {
+            header,
             root_partition,
             partitions: partition_map,
-            read_file: read_rc,
-            write_file: write_rc,
+            backing,
+            storage,
+            cache,
+            wal,
+            durability: Durability::default(),
+            last_sync: Instant::now(),
+            unsynced: false,
+            in_command: false,
+            key: key.cloned(),
+        })
+    }
+
+    /// Reads the partition map and builds the partitions it describes.
+    /// Fails if the map is corrupt or describes sectors outside the disk.
+    fn load_partitions(storage: &Arc<RwLock<dyn Storage>>, wal: &Arc<RwLock<WriteAheadLog>>, header: &DiskHeader)
+        -> Result<(RootPartition, Vec<CranePartition>), FSError> {
+        let rpartition = Self::logged_partition(storage, wal, 0, vec![(ROOT_START, ROOT_SECTORS)], ROOT_SECTORS, 0);
+        let disk_len = storage.read().unwrap().len()?/(header.sector_length as u64);
+
+        let root_partition = RootPartition::import_from(rpartition, |offset, len| {
+            if offset < WAL_START + WAL_SECTORS || offset + len > disk_len {
+                return Err(FSError::CorruptMetadata(format!("partition map block at sector {} is outside the disk", offset)));
+            }
+            Ok(Self::logged_partition(storage, wal, 0, vec![(offset, len)], len, 0))
+        })?;
+
+        let mut partitions = vec![];
+        for i in 0..root_partition.partition_starts.len() {
+            let (s, e) = (root_partition.partition_starts[i], root_partition.partition_ends[i]);
+            if s > e || e > disk_len {
+                return Err(FSError::CorruptMetadata(format!("partition {} spans sectors {}..{} of a {} sector disk", i + 1, s, e, disk_len)));
+            }
+            for (s, e) in root_partition.extra_extents[i].iter() {
+                if s >= e || *e > disk_len {
+                    return Err(FSError::CorruptMetadata(format!("an extent of partition {} spans sectors {}..{} of a {} sector disk", i + 1, s, e, disk_len)));
+                }
+            }
+
+            let partition = Self::logged_partition(storage, wal, (i + 1) as u64, root_partition.extents(i),
+                root_partition.init_lens[i], root_partition.partition_types[i]);
+            partitions.push(partition);
         }
+
+        Ok((root_partition, partitions))
     }
 
-    /// Initializes a new disk to a file.
+    /// Initializes a new disk in an empty storage, writing its header right away.
     /// # Arguments
-    /// * 'read_file' - The file to read from.
-    /// * `write_file` - The file to write to.
-    pub fn init_file(read_file: File, write_file: File) -> Self {
-        let read_rc = Rc::new(RefCell::new(read_file));
-        let write_rc = Rc::new(RefCell::new(write_file));
+    /// * `storage` - The storage to create the disk in.
+    pub fn init_storage(storage: Arc<RwLock<dyn Storage>>) -> Result<Self, FSError> {
+        Self::init_storage_with_options(storage, DiskOptions::default())
+    }
+
+    /// Initializes a new disk in an empty storage laid out with the given options.
+    /// # Arguments
+    /// * `backing` - The storage to create the disk in.
+    /// * `options` - The sector length and checksum of the disk.
+    pub fn init_storage_with_options(backing: Arc<RwLock<dyn Storage>>, options: DiskOptions) -> Result<Self, FSError> {
+        Self::init_storage_with_key(backing, options, None)
+    }
+
+    /// Initializes a new disk in an empty storage like `init_storage_with_options`, encrypting
+    /// it with a key if the options ask for encryption.
+    /// # Arguments
+    /// * `backing` - The storage to create the disk in.
+    /// * `options` - The sector length, checksum and encryption of the disk.
+    /// * `key` - The key to encrypt the disk with, required if it is encrypted.
+    pub fn init_storage_with_key(backing: Arc<RwLock<dyn Storage>>, options: DiskOptions, key: Option<&EncryptionKey>) -> Result<Self, FSError> {
+        let sector_length = options.sector_length;
+        if !is_valid_sector_length(sector_length) {
+            return Err(HeaderError::InvalidSectorLength(sector_length as u32).into());
+        }
+
+        let mut header = DiskHeader::new(sector_length, options.checksum);
+        if options.encryption != EncryptionKind::None {
+            let cipher = SectorCipher::new(options.encryption, key.ok_or(FSError::KeyRequired)?)
+                .expect("The disk is encrypted");
+            header.encryption = options.encryption;
+            header.key_check = cipher.key_check();
+        }
+        let cache = Self::cache_over(&backing, &header, key)?;
+        let storage: Arc<RwLock<dyn Storage>> = cache.clone();
+        let wal = Arc::new(RwLock::new(WriteAheadLog::new(WAL_START, WAL_SECTORS, sector_length, Arc::downgrade(&storage))));
+
+        let root_partition = RootPartition::new(
+            Self::logged_partition(&storage, &wal, 0, vec![(ROOT_START, ROOT_SECTORS)], ROOT_SECTORS, 0));
 
-        let root_partition = RootPartition::new(CranePartition::new(0, 0, 12, 12, 
-            Rc::downgrade(&read_rc), Rc::downgrade(&write_rc)));
-        
         let mut disk = CraneDisk {
+            header,
             root_partition,
             partitions: vec![],
-            read_file: read_rc,
-            write_file: write_rc,
+            backing,
+            storage,
+            cache,
+            wal,
+            durability: Durability::default(),
+            last_sync: Instant::now(),
+            unsynced: false,
+            in_command: false,
+            key: key.cloned(),
         };
-        
-        disk.add_sectors(8);
 
-        disk
+        disk.add_sectors(HEADER_SECTORS + ROOT_SECTORS + WAL_SECTORS)?;
+        disk.storage.write().unwrap().write_at(0, &disk.header.produce_bytes())?;
+        disk.cache.write().unwrap().sync()?;
+
+        Ok(disk)
+    }
+
+    /// Returns the options a disk laid out like this one is initialized with.
+    pub fn options(&self) -> DiskOptions {
+        DiskOptions {
+            sector_length: self.sector_length(),
+            checksum: self.header.checksum,
+            encryption: self.header.encryption,
+        }
+    }
+
+    /// Returns the key the disk was opened or initialized with, if it is encrypted.
+    pub(crate) fn key(&self) -> Option<&EncryptionKey> {
+        self.key.as_ref()
+    }
+
+    /// Returns the header the disk was opened or initialized with.
+    pub fn header(&self) -> &DiskHeader {
+        &self.header
+    }
+
+    /// Returns the byte length of the disk's sectors.
+    pub fn sector_length(&self) -> u64 {
+        self.header.sector_length as u64
+    }
+
+    /// Builds the cache the disk is accessed through, checksumming and encrypting the sectors
+    /// behind it if the header asks for it.
+    fn cache_over(backing: &Arc<RwLock<dyn Storage>>, header: &DiskHeader, key: Option<&EncryptionKey>) -> Result<Arc<RwLock<PageCache>>, FSError> {
+        let sector_length = header.sector_length as u64;
+        let mut inner: Arc<RwLock<dyn Storage>> = match header.checksum {
+            ChecksumKind::None => backing.clone(),
+            kind => Arc::new(RwLock::new(ChecksumStorage::new(backing.clone(), kind, sector_length, HEADER_SECTORS))),
+        };
+
+        match (header.encryption, key) {
+            (EncryptionKind::None, None) => {}
+            (EncryptionKind::None, Some(_)) => return Err(FSError::NotEncrypted),
+            (_, None) => return Err(FSError::KeyRequired),
+            (kind, Some(key)) => {
+                let cipher = SectorCipher::new(kind, key).expect("The disk is encrypted");
+                if !cipher.verify_key(&header.key_check) {
+                    return Err(FSError::WrongKey);
+                }
+                inner = Arc::new(RwLock::new(EncryptedStorage::new(inner, cipher, sector_length, HEADER_SECTORS)));
+            }
+        }
+
+        Ok(Arc::new(RwLock::new(PageCache::new(inner, sector_length, DEFAULT_CACHE_SECTORS)?)))
+    }
+
+    /// Returns the raw storage the disk was opened from, underneath its cache, checksums and
+    /// encryption, for example to reopen the disk or to copy its bytes. Writes that are only cached
+    /// haven't reached it yet.
+    pub fn storage(&self) -> Arc<RwLock<dyn Storage>> {
+        self.backing.clone()
+    }
+
+    /// Returns the hit and miss counters of the sector cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.read().unwrap().stats()
+    }
+
+    /// Changes how many sectors the disk keeps cached, zero turns the cache off.
+    /// # Arguments
+    /// * `sectors` - The maximum number of sectors to cache.
+    pub fn set_cache_capacity(&mut self, sectors: usize) -> Result<(), FSError> {
+        self.cache.write().unwrap().set_capacity(sectors)
+    }
+
+    /// Copies the saved image of the disk into a file, so that an in memory disk can be kept.
+    /// # Arguments
+    /// * `file` - The file to copy the disk to, it should be empty.
+    pub fn persist_to(&self, file: &mut File) -> Result<(), FSError> {
+        self.cache.write().unwrap().flush()?;
+        let mut storage = self.backing.write().unwrap();
+        let len = storage.len()?;
+        let bytes = storage.read_at(0, len)?;
+
+        file.write_all(&bytes)?;
+        Ok(file.sync_all()?)
     }
 
     /// Adds sectors of empty bytes, returns the new sector length of the file
     /// # Arguments
     /// * `sectors` - The number of sectors to add.
-    pub fn add_sectors(&mut self, sectors: u64) -> u64 {
-        let mut f = self.write_file.borrow_mut();
-        f.seek(SeekFrom::End(0)).unwrap();
+    pub fn add_sectors(&mut self, sectors: u64) -> Result<u64, FSError> {
+        {
+            let bytes = sectors*self.sector_length();
+            let mut storage = self.storage.write().unwrap();
+            let end = storage.len()?;
 
-        for _ in 0..sectors {
-            f.write(&[0u8; SECTOR_LENGTH]).unwrap();
+            storage.write_at(end, &vec![0u8; bytes as usize])?;
         }
 
-        self.len()
+        Ok(self.len())
+    }
+
+    /// Returns when the disk forces its writes through to stable storage.
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Changes when the disk forces its writes through to stable storage.
+    /// # Arguments
+    /// * `durability` - When to sync the disk.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Saves the root partition to the disk, commits every write made since the last save
+    /// through the write ahead log and writes back the cached sectors. Syncs the disk unless
+    /// its durability is `None`, or `GroupCommit` and the last sync was recent.
+    pub fn save(&mut self) -> Result<(), FSError> {
+        self.commit_batch(true)
+    }
+
+    /// Commits every write made by a command like `save`, only syncing the disk if its durability
+    /// asks for it after every command.
+    pub fn commit(&mut self) -> Result<(), FSError> {
+        self.commit_batch(false)
+    }
+
+    /// Forces every saved write through to stable storage, whatever the durability of the disk.
+    pub fn sync(&mut self) -> Result<(), FSError> {
+        self.cache.write().unwrap().sync()?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+
+    /// Commits the pending writes, syncing the disk as its durability asks.
+    ///
+    /// A batch too large for the write ahead log fails with `FSError::LogFull` and is rolled back,
+    /// the partitions are reloaded as they were at the last commit and the disk stays usable.
+    /// # Arguments
+    /// * `explicit` - Whether the batch is an explicit save rather than a command.
+    fn commit_batch(&mut self, explicit: bool) -> Result<(), FSError> {
+        self.in_command = false;
+        let sync = match self.durability {
+            Durability::None => false,
+            Durability::OnSave => explicit,
+            Durability::EveryCommand => true,
+            Durability::GroupCommit(interval) => self.last_sync.elapsed() >= interval,
+        };
+
+        self.update_root()?;
+        let committed = {
+            let mut wal = self.wal.write().unwrap();
+            wal.set_sync_writes(sync);
+            wal.commit()
+        };
+        if let Err(FSError::LogFull { .. }) = committed {
+            self.roll_back()?;
+        }
+        committed?;
+
+        if sync {
+            self.sync()
+        } else {
+            self.unsynced = true;
+            self.cache.write().unwrap().flush()
+        }
+    }
+
+    /// Whether writes were made since the last commit, or a command is in flight.
+    pub fn has_pending(&self) -> bool {
+        self.in_command || self.wal.read().unwrap().pending_bytes() > 0
     }
 
-    /// Saves the root partition to the disk.
-    pub fn save(&mut self) {
-        self.update_root();
+    /// Marks the pending writes as belonging to a command, `checkpoint` doesn't save them until the
+    /// command is committed or discarded so that only whole commands reach the disk.
+    pub fn begin_command(&mut self) {
+        self.in_command = true;
     }
 
-    pub fn append_partition(&mut self, sector_length: u64, partition_type: u64) -> u64 {
+    /// Drops every write made since the last commit, ending the command in flight if there is one,
+    /// and reloads the partitions as they were at the last commit.
+    pub fn discard(&mut self) -> Result<(), FSError> {
+        self.wal.write().unwrap().discard();
+        self.in_command = false;
+        self.roll_back()
+    }
+
+    /// Reloads the partition map and partitions as they were at the last commit, after the writes
+    /// since then were dropped. Partitions are reloaded in place so everyone holding them sees it.
+    fn roll_back(&mut self) -> Result<(), FSError> {
+        let (root_partition, partitions) = Self::load_partitions(&self.storage, &self.wal, &self.header)?;
+        self.root_partition = root_partition;
+
+        self.partitions.truncate(partitions.len());
+        for (i, partition) in partitions.into_iter().enumerate() {
+            match self.partitions.get(i) {
+                Some(p) => *p.write().unwrap() = partition,
+                None => self.partitions.push(Arc::new(RwLock::new(partition))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the disk if the writes made since the last save fill more than half of the write
+    /// ahead log, so that long jobs like copying partitions can write more than fits in one batch.
+    /// Never saves while a command is in flight, see `begin_command`. Returns whether the disk was saved.
+    pub fn checkpoint(&mut self) -> Result<bool, FSError> {
+        let full = {
+            let wal = self.wal.read().unwrap();
+            !self.in_command && wal.pending_bytes()*2 > wal.capacity()
+        };
+        if full {
+            self.save()?;
+        }
+        Ok(full)
+    }
+
+    /// Creates a partition, reusing a released extent if one is large enough before growing the file.
+    /// Returns the id of the new partition.
+    /// # Arguments
+    /// * `sector_length` - How many sectors the partition is.
+    /// * `partition_type` - The type of the partition.
+    pub fn append_partition(&mut self, sector_length: u64, partition_type: u64) -> Result<u64, FSError> {
+        if let Some(id) = self.reuse_free_extent(sector_length, partition_type)? {
+            return Ok(id);
+        }
+
         let old_len = self.len();
-        let new_len = self.add_sectors(sector_length);
+        let new_len = self.add_sectors(sector_length)?;
         let id = (self.partitions.len() as u64) + 1;
-        let partition = CranePartition::with_type(id, old_len, new_len-old_len, 0, partition_type,
-        Rc::downgrade(&self.read_file), Rc::downgrade(&self.write_file));
+        let partition = Self::logged_partition(&self.storage, &self.wal, id, vec![(old_len, new_len-old_len)], 0, partition_type);
+
+        self.partitions.push(Arc::new(RwLock::new(partition)));
+        self.update_root()?;
+
+        Ok(id)
+    }
+
+    /// Copies a partition, possibly from another disk, into a new partition of the same type and
+    /// length made of a single extent. Returns the id of the new partition.
+    ///
+    /// The copy is saved as it goes, so partitions larger than the write ahead log can be copied.
+    /// # Arguments
+    /// * `source` - The partition to copy.
+    pub fn copy_partition(&mut self, source: &mut CranePartition) -> Result<u64, FSError> {
+        let sl = self.sector_length();
+        if source.sector_length() != sl {
+            return Err(FSError::CorruptData(format!("can't copy {} byte sectors onto a disk with {} byte sectors", source.sector_length(), sl)));
+        }
+
+        let id = self.append_partition(source.total_len(), source.partition_type)?;
+        let sectors = source.initialized_len.div_ceil(sl).min(source.total_len());
+        let chunk = u64::max(1, self.wal.read().unwrap().capacity()/(4*sl));
+
+        let mut sector = 0;
+        while sector < sectors {
+            let end = u64::min(sector + chunk, sectors);
+            let bytes = source.read_sectors(sector, end)?;
+            self.get_partition_with_id(id)?.write().unwrap().write_sectors(sector, 0, &bytes)?;
+            self.checkpoint()?;
+            sector = end;
+        }
+        self.get_partition_with_id(id)?.write().unwrap().initialized_len = source.initialized_len;
+
+        Ok(id)
+    }
+
+    /// Releases a partition so that its sectors can be reused by later partitions.
+    /// The id stays reserved until an extent is allocated in its place.
+    /// # Arguments
+    /// * `id` - The id of the partition to release.
+    pub fn release_partition(&mut self, id: u64) -> Result<(), FSError> {
+        let extents = self.get_partition_with_id(id)?.read().unwrap().extents().to_vec();
+
+        for (i, (start, len)) in extents.into_iter().enumerate() {
+            // Every extent after the first is recorded as a free extent of its own
+            let free_id = if i == 0 { id } else { self.spare_id() };
+            self.free_extent(free_id, start, start + len);
+        }
+
+        self.update_root()
+    }
+
+    /// Grows a partition in place by some sectors, its id and the positions in it stay valid.
+    ///
+    /// The sectors are taken from the smallest released extent they fit in, or else added to the
+    /// end of the disk, extending the partition's last extent if it is the last thing on the disk.
+    /// # Arguments
+    /// * `id` - The id of the partition to grow.
+    /// * `sectors` - How many sectors to add to the partition.
+    pub fn grow_partition(&mut self, id: u64, sectors: u64) -> Result<(), FSError> {
+        self.get_partition_with_id(id)?;
+        let free = self.partitions.iter()
+            .map(|p| p.read().unwrap())
+            .filter(|p| p.partition_type == FREE_PARTITION_TYPE && p.total_len() >= sectors && p.total_len() > 0)
+            .min_by_key(|p| p.total_len())
+            .map(|p| (p.id(), p.offset(), p.total_len()));
+
+        let start = match free {
+            Some((free_id, offset, total_len)) => {
+                // Released sectors still hold the old partition's bytes
+                self.zero_sectors(offset, sectors)?;
+                self.set_partition(free_id, offset + sectors, total_len - sectors, FREE_PARTITION_TYPE);
+                offset
+            },
+            None => {
+                let old_len = self.len();
+                self.add_sectors(sectors)?;
+                old_len
+            }
+        };
+
+        let (mut extents, initialized_len, partition_type) = {
+            let p = self.get_partition_with_id(id)?.read().unwrap();
+            (p.extents().to_vec(), p.initialized_len, p.partition_type)
+        };
+        match extents.last_mut() {
+            Some((s, l)) if *s + *l == start => *l += sectors,
+            _ => extents.push((start, sectors)),
+        }
+
+        // Replaced in place so that everyone holding the partition sees it grow
+        let partition = Self::logged_partition(&self.storage, &self.wal, id, extents, initialized_len, partition_type);
+        *self.get_partition_with_id(id)?.write().unwrap() = partition;
+
+        self.update_root()
+    }
+
+    /// Records the sectors from `start` to `end` as a free extent under the given id, merging it
+    /// with the free extents right before and after it.
+    fn free_extent(&mut self, id: u64, mut start: u64, mut end: u64) {
+        // Merge with the free extents right before and after this one
+        let neighbours: Vec<(u64, u64, u64)> = self.partitions.iter()
+            .map(|p| p.read().unwrap())
+            .filter(|p| p.id() != id && p.partition_type == FREE_PARTITION_TYPE && p.total_len() > 0)
+            .filter(|p| p.offset() + p.total_len() == start || p.offset() == end)
+            .map(|p| (p.id(), p.offset(), p.offset() + p.total_len()))
+            .collect();
+
+        for (nid, s, e) in neighbours {
+            start = start.min(s);
+            end = end.max(e);
+            self.set_partition(nid, e, 0, FREE_PARTITION_TYPE);
+        }
+
+        self.set_partition(id, start, end - start, FREE_PARTITION_TYPE);
+    }
+
+    /// Finds an id that holds no sectors to record a free extent under, adding one if there is none.
+    fn spare_id(&mut self) -> u64 {
+        let spare = self.partitions.iter()
+            .map(|p| p.read().unwrap())
+            .find(|p| p.partition_type == FREE_PARTITION_TYPE && p.total_len() == 0)
+            .map(|p| p.id());
+
+        spare.unwrap_or_else(|| {
+            let id = self.partitions.len() as u64 + 1;
+            self.partitions.push(Arc::new(RwLock::new(Self::logged_partition(&self.storage, &self.wal, id, vec![(0, 0)], 0, FREE_PARTITION_TYPE))));
+            id
+        })
+    }
+
+    /// Lists the released extents as pairs of start sector and sector length.
+    pub fn free_extents(&self) -> Vec<(u64, u64)> {
+        self.partitions.iter()
+            .map(|p| p.read().unwrap())
+            .filter(|p| p.partition_type == FREE_PARTITION_TYPE && p.total_len() > 0)
+            .map(|p| (p.offset(), p.total_len()))
+            .collect()
+    }
 
-        self.add_to_root(&partition);
-        self.partitions.push(Rc::new(RefCell::new(partition)));
-        
-        id
+    /// Allocates a partition in the smallest free extent it fits in, splitting off the rest
+    /// of the extent as a new free extent.
+    fn reuse_free_extent(&mut self, sector_length: u64, partition_type: u64) -> Result<Option<u64>, FSError> {
+        let extent = self.partitions.iter()
+            .map(|p| p.read().unwrap())
+            .filter(|p| p.partition_type == FREE_PARTITION_TYPE && p.total_len() >= sector_length && p.total_len() > 0)
+            .min_by_key(|p| p.total_len())
+            .map(|p| (p.id(), p.offset(), p.total_len()));
+        let (id, offset, total_len) = match extent {
+            Some(extent) => extent,
+            None => return Ok(None),
+        };
+
+        // Released sectors still hold the old partition's bytes
+        self.zero_sectors(offset, sector_length)?;
+        self.set_partition(id, offset, sector_length, partition_type);
+
+        if total_len > sector_length {
+            let rest = self.spare_id();
+            self.set_partition(rest, offset + sector_length, total_len - sector_length, FREE_PARTITION_TYPE);
+        }
+
+        self.update_root()?;
+        Ok(Some(id))
     }
 
-    /// Gets a partition by its partition id.
+    /// Zeroes sectors of the disk through the write ahead log, saving between parts of the log so
+    /// that extents larger than the log can be zeroed.
+    /// # Arguments
+    /// * `start` - The first sector to zero.
+    /// * `sectors` - How many sectors to zero.
+    fn zero_sectors(&mut self, start: u64, sectors: u64) -> Result<(), FSError> {
+        let sl = self.sector_length();
+        let chunk = u64::max(1, self.wal.read().unwrap().capacity()/(4*sl));
+
+        let mut sector = start;
+        while sector < start + sectors {
+            let end = u64::min(sector + chunk, start + sectors);
+            self.wal.write().unwrap().log(sector*sl, &vec![0u8; ((end - sector)*sl) as usize]);
+            self.checkpoint()?;
+            sector = end;
+        }
+        Ok(())
+    }
+
+    /// Replaces the partition with the given id.
+    fn set_partition(&mut self, id: u64, offset: u64, total_len: u64, partition_type: u64) {
+        let partition = Self::logged_partition(&self.storage, &self.wal, id, vec![(offset, total_len)], 0, partition_type);
+        self.partitions[id as usize - 1] = Arc::new(RwLock::new(partition));
+    }
+
+    /// Returns the start sector and sector length of the disk's own metadata, its header, partition
+    /// map and write ahead log, which no partition may overlap.
+    pub fn reserved_extents(&self) -> Vec<(u64, u64)> {
+        let mut extents = vec![(0, WAL_START + WAL_SECTORS)];
+        extents.append(&mut self.root_partition.chain_extents());
+        extents
+    }
+
+    /// Gets a partition by its partition id, ids start at 1.
     /// # Arguments
     /// * `id` - The id of the partition to get.
-    pub fn get_partition_with_id(&self, id: u64) -> &Rc<RefCell<CranePartition>> {
-        &self.partitions[id as usize - 1]
+    pub fn get_partition_with_id(&self, id: u64) -> Result<&Arc<RwLock<CranePartition>>, FSError> {
+        id.checked_sub(1)
+            .and_then(|i| self.partitions.get(i as usize))
+            .ok_or(FSError::UnknownPartition(id))
     }
 
     /// Gets all partitions of a given type.
     /// # Arguments
     /// * `t` - The type of the partitions to get.
-    pub fn get_partition_by_type(&self, t: u64) -> Vec<&Rc<RefCell<CranePartition>>> {
+    pub fn get_partition_by_type(&self, t: u64) -> Vec<&Arc<RwLock<CranePartition>>> {
         self.partitions.iter()
-            .filter(|x| x.borrow().partition_type == t)
+            .filter(|x| x.read().unwrap().partition_type == t)
             .collect()
     }
 
-    fn add_to_root(&mut self, partition: &CranePartition) {
-        self.root_partition.partition_starts.push(partition.offset());
-        self.root_partition.partition_ends.push(partition.total_len() + partition.offset());
-        self.root_partition.init_lens.push(partition.initialized_len);
-        self.root_partition.partition_types.push(partition.partition_type);
+    /// Creates a partition whose writes go through the write ahead log.
+    fn logged_partition(storage: &Arc<RwLock<dyn Storage>>, wal: &Arc<RwLock<WriteAheadLog>>, id: u64, extents: Vec<(u64, u64)>, initialized_len: u64, partition_type: u64)
+        -> CranePartition {
+        let sector_length = wal.read().unwrap().sector_length();
+        let reader = WalReader::new(CraneReader::with_extents(id, &extents, sector_length, Arc::downgrade(storage)), Arc::downgrade(wal));
+        let writer = WalWriter::new(id, &extents, sector_length, Arc::downgrade(wal));
 
-        self.root_partition.write();
+        CranePartition::from_parts(id, extents, initialized_len, partition_type, Box::new(reader), Box::new(writer))
     }
 
-    fn update_root(&mut self) {
-        self.root_partition.init_lens = self.partitions.iter().map(|x| x.borrow().initialized_len).collect();
+    fn update_root(&mut self) -> Result<(), FSError> {
+        let partitions: Vec<_> = self.partitions.iter().map(|x| x.read().unwrap()).collect();
+
+        self.root_partition.partition_starts = partitions.iter().map(|x| x.offset()).collect();
+        self.root_partition.partition_ends = partitions.iter().map(|x| x.offset() + x.extents().first().map(|(_, l)| *l).unwrap_or(0)).collect();
+        self.root_partition.init_lens = partitions.iter().map(|x| x.initialized_len).collect();
+        self.root_partition.partition_types = partitions.iter().map(|x| x.partition_type).collect();
+        self.root_partition.extra_extents = partitions.iter()
+            .map(|x| x.extents().iter().skip(1).map(|(s, l)| (*s, s + l)).collect())
+            .collect();
+        drop(partitions);
+
+        while self.root_partition.capacity() < self.root_partition.entry_count() {
+            let offset = self.len();
+            self.add_sectors(ROOT_BLOCK_SECTORS)?;
+
+            let block = Self::logged_partition(&self.storage, &self.wal, 0, vec![(offset, ROOT_BLOCK_SECTORS)], ROOT_BLOCK_SECTORS, 0);
+            self.root_partition.extend(block);
+        }
 
-        self.root_partition.write();
+        self.root_partition.write()
     }
 
     /// Gets the sector length of the disk.
     pub fn len(&self) -> u64 {
-        (self.read_file.borrow().metadata().unwrap().len() as u64)/(SECTOR_LENGTH as u64)
+        // The cache keeps track of the length itself, so this can't fail
+        self.cache.read().unwrap().len().unwrap_or(0)/self.sector_length()
+    }
+
+    /// Whether the disk has no sectors at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
+impl Drop for CraneDisk {
+    /// Syncs the commands a group commit hasn't synced yet, nothing reports a failure this late.
+    fn drop(&mut self) {
+        if matches!(self.durability, Durability::GroupCommit(_)) && self.unsynced {
+            let _ = self.sync();
+        }
+    }
+}
 
 #[cfg(test)]
 mod test {
     use std::fs::OpenOptions;
 
-    use crate::cfs::writer::Writer;
+    use crate::cfs::{header::FORMAT_VERSION, partition_kind::{PartitionKind, PartitionType}};
 
     use super::*;
 
+    fn create_disk(path: &str) {
+        let write_file = File::create(path).unwrap();
+        let read_file = File::open(path).unwrap();
+
+        let mut disk = CraneDisk::init_file(read_file, write_file).unwrap();
+
+        disk.append_partition(8, 0).unwrap();
+        assert_eq!(disk.partitions.len(), 1);
+
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+    }
+
     #[test]
     fn test_new_disk() {
-        let write_file = File::create("./test/disk/disk.db").unwrap();
-        let read_file = File::open("./test/disk/disk.db").unwrap();
+        create_disk("./test/disk/disk.db");
+    }
+
+    #[test]
+    fn read_disk() {
+        create_disk("./test/disk/read.db");
+        let write_file = OpenOptions::new().write(true).open("./test/disk/read.db").unwrap();
+        let read_file = File::open("./test/disk/read.db").unwrap();
 
-        let mut disk = CraneDisk::init_file(read_file, write_file);
+        let disk = CraneDisk::from_file(read_file, write_file).unwrap();
 
-        disk.append_partition(8, 0);
         assert_eq!(disk.partitions.len(), 1);
+        assert_eq!(disk.partitions[0].read().unwrap().initialized_len, 8);
+    }
 
-        disk.partitions[0].borrow_mut().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
-        disk.save();
+    #[test]
+    fn test_memory_disk() {
+        let mut disk = CraneDisk::in_memory();
+        disk.append_partition(8, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+
+        assert_eq!(disk.partitions.len(), 1);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[..8], 25u64.to_be_bytes());
     }
 
     #[test]
-    fn read_disk() {
-        test_new_disk();
-        let write_file = OpenOptions::new().write(true).open("./test/disk/disk.db").unwrap();
-        let read_file = File::open("./test/disk/disk.db").unwrap();
+    fn test_disk_cache() {
+        let mut disk = CraneDisk::in_memory();
+        disk.append_partition(8, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        let misses = disk.cache_stats().misses;
+        disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap();
+        disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap();
+
+        assert_eq!(disk.cache_stats().misses, misses);
+
+        disk.set_cache_capacity(0).unwrap();
+        disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap();
+
+        assert_eq!(disk.cache_stats().misses, misses + 1);
+    }
+
+    #[test]
+    fn test_persist_memory_disk() {
+        let mut disk = CraneDisk::in_memory();
+        disk.append_partition(8, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        let mut file = File::create("./test/disk/persist.db").unwrap();
+        disk.persist_to(&mut file).unwrap();
+
+        let write_file = OpenOptions::new().write(true).open("./test/disk/persist.db").unwrap();
+        let read_file = File::open("./test/disk/persist.db").unwrap();
+        let disk = CraneDisk::from_file(read_file, write_file).unwrap();
+
+        assert_eq!(disk.partitions.len(), 1);
+        assert_eq!(disk.partitions[0].read().unwrap().initialized_len, 8);
+    }
+
+    #[test]
+    fn test_mmap_disk() {
+        let path = "./test/disk/mmap.db";
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path).unwrap();
+        let mut disk = CraneDisk::init_mmap_file(file).unwrap();
+        disk.append_partition(8, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        let len = disk.len();
+        assert_eq!(disk.add_sectors(4).unwrap(), len + 4);
+        disk.save().unwrap();
+        drop(disk);
+
+        let file = OpenOptions::new().read(true).write(true).open(path).unwrap();
+        let disk = CraneDisk::from_mmap_file(file).unwrap();
+
+        assert_eq!(disk.len(), len + 4);
+        assert_eq!(disk.partitions.len(), 1);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[0..8], 25u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_open_locked() {
+        let path = "./test/disk/locked.db";
+        let _ = std::fs::remove_file(path);
+
+        let mut disk = CraneDisk::open(path, super::OpenOptions::default()).unwrap();
+        disk.append_partition(8, 0).unwrap();
+        disk.save().unwrap();
+
+        let read_only = super::OpenOptions { read_only: true, ..Default::default() };
+        assert!(matches!(CraneDisk::open(path, super::OpenOptions::default()), Err(FSError::Locked(_))));
+        assert!(matches!(CraneDisk::open(path, read_only.clone()), Err(FSError::Locked(_))));
+        drop(disk);
+
+        let first = CraneDisk::open(path, read_only.clone()).unwrap();
+        let second = CraneDisk::open(path, read_only).unwrap();
+        assert_eq!(first.partitions.len(), 1);
+        assert_eq!(second.partitions.len(), 1);
+        assert!(matches!(CraneDisk::open(path, super::OpenOptions::default()), Err(FSError::Locked(_))));
+        drop(first);
+        drop(second);
+
+        let disk = CraneDisk::open(path, super::OpenOptions::default()).unwrap();
+        assert_eq!(disk.partitions.len(), 1);
+    }
+
+    #[test]
+    fn test_open_missing() {
+        let path = "./test/disk/missing.db";
+        let _ = std::fs::remove_file(path);
+        let options = super::OpenOptions { create: false, ..Default::default() };
+
+        assert!(matches!(CraneDisk::open(path, options), Err(FSError::Io(_))));
+    }
+
+    /// A memory storage counting how often it is synced
+    struct SyncCounter {
+        inner: MemoryStorage,
+        syncs: Arc<RwLock<u64>>,
+    }
+
+    impl Storage for SyncCounter {
+        fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+            self.inner.read_at(byte, len)
+        }
+
+        fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+            self.inner.write_at(byte, bytes)
+        }
+
+        fn len(&self) -> Result<u64, FSError> {
+            self.inner.len()
+        }
+
+        fn sync(&mut self) -> Result<(), FSError> {
+            *self.syncs.write().unwrap() += 1;
+            Ok(())
+        }
+
+        fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+            self.inner.truncate(len)
+        }
+    }
+
+    #[test]
+    fn test_durability() {
+        // Returns how often saving and then committing a command synced the disk
+        let syncs = |durability: Durability| {
+            let syncs = Arc::new(RwLock::new(0));
+            let storage = Arc::new(RwLock::new(SyncCounter { inner: MemoryStorage::new(), syncs: syncs.clone() }));
+            let mut disk = CraneDisk::init_storage(storage).unwrap();
+            disk.set_durability(durability);
+            disk.append_partition(8, 0).unwrap();
+
+            let before = *syncs.read().unwrap();
+            disk.partitions[0].write().unwrap().write_sectors(0, 0, &[1]).unwrap();
+            disk.save().unwrap();
+            let saved = *syncs.read().unwrap() - before;
+
+            disk.partitions[0].write().unwrap().write_sectors(0, 0, &[2]).unwrap();
+            disk.commit().unwrap();
+            let committed = *syncs.read().unwrap() - before - saved;
+
+            assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[0], 2);
+            (saved > 0, committed > 0)
+        };
+
+        assert_eq!(syncs(Durability::None), (false, false));
+        assert_eq!(syncs(Durability::OnSave), (true, false));
+        assert_eq!(syncs(Durability::EveryCommand), (true, true));
+        assert_eq!(syncs(Durability::GroupCommit(Duration::from_secs(3600))), (false, false));
+        assert_eq!(syncs(Durability::GroupCommit(Duration::ZERO)), (true, true));
+    }
+
+    #[test]
+    fn test_group_commit_synced_on_drop() {
+        let syncs = Arc::new(RwLock::new(0));
+        let storage = Arc::new(RwLock::new(SyncCounter { inner: MemoryStorage::new(), syncs: syncs.clone() }));
+        let mut disk = CraneDisk::init_storage(storage).unwrap();
+        disk.set_durability(Durability::GroupCommit(Duration::from_secs(3600)));
+        disk.append_partition(8, 0).unwrap();
+        disk.save().unwrap();
+
+        // No command comes after the last one to sync it with
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &[1]).unwrap();
+        disk.commit().unwrap();
+        let before = *syncs.read().unwrap();
+
+        drop(disk);
+        assert!(*syncs.read().unwrap() > before);
+    }
+
+    #[test]
+    fn test_batch_too_large_rolled_back() {
+        let mut disk = CraneDisk::in_memory();
+        disk.append_partition(8, 1).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &[1]).unwrap();
+        disk.save().unwrap();
+
+        // Writing a whole partition larger than the write ahead log in one batch
+        let id = disk.append_partition(400, 2).unwrap();
+        disk.get_partition_with_id(id).unwrap().write().unwrap().write_sectors(0, 0, &vec![7u8; 400*SECTOR_LENGTH]).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &[2]).unwrap();
+        assert!(matches!(disk.save(), Err(FSError::LogFull { .. })));
+
+        assert_eq!(disk.partitions.len(), 1);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[0], 1);
+
+        // The disk is still usable
+        let id = disk.append_partition(8, 3).unwrap();
+        disk.get_partition_with_id(id).unwrap().write().unwrap().write_sectors(0, 0, &[3]).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &[4]).unwrap();
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        assert_eq!(disk.partitions.len(), 2);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[0], 4);
+        assert_eq!(disk.partitions[1].write().unwrap().read_sectors(0, 1).unwrap()[0], 3);
+        assert_eq!(disk.partitions[1].read().unwrap().partition_type, 3);
+    }
+
+    #[test]
+    fn test_rolled_back_map_keeps_kinds() {
+        let mut disk = CraneDisk::in_memory();
+        let kinds = [PartitionKind::Schema, PartitionKind::Index, PartitionKind::Data];
+        for kind in kinds.iter() {
+            disk.append_partition(2, PartitionType::new(*kind, 3).raw()).unwrap();
+        }
+        disk.save().unwrap();
+        let header = disk.storage().write().unwrap().read_at(0, SECTOR_LENGTH as u64).unwrap();
+
+        // The map changes in the same batch as writes too large for the log
+        disk.release_partition(2).unwrap();
+        let id = disk.append_partition(400, PartitionType::new(PartitionKind::Overflow, 3).raw()).unwrap();
+        disk.get_partition_with_id(id).unwrap().write().unwrap().write_sectors(0, 0, &vec![7u8; 400*SECTOR_LENGTH]).unwrap();
+        assert!(matches!(disk.save(), Err(FSError::LogFull { .. })));
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        assert_eq!(disk.storage().write().unwrap().read_at(0, SECTOR_LENGTH as u64).unwrap(), header);
+        let types: Vec<_> = disk.partitions.iter().map(|p| PartitionType::from_raw(p.read().unwrap().partition_type)).collect();
+        assert_eq!(types, kinds.iter().map(|k| PartitionType::new(*k, 3)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_checkpoint_in_command() {
+        let mut disk = CraneDisk::in_memory();
+        let id = disk.append_partition(200, 3).unwrap();
+        disk.save().unwrap();
+
+        disk.begin_command();
+        disk.get_partition_with_id(id).unwrap().write().unwrap().write_sectors(0, 0, &vec![7u8; 200*SECTOR_LENGTH]).unwrap();
+        assert!(!disk.checkpoint().unwrap());
+        disk.discard().unwrap();
+        assert!(!disk.checkpoint().unwrap());
+        assert_eq!(disk.get_partition_with_id(id).unwrap().write().unwrap().read_sectors(0, 1).unwrap(), vec![0u8; SECTOR_LENGTH]);
+
+        // Outside of a command the same writes are saved
+        disk.get_partition_with_id(id).unwrap().write().unwrap().write_sectors(0, 0, &vec![7u8; 200*SECTOR_LENGTH]).unwrap();
+        assert!(disk.checkpoint().unwrap());
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        assert_eq!(disk.get_partition_with_id(id).unwrap().write().unwrap().read_sectors(0, 1).unwrap(), vec![7u8; SECTOR_LENGTH]);
+    }
+
+    #[test]
+    fn test_unknown_partition_id() {
+        let mut disk = CraneDisk::in_memory();
+        let id = disk.append_partition(2, 3).unwrap();
+        assert!(matches!(disk.get_partition_with_id(0), Err(FSError::UnknownPartition(0))));
+        assert!(matches!(disk.get_partition_with_id(id + 1), Err(FSError::UnknownPartition(i)) if i == id + 1));
+        assert!(matches!(disk.grow_partition(id + 1, 2), Err(FSError::UnknownPartition(_))));
+        assert!(matches!(disk.release_partition(0), Err(FSError::UnknownPartition(0))));
+        assert_eq!(disk.len(), disk.get_partition_with_id(id).unwrap().read().unwrap().offset() + 2);
+    }
+
+    #[test]
+    fn test_reuse_released_partition() {
+        let mut disk = CraneDisk::in_memory();
+        disk.append_partition(8, 1).unwrap();
+        let id = disk.append_partition(16, 2).unwrap();
+        disk.append_partition(8, 3).unwrap();
+        disk.partitions[1].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        let offset = disk.get_partition_with_id(id).unwrap().read().unwrap().offset();
+        let len = disk.len();
 
-        let disk = CraneDisk::from_file(read_file, write_file);
+        disk.release_partition(id).unwrap();
+        assert_eq!(disk.free_extents(), vec![(offset, 16)]);
+
+        let new_id = disk.append_partition(4, 4).unwrap();
+        disk.save().unwrap();
+
+        assert_eq!(new_id, id);
+        assert_eq!(disk.len(), len);
+        assert_eq!(disk.free_extents(), vec![(offset + 4, 12)]);
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        let partition = disk.get_partition_with_id(new_id).unwrap();
+
+        assert_eq!(partition.read().unwrap().offset(), offset);
+        assert_eq!(partition.read().unwrap().partition_type, 4);
+        assert_eq!(partition.write().unwrap().read_sectors(0, 1).unwrap(), vec![0u8; SECTOR_LENGTH]);
+        assert_eq!(disk.free_extents(), vec![(offset + 4, 12)]);
+    }
+
+    #[test]
+    fn test_reuse_large_released_partition() {
+        let mut disk = CraneDisk::in_memory();
+        let id = disk.append_partition(400, 1).unwrap();
+        for i in 0..4 {
+            disk.get_partition_with_id(id).unwrap().write().unwrap().write_sectors(i*100, 0, &vec![9u8; 100*SECTOR_LENGTH]).unwrap();
+            disk.save().unwrap();
+        }
+        disk.release_partition(id).unwrap();
+        disk.save().unwrap();
+
+        // Zeroing the whole extent doesn't fit in one batch of the write ahead log
+        let new_id = disk.append_partition(400, 2).unwrap();
+        disk.save().unwrap();
+        assert_eq!(new_id, id);
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        let partition = disk.get_partition_with_id(new_id).unwrap();
+        assert_eq!(partition.read().unwrap().partition_type, 2);
+        assert!(partition.write().unwrap().read_sectors(0, 400).unwrap().iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_merge_released_partitions() {
+        let mut disk = CraneDisk::in_memory();
+        let first = disk.append_partition(8, 1).unwrap();
+        let second = disk.append_partition(8, 2).unwrap();
+        let offset = disk.get_partition_with_id(first).unwrap().read().unwrap().offset();
+
+        disk.release_partition(first).unwrap();
+        disk.release_partition(second).unwrap();
+
+        assert_eq!(disk.free_extents(), vec![(offset, 16)]);
+
+        let len = disk.len();
+        disk.append_partition(16, 3).unwrap();
+
+        assert_eq!(disk.len(), len);
+        assert!(disk.free_extents().is_empty());
+    }
+
+    #[test]
+    fn test_many_partitions() {
+        let mut disk = CraneDisk::in_memory();
+        for i in 0..300 {
+            disk.append_partition(1, i).unwrap();
+        }
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+
+        assert_eq!(disk.partitions.len(), 300);
+        assert_eq!(disk.root_partition.chain_extents().len(), 2);
+        assert_eq!(disk.get_partition_with_id(300).unwrap().read().unwrap().partition_type, 299);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[..8], 25u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_map_larger_than_log() {
+        let options = DiskOptions { sector_length: 128, ..DiskOptions::default() };
+        let mut disk = CraneDisk::init_storage_with_options(Arc::new(RwLock::new(MemoryStorage::new())), options).unwrap();
+        let entries = disk.wal.read().unwrap().capacity()/32;
+
+        // The whole map takes up more than the write ahead log, only the blocks that changed are logged
+        for i in 0..(entries + 100) {
+            disk.append_partition(1, i).unwrap();
+            if i % 50 == 0 {
+                disk.save().unwrap();
+            }
+        }
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        assert_eq!(disk.partitions.len() as u64, entries + 100);
+        assert_eq!(disk.get_partition_with_id(entries + 100).unwrap().read().unwrap().partition_type, entries + 99);
+    }
+
+    #[test]
+    fn test_sector_length() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut disk = CraneDisk::init_storage_with_options(storage.clone(), DiskOptions {
+            sector_length: 4096,
+            ..DiskOptions::default()
+        }).unwrap();
+        disk.append_partition(2, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(1, 8, &25u64.to_be_bytes()).unwrap();
+        disk.save().unwrap();
+
+        assert_eq!(storage.read().unwrap().len().unwrap() % 4096, 0);
+
+        let disk = CraneDisk::from_storage(storage).unwrap();
+        let partition = disk.get_partition_with_id(1).unwrap();
+
+        assert_eq!(disk.sector_length(), 4096);
+        assert_eq!(partition.read().unwrap().total_bytes(), 8192);
+        assert_eq!(partition.read().unwrap().initialized_len, 4096 + 16);
+        assert_eq!(partition.write().unwrap().read_sectors(1, 2).unwrap()[8..16], 25u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_checksummed_disk() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut disk = CraneDisk::init_storage_with_options(storage.clone(), DiskOptions {
+            checksum: ChecksumKind::Crc32,
+            ..DiskOptions::default()
+        }).unwrap();
+        disk.append_partition(8, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, &[0xab; 16]).unwrap();
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(storage.clone()).unwrap();
+        assert_eq!(disk.header().checksum, ChecksumKind::Crc32);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[..16], [0xab; 16]);
+
+        let byte = {
+            let mut s = storage.write().unwrap();
+            let len = s.len().unwrap();
+            s.read_at(0, len).unwrap().windows(16).rposition(|w| w == [0xab; 16]).unwrap() as u64
+        };
+        storage.write().unwrap().write_at(byte + 3, &[0xaa]).unwrap();
+
+        let disk = CraneDisk::from_storage(storage).unwrap();
+        let sector = disk.get_partition_with_id(1).unwrap().read().unwrap().offset();
+
+        assert!(matches!(disk.partitions[0].write().unwrap().read_sectors(0, 1), Err(FSError::ChecksumMismatch { sector: s }) if s == sector));
+    }
+
+    #[test]
+    fn test_encrypted_disk() {
+        let key = EncryptionKey::new([0x5a; 32]);
+        let options = DiskOptions {
+            checksum: ChecksumKind::XxHash32,
+            encryption: EncryptionKind::Aes256Gcm,
+            ..DiskOptions::default()
+        };
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        assert!(matches!(CraneDisk::init_storage_with_options(storage.clone(), options), Err(FSError::KeyRequired)));
+
+        let mut disk = CraneDisk::init_storage_with_key(storage.clone(), options, Some(&key)).unwrap();
+        disk.append_partition(8, 0).unwrap();
+        disk.partitions[0].write().unwrap().write_sectors(0, 0, b"customer address").unwrap();
+        disk.save().unwrap();
+        drop(disk);
+
+        let raw = {
+            let mut s = storage.write().unwrap();
+            let len = s.len().unwrap();
+            s.read_at(0, len).unwrap()
+        };
+        assert!(!raw.windows(16).any(|w| w == b"customer address"));
+
+        // The header stays readable, the rest of the disk needs the key
+        let header = DiskHeader::read_from(&mut *storage.write().unwrap()).unwrap();
+        assert_eq!(header.encryption, EncryptionKind::Aes256Gcm);
+        assert!(matches!(CraneDisk::from_storage(storage.clone()), Err(FSError::KeyRequired)));
+        assert!(matches!(CraneDisk::from_storage_with_key(storage.clone(), Some(&EncryptionKey::new([1; 32]))), Err(FSError::WrongKey)));
+
+        let disk = CraneDisk::from_storage_with_key(storage, Some(&key)).unwrap();
+        assert_eq!(disk.options(), options);
+        assert_eq!(&disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap()[..16], b"customer address");
+
+        let plain: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        CraneDisk::init_storage(plain.clone()).unwrap();
+        assert!(matches!(CraneDisk::from_storage_with_key(plain, Some(&key)), Err(FSError::NotEncrypted)));
+    }
+
+    #[test]
+    fn test_reject_foreign_storage() {
+        let empty: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        assert!(matches!(CraneDisk::from_storage(empty), Err(FSError::Header(HeaderError::NotCraneDisk))));
+
+        let text: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::from_bytes(b"just some text".repeat(100))));
+        assert!(matches!(CraneDisk::from_storage(text), Err(FSError::Header(HeaderError::NotCraneDisk))));
+
+        let disk = CraneDisk::in_memory();
+        let header = disk.header().clone();
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+
+        assert_eq!(disk.header(), &header);
+    }
+
+    #[test]
+    fn test_reject_corrupt_partition_map() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut disk = CraneDisk::init_storage(storage.clone()).unwrap();
+        disk.append_partition(8, 0).unwrap();
+        disk.save().unwrap();
+
+        // Point the first partition's end far past the end of the disk
+        let end_byte = ROOT_START*(SECTOR_LENGTH as u64) + 32 + 8;
+        storage.write().unwrap().write_at(end_byte, &u64::MAX.to_be_bytes()).unwrap();
+        assert!(matches!(CraneDisk::from_storage(storage.clone()), Err(FSError::CorruptMetadata(_))));
+
+        // Link the head block of the map to itself
+        let head_byte = ROOT_START*(SECTOR_LENGTH as u64);
+        storage.write().unwrap().write_at(head_byte, &ROOT_START.to_be_bytes()).unwrap();
+        assert!(matches!(CraneDisk::from_storage(storage), Err(FSError::CorruptMetadata(_))));
+    }
+
+    #[test]
+    fn test_partition_bounds() {
+        let mut disk = CraneDisk::in_memory();
+        disk.append_partition(1, 0).unwrap();
+        disk.append_partition(1, 0).unwrap();
+
+        let result = disk.partitions[0].write().unwrap().write_sectors(0, SECTOR_LENGTH as u64 - 4, &[7u8; 8]);
+        assert!(matches!(result, Err(FSError::OutOfBounds { partition: 1, .. })));
+        assert!(disk.partitions[0].write().unwrap().read_sectors(0, 2).is_err());
+        disk.save().unwrap();
+
+        assert_eq!(disk.partitions[0].read().unwrap().initialized_len, 0);
+        assert_eq!(disk.partitions[1].write().unwrap().read_sectors(0, 1).unwrap(), vec![0u8; SECTOR_LENGTH]);
+    }
+
+    #[test]
+    fn test_grow_partition() {
+        let sl = SECTOR_LENGTH as u64;
+        let mut disk = CraneDisk::in_memory();
+        let first = disk.append_partition(2, 1).unwrap();
+        let second = disk.append_partition(2, 2).unwrap();
+        disk.partitions[1].write().unwrap().write_sectors(0, 0, &[9u8; 8]).unwrap();
+
+        // The second partition is last on the disk so it grows in place
+        disk.grow_partition(second, 2).unwrap();
+        assert_eq!(disk.get_partition_with_id(second).unwrap().read().unwrap().extents().len(), 1);
+
+        let partition = disk.get_partition_with_id(first).unwrap().clone();
+        disk.grow_partition(first, 3).unwrap();
+        assert_eq!(partition.read().unwrap().total_len(), 5);
+        assert_eq!(partition.read().unwrap().extents().len(), 2);
+
+        partition.write().unwrap().write_sectors(1, sl - 4, &[7u8; 8]).unwrap();
+        partition.write().unwrap().write_sectors(4, sl - 8, &[5u8; 8]).unwrap();
+        assert!(partition.write().unwrap().write_sectors(4, sl - 4, &[5u8; 8]).is_err());
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        let partition = disk.get_partition_with_id(first).unwrap();
+        let bytes = partition.write().unwrap().read_sectors(0, 5).unwrap();
+
+        assert_eq!(disk.header().version, FORMAT_VERSION);
+        assert_eq!(disk.partitions.len(), 2);
+        assert_eq!(partition.read().unwrap().extents().len(), 2);
+        assert_eq!(partition.read().unwrap().initialized_len, 5*sl);
+        assert_eq!(bytes[((2*sl - 4) as usize)..((2*sl + 4) as usize)], [7u8; 8]);
+        assert_eq!(bytes[((5*sl - 8) as usize)..], [5u8; 8]);
+        assert_eq!(disk.partitions[1].write().unwrap().read_sectors(0, 1).unwrap()[..8], [9u8; 8]);
+    }
+
+    #[test]
+    fn test_grow_into_large_released_partition() {
+        let mut disk = CraneDisk::in_memory();
+        let released = disk.append_partition(400, 1).unwrap();
+        let id = disk.append_partition(4, 2).unwrap();
+        for i in 0..4 {
+            disk.get_partition_with_id(released).unwrap().write().unwrap().write_sectors(i*100, 0, &vec![9u8; 100*SECTOR_LENGTH]).unwrap();
+            disk.save().unwrap();
+        }
+        disk.release_partition(released).unwrap();
+        disk.save().unwrap();
+
+        // Zeroing the sectors taken from the released extent doesn't fit in one batch of the log
+        disk.grow_partition(id, 400).unwrap();
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        let partition = disk.get_partition_with_id(id).unwrap();
+        assert_eq!(partition.read().unwrap().total_len(), 404);
+        assert!(partition.write().unwrap().read_sectors(4, 404).unwrap().iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_release_grown_partition() {
+        let mut disk = CraneDisk::in_memory();
+        let first = disk.append_partition(2, 1).unwrap();
+        disk.append_partition(2, 2).unwrap();
+        disk.grow_partition(first, 3).unwrap();
+
+        let extents = disk.get_partition_with_id(first).unwrap().read().unwrap().extents().to_vec();
+        disk.release_partition(first).unwrap();
+        disk.save().unwrap();
+
+        let disk = CraneDisk::from_storage(disk.storage()).unwrap();
+        assert_eq!(disk.free_extents(), extents);
+    }
+
+    #[test]
+    fn test_invalid_sector_length() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let disk = CraneDisk::init_storage_with_options(storage, DiskOptions {
+            sector_length: 300,
+            ..DiskOptions::default()
+        });
+
+        assert!(matches!(disk, Err(FSError::Header(HeaderError::InvalidSectorLength(300)))));
+    }
+
+    #[test]
+    fn test_unsaved_writes_discarded() {
+        let storage = {
+            let mut disk = CraneDisk::in_memory();
+            disk.append_partition(8, 0).unwrap();
+            disk.save().unwrap();
+
+            disk.partitions[0].write().unwrap().write_sectors(0, 0, &25u64.to_be_bytes()).unwrap();
+            disk.append_partition(8, 0).unwrap();
+
+            disk.storage()
+        };
+
+        let disk = CraneDisk::from_storage(storage).unwrap();
 
         assert_eq!(disk.partitions.len(), 1);
-        assert_eq!(disk.partitions[0].borrow().initialized_len, 8);
+        assert_eq!(disk.partitions[0].read().unwrap().initialized_len, 0);
+        assert_eq!(disk.partitions[0].write().unwrap().read_sectors(0, 1).unwrap(), vec![0u8; SECTOR_LENGTH]);
     }
-}
\ No newline at end of file
+}
@@ -1,12 +1,13 @@
-use std::{cell::RefCell, cmp::max, fs::File, rc::Weak};
+use std::{cmp::max, sync::{RwLock, Weak}};
 
-use crate::SECTOR_LENGTH;
-
-use super::{FSError, crane_reader::CraneReader, crane_writer::CraneWriter, reader::Reader, writer::Writer};
+use super::{FSError, crane_reader::CraneReader, crane_writer::CraneWriter, reader::Reader, storage::Storage, writer::Writer};
 
+/// A partition of a disk, its sectors are logically contiguous but can be spread over
+/// several extents of the disk so that it can grow in place.
 pub struct CranePartition {
     id: u64,
-    offset: u64,
+    /// The start sector and sector length of every extent, in logical order
+    extents: Vec<(u64, u64)>,
     total_len: u64,
     pub partition_type: u64,
     pub initialized_len: u64,
@@ -15,61 +16,62 @@ pub struct CranePartition {
 }
 
 impl CranePartition {
-    //TODO: Create a builder for this, too many constructor arguments
-    /// Creates a partition while assigning a type.
+    /// Creates a partition of a single extent that reads and writes its storage directly.
+    ///
+    /// Its writes skip the write ahead log of any disk the storage belongs to, the partitions of
+    /// a disk are built by the disk itself and got with `CraneDisk::get_partition_with_id`.
     /// # Arguments
     /// * `id` - The partition id.
     /// * `offset` - How offset the partition is from the start of the file in sectors.
     /// * `total_len` - How many sectors the partition is.
     /// * `initialized_len` - How many sectors in the partition that have been initialized.
-    /// * `partition_type` - The type of the partition.
-    /// * `rfile` - The file to read from.
-    /// * `wfile` - The file to write to.
-    pub fn with_type(id: u64, offset: u64, total_len: u64, initialized_len: u64, partition_type: u64, rfile: Weak<RefCell<File>>, wfile: Weak<RefCell<File>>) 
-        -> Self {
+    /// * `sector_length` - The byte length of the sectors of the storage.
+    /// * `storage` - The storage the partition lives in.
+    pub fn new(id: u64, offset: u64, total_len: u64, initialized_len: u64, sector_length: u64, storage: Weak<RwLock<dyn Storage>>) -> Self {
         let s = offset;
         let e = total_len + offset;
-        let reader = CraneReader::new(s, e, rfile.clone());
-        let writer = CraneWriter::new(s, e, wfile.clone());
+        let reader = CraneReader::new(id, s, e, sector_length, storage.clone());
+        let writer = CraneWriter::new(id, s, e, sector_length, storage);
         CranePartition {
             id,
-            offset,
+            extents: vec![(offset, total_len)],
             total_len,
             initialized_len,
-            partition_type,
+            partition_type: 0,
             reader: Box::new(reader),
             writer: Box::new(writer),
         }
-
     }
 
-    /// Creates a partition without assigning a type.
+    /// Creates a partition over an already built reader and writer.
     /// # Arguments
     /// * `id` - The partition id.
-    /// * `offset` - How offset the partition is from the start of the file in sectors.
-    /// * `total_len` - How many sectors the partition is.
+    /// * `extents` - The start sector and sector length of every extent of the partition, in logical order.
     /// * `initialized_len` - How many sectors in the partition that have been initialized.
-    /// * `rfile` - The file to read from.
-    /// * `wfile` - The file to write to.
-    pub fn new(id: u64, offset: u64, total_len: u64, initialized_len: u64, rfile: Weak<RefCell<File>>, wfile: Weak<RefCell<File>>) -> Self {
-        let s = offset;
-        let e = total_len + offset;
-        let reader = CraneReader::new(s, e, rfile.clone());
-        let writer = CraneWriter::new(s, e, wfile.clone());
+    /// * `partition_type` - The type of the partition.
+    /// * `reader` - The reader over the partition's sectors.
+    /// * `writer` - The writer over the partition's sectors.
+    pub fn from_parts(id: u64, extents: Vec<(u64, u64)>, initialized_len: u64, partition_type: u64, reader: Box<dyn Reader>, writer: Box<dyn Writer>)
+        -> Self {
         CranePartition {
             id,
-            offset,
-            total_len,
+            total_len: extents.iter().map(|(_, l)| l).sum(),
+            extents,
             initialized_len,
-            partition_type: 0,
-            reader: Box::new(reader),
-            writer: Box::new(writer),
+            partition_type,
+            reader,
+            writer,
         }
     }
 
-    /// Returns the offset of the partition.
+    /// Returns the offset of the partition, the start sector of its first extent.
     pub fn offset(&self) -> u64 {
-        self.offset
+        self.extents.first().map(|(s, _)| *s).unwrap_or(0)
+    }
+
+    /// Returns the start sector and sector length of every extent of the partition.
+    pub fn extents(&self) -> &[(u64, u64)] {
+        &self.extents
     }
 
     // Returns the total length of the partition in sectors.
@@ -79,13 +81,49 @@ impl CranePartition {
 
     /// Returns the total length of the partition in bytes.
     pub fn total_bytes(&self) -> u64 {
-        self.total_len * (SECTOR_LENGTH as u64)
+        self.total_len * self.sector_length()
+    }
+
+    /// Returns the byte length of the sectors of the disk the partition is on.
+    pub fn sector_length(&self) -> u64 {
+        self.writer.sector_length()
     }
 
     /// Returns the id of the partition.
     pub fn id(&self) -> u64 {
         self.id
     }
+
+    /// Splits a range of the partition's bytes into the ranges of the storage holding them,
+    /// as pairs of storage byte and byte length.
+    /// # Arguments
+    /// * `byte` - The first byte of the range, from the start of the partition.
+    /// * `len` - The byte length of the range.
+    fn physical_ranges(&self, byte: u64, len: u64) -> Result<Vec<(u64, u64)>, FSError> {
+        let end = byte + len;
+        if end > self.total_bytes() {
+            return Err(FSError::OutOfBounds {
+                partition: self.id,
+                start: byte,
+                end,
+                len: self.total_bytes(),
+            });
+        }
+
+        let sl = self.sector_length();
+        let mut ranges = vec![];
+        let mut logical = 0u64;
+        for (start, sectors) in self.extents.iter() {
+            let (s, e) = (logical, logical + sectors*sl);
+            if byte < e && end > s {
+                let from = byte.max(s);
+                ranges.push((start*sl + from - s, end.min(e) - from));
+            }
+            logical = e;
+        }
+
+        Ok(ranges)
+    }
 }
 
 impl Writer for CranePartition {
@@ -94,10 +132,15 @@ impl Writer for CranePartition {
     }
 
     fn write_sectors(&mut self, start: u64, offset: u64, bytes: &[u8]) -> Result<(), FSError> {
-        let s = start + self.offset;
-        let e = s + offset + (bytes.len() as u64);
-        self.initialized_len = max(start*(SECTOR_LENGTH as u64) + offset + (bytes.len() as u64), self.initialized_len);
-        self.writer.write_sectors(s,offset, bytes)
+        let sl = self.sector_length();
+        let mut written = 0usize;
+        for (byte, len) in self.physical_ranges(start*sl + offset, bytes.len() as u64)? {
+            let part = &bytes[written..(written + len as usize)];
+            self.writer.write_sectors(byte/sl, byte % sl, part)?;
+            written += len as usize;
+        }
+        self.initialized_len = max(start*self.writer.sector_length() + offset + (bytes.len() as u64), self.initialized_len);
+        Ok(())
     }
 
     fn capacity(&self) -> u64 {
@@ -111,10 +154,16 @@ impl Reader for CranePartition {
     }
 
     fn read_sectors(&mut self, start: u64, end: u64) -> Result<Vec<u8>, FSError> {
-        let s = start + self.offset;
-        let e = end + self.offset;
+        let sl = self.sector_length();
+        if end < start {
+            return Err(FSError::OutOfBounds { partition: self.id, start: start*sl, end: end*sl, len: self.total_bytes() });
+        }
 
-        self.reader.read_sectors(s, e)
+        let mut bytes = vec![];
+        for (byte, len) in self.physical_ranges(start*sl, (end - start)*sl)? {
+            bytes.append(&mut self.reader.read_sectors(byte/sl, (byte + len)/sl)?);
+        }
+        Ok(bytes)
     }
 
     fn capacity(&self) -> u64 {
@@ -1,23 +1,37 @@
-use std::{cell::RefCell, fs::File, io::{Read, Seek, SeekFrom}, rc::Weak, slice};
+use std::sync::{RwLock, Weak};
 
-use crate::SECTOR_LENGTH;
-
-use super::{FSError, reader::{Reader}};
+use super::{FSError, bounds::Bounds, reader::{Reader}, storage::Storage};
 
+/// Reads the sectors of a single partition, refusing to read outside of it.
 pub struct CraneReader {
     sector_length: u64,
-    start_byte: u64,
-    end_byte: u64,
-    file: Weak<RefCell<File>>
+    bounds: Bounds,
+    storage: Weak<RwLock<dyn Storage>>
 }
 
 impl CraneReader {
-    pub fn new(start: u64, end: u64, file: Weak<RefCell<File>>) -> Self {
+    /// Creates a reader over a partition.
+    /// # Arguments
+    /// * `partition` - The id of the partition, reported in out of bounds errors.
+    /// * `start` - The first sector of the partition.
+    /// * `end` - The sector after the last one of the partition.
+    /// * `sector_length` - The byte length of the sectors.
+    /// * `storage` - The storage the partition lives in.
+    pub fn new(partition: u64, start: u64, end: u64, sector_length: u64, storage: Weak<RwLock<dyn Storage>>) -> Self {
+        Self::with_extents(partition, &[(start, end - start)], sector_length, storage)
+    }
+
+    /// Creates a reader over a partition made of several extents.
+    /// # Arguments
+    /// * `partition` - The id of the partition, reported in out of bounds errors.
+    /// * `extents` - The start sector and sector length of every extent of the partition.
+    /// * `sector_length` - The byte length of the sectors.
+    /// * `storage` - The storage the partition lives in.
+    pub fn with_extents(partition: u64, extents: &[(u64, u64)], sector_length: u64, storage: Weak<RwLock<dyn Storage>>) -> Self {
         CraneReader {
-            sector_length: SECTOR_LENGTH as u64,
-            start_byte: start,
-            end_byte: end,
-            file
+            sector_length,
+            bounds: Bounds::new(partition, extents, sector_length),
+            storage
         }
     }
 }
@@ -30,50 +44,38 @@ impl Reader for CraneReader {
     fn read_sectors(&mut self, start: u64, end: u64) -> Result<Vec<u8>, FSError> {
         let start_byte = start*self.sector_length;
         let end_byte = end*self.sector_length;
+        self.bounds.check(start_byte, end_byte)?;
 
         let len = end_byte - start_byte;
 
-        if let Some(raw_file) = self.file.upgrade() {
-            let mut f = (&raw_file).borrow_mut(); 
-                
-            f.seek(SeekFrom::Start(start_byte)).expect("Unable to shift file");
-
-            let buffer = (&*f).bytes().take(len as usize).map(|v| v.expect("Unable to read byte in file")).collect();
-
-            return Ok(buffer);
+        if let Some(storage) = self.storage.upgrade() {
+            return storage.write().unwrap().read_at(start_byte, len);
         }
 
-        Err(FSError {})
+        Err(FSError::StorageClosed)
     }
 
 
     fn capacity(&self) -> u64 {
-        (self.end_byte-self.start_byte)/self.sector_length
+        self.bounds.len()/self.sector_length
     }
 }
 
 #[cfg(test)]
 mod test {
     use std::convert::TryInto;
-    use std::{fs::OpenOptions, path::PathBuf, rc::Rc};
+    use std::sync::Arc;
     use super::Reader;
 
-    use super::*;
-
-    pub fn get_db_file() -> File {
-        let path = PathBuf::from("./test/read.db");
+    use crate::cfs::memory_storage::MemoryStorage;
 
-        match &path.exists() {
-            true => OpenOptions::new().read(true).open(path).unwrap(),
-            false => File::create(path).unwrap(),
-        }
-    }
+    use super::*;
 
     #[test]
     pub fn test_writer() {
-        let file = Rc::new(RefCell::new(get_db_file()));
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::from_bytes(2048u64.to_be_bytes().to_vec())));
 
-        let mut reader = CraneReader::new(0, 16, Rc::downgrade(&file));
+        let mut reader = CraneReader::new(1, 0, 16, 256, Arc::downgrade(&storage));
 
         let data = reader.read_sectors(0, 1).unwrap();
 
@@ -81,5 +83,17 @@ mod test {
         let number = u64::from_be_bytes(data[..].try_into().unwrap());
 
         assert_eq!(number, 2048);
+        assert_eq!(reader.capacity(), 16);
+    }
+
+    #[test]
+    pub fn test_out_of_bounds() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::from_bytes(vec![0u8; 4096])));
+
+        let mut reader = CraneReader::new(2, 4, 8, 256, Arc::downgrade(&storage));
+
+        assert!(reader.read_sectors(4, 8).is_ok());
+        assert!(matches!(reader.read_sectors(7, 9), Err(FSError::OutOfBounds { partition: 2, start: 768, end: 1280, len: 1024 })));
+        assert!(matches!(reader.read_sectors(3, 5), Err(FSError::OutOfBounds { partition: 2, .. })));
     }
 }
\ No newline at end of file
@@ -1,25 +1,30 @@
-use std::io::{Seek, SeekFrom, Write};
-use std::{cell::RefCell, fs::File, rc::Weak};
+use std::sync::{RwLock, Weak};
 
 use super::FSError;
+use super::bounds::Bounds;
+use super::storage::Storage;
 use super::writer::{Writer};
 
-const SECTOR_LENGTH: u64 = 256;
-
+/// Writes to the sectors of a single partition, refusing to write outside of it.
 pub struct CraneWriter {
     sector_length: u64,
-    start_byte: u64,
-    end_byte: u64,
-    file: Weak<RefCell<File>>
+    bounds: Bounds,
+    storage: Weak<RwLock<dyn Storage>>
 }
 
 impl CraneWriter {
-    pub fn new(start: u64, end: u64, file: Weak<RefCell<File>>) -> Self {
+    /// Creates a writer over a partition.
+    /// # Arguments
+    /// * `partition` - The id of the partition, reported in out of bounds errors.
+    /// * `start` - The first sector of the partition.
+    /// * `end` - The sector after the last one of the partition.
+    /// * `sector_length` - The byte length of the sectors.
+    /// * `storage` - The storage the partition lives in.
+    pub fn new(partition: u64, start: u64, end: u64, sector_length: u64, storage: Weak<RwLock<dyn Storage>>) -> Self {
         CraneWriter {
-            sector_length: SECTOR_LENGTH,
-            start_byte: start,
-            end_byte: end,
-            file
+            sector_length,
+            bounds: Bounds::new(partition, &[(start, end - start)], sector_length),
+            storage
         }
     }
 }
@@ -30,49 +35,55 @@ impl Writer for CraneWriter {
     }
 
     fn write_sectors(&mut self, start: u64, offset: u64, bytes: &[u8]) -> Result<(), FSError> {
-        let start_byte = start*self.sector_length;
-
-        if let Some(filerc) = self.file.upgrade() {
-            let mut f = filerc.borrow_mut();
+        let start_byte = start*self.sector_length + offset;
+        self.bounds.check(start_byte, start_byte + bytes.len() as u64)?;
 
-            f.seek(SeekFrom::Start(start_byte + offset)).expect("Unable to shift in file");
-
-            f.write_all(bytes).expect("Failure writing bytes to db");
-            return Ok(());
+        if let Some(storage) = self.storage.upgrade() {
+            return storage.write().unwrap().write_at(start_byte, bytes);
         }
-        Err(FSError {})
+        Err(FSError::StorageClosed)
     }
 
     fn capacity(&self) -> u64 {
-        (self.end_byte-self.start_byte)/self.sector_length
+        self.bounds.len()/self.sector_length
     }
 }
 
 
 #[cfg(test)]
 mod test {
-    use std::{fs::OpenOptions, path::PathBuf, rc::Rc};
+    use std::sync::Arc;
     use super::Writer;
 
-    use super::*;
-
-    pub fn get_db_file() -> File {
-        let path = PathBuf::from("./test/write.db");
+    use crate::cfs::memory_storage::MemoryStorage;
 
-        match &path.exists() {
-            true => OpenOptions::new().write(true).open(path).unwrap(),
-            false => File::create(path).unwrap(),
-        }
-    }
+    use super::*;
 
     #[test]
     pub fn test_writer() {
-        let file = Rc::new(RefCell::new(get_db_file()));
+        let storage = Arc::new(RwLock::new(MemoryStorage::new()));
+        let dyn_storage: Arc<RwLock<dyn Storage>> = storage.clone();
 
-        let mut writer = CraneWriter::new(0, 16, Rc::downgrade(&file));
+        let mut writer = CraneWriter::new(1, 0, 16, 256, Arc::downgrade(&dyn_storage));
 
         let bytes = (2048u64).to_be_bytes();
 
-        writer.write_sectors(0, 0, &bytes).unwrap();
+        writer.write_sectors(1, 8, &bytes).unwrap();
+
+        assert_eq!(storage.read().unwrap().bytes().len(), 272);
+        assert_eq!(&storage.read().unwrap().bytes()[264..], &bytes);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_out_of_bounds() {
+        let storage = Arc::new(RwLock::new(MemoryStorage::new()));
+        let dyn_storage: Arc<RwLock<dyn Storage>> = storage.clone();
+
+        let mut writer = CraneWriter::new(2, 1, 2, 256, Arc::downgrade(&dyn_storage));
+
+        writer.write_sectors(1, 248, &[1u8; 8]).unwrap();
+        assert!(matches!(writer.write_sectors(1, 250, &[1u8; 8]), Err(FSError::OutOfBounds { partition: 2, start: 250, end: 258, len: 256 })));
+        assert!(matches!(writer.write_sectors(0, 0, &[1u8]), Err(FSError::OutOfBounds { partition: 2, .. })));
+        assert_eq!(storage.read().unwrap().bytes().len(), 512);
+    }
+}
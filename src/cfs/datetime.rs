@@ -0,0 +1,577 @@
+use std::{convert::TryFrom, error::Error, fmt, ops::{Add, Neg, Sub}, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+
+/// The number of microseconds in a second
+const MICROS_PER_SECOND: i64 = 1_000_000;
+/// The number of microseconds in a minute
+const MICROS_PER_MINUTE: i64 = 60*MICROS_PER_SECOND;
+/// The number of microseconds in an hour
+const MICROS_PER_HOUR: i64 = 60*MICROS_PER_MINUTE;
+/// The number of microseconds in a day
+const MICROS_PER_DAY: i64 = 24*MICROS_PER_HOUR;
+/// The furthest a timezone offset can be from UTC, in minutes
+pub const MAX_OFFSET_MINUTES: i16 = 18*60;
+
+/// A string that isn't the ISO-8601 form of the date or time it was parsed as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDateTimeError(String);
+
+impl fmt::Display for ParseDateTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid ISO-8601 date or time: {:?}", self.0)
+    }
+}
+
+impl Error for ParseDateTimeError {}
+
+/// A signed length of time with microsecond precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Interval {
+    micros: i64,
+}
+
+impl Interval {
+    /// Creates an interval of some microseconds.
+    /// # Arguments
+    /// * `micros` - The length of the interval.
+    pub fn from_micros(micros: i64) -> Self {
+        Interval { micros }
+    }
+
+    /// Creates an interval of some seconds.
+    /// # Arguments
+    /// * `seconds` - The length of the interval.
+    pub fn from_seconds(seconds: i64) -> Self {
+        Self::from_micros(seconds*MICROS_PER_SECOND)
+    }
+
+    /// Creates an interval of some minutes.
+    /// # Arguments
+    /// * `minutes` - The length of the interval.
+    pub fn from_minutes(minutes: i64) -> Self {
+        Self::from_micros(minutes*MICROS_PER_MINUTE)
+    }
+
+    /// Creates an interval of some hours.
+    /// # Arguments
+    /// * `hours` - The length of the interval.
+    pub fn from_hours(hours: i64) -> Self {
+        Self::from_micros(hours*MICROS_PER_HOUR)
+    }
+
+    /// Creates an interval of some days, each exactly 24 hours long.
+    /// # Arguments
+    /// * `days` - The length of the interval.
+    pub fn from_days(days: i64) -> Self {
+        Self::from_micros(days*MICROS_PER_DAY)
+    }
+
+    /// The length of the interval in microseconds.
+    pub fn micros(&self) -> i64 {
+        self.micros
+    }
+
+    /// The number of whole days in the interval, rounded towards zero.
+    pub fn days(&self) -> i64 {
+        self.micros/MICROS_PER_DAY
+    }
+}
+
+impl Add for Interval {
+    type Output = Interval;
+
+    fn add(self, other: Interval) -> Interval {
+        Interval::from_micros(self.micros + other.micros)
+    }
+}
+
+impl Sub for Interval {
+    type Output = Interval;
+
+    fn sub(self, other: Interval) -> Interval {
+        Interval::from_micros(self.micros - other.micros)
+    }
+}
+
+impl Neg for Interval {
+    type Output = Interval;
+
+    fn neg(self) -> Interval {
+        Interval::from_micros(-self.micros)
+    }
+}
+
+/// A day of the proleptic Gregorian calendar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Date {
+    /// Days since 1970-01-01
+    days: i32,
+}
+
+impl Date {
+    /// Creates the date a number of days after 1970-01-01.
+    /// # Arguments
+    /// * `days` - The days since 1970-01-01, negative for earlier dates.
+    pub fn from_days_since_epoch(days: i32) -> Self {
+        Date { days }
+    }
+
+    /// Creates a date from its year, month and day, if it exists.
+    /// # Arguments
+    /// * `year` - The year, 0 being 1 BC.
+    /// * `month` - The month, from 1 to 12.
+    /// * `day` - The day of the month, from 1.
+    pub fn from_ymd(year: i32, month: u32, day: u32) -> Option<Self> {
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        // Counts from March so the leap day ends the year, see Howard Hinnant's `days_from_civil`
+        let y = year as i64 - (month <= 2) as i64;
+        let era = y.div_euclid(400);
+        let yoe = y.rem_euclid(400);
+        let mp = (month as i64 + 9) % 12;
+        let doy = (153*mp + 2)/5 + day as i64 - 1;
+        let doe = yoe*365 + yoe/4 - yoe/100 + doy;
+
+        i32::try_from(era*146_097 + doe - 719_468).ok().map(Self::from_days_since_epoch)
+    }
+
+    /// The days since 1970-01-01, negative for earlier dates.
+    pub fn days_since_epoch(&self) -> i32 {
+        self.days
+    }
+
+    /// The year, month and day of the date.
+    pub fn ymd(&self) -> (i32, u32, u32) {
+        let z = self.days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe/1460 + doe/36_524 - doe/146_096)/365;
+        let doy = doe - (365*yoe + yoe/4 - yoe/100);
+        let mp = (5*doy + 2)/153;
+        let day = doy - (153*mp + 2)/5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era*400 + (month <= 2) as i64;
+
+        (year as i32, month as u32, day as u32)
+    }
+
+    /// The date some days later, or earlier for negative days, if it can be represented.
+    /// # Arguments
+    /// * `days` - The days to add.
+    pub fn checked_add_days(&self, days: i64) -> Option<Self> {
+        i32::try_from(self.days as i64 + days).ok().map(Self::from_days_since_epoch)
+    }
+
+    /// The number of days from another date to this one, negative if this one is earlier.
+    /// # Arguments
+    /// * `other` - The date to count from.
+    pub fn days_since(&self, other: Date) -> i64 {
+        self.days as i64 - other.days as i64
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        if year < 0 {
+            write!(f, "-{:04}-{:02}-{:02}", -year, month, day)
+        } else {
+            write!(f, "{:04}-{:02}-{:02}", year, month, day)
+        }
+    }
+}
+
+impl FromStr for Date {
+    type Err = ParseDateTimeError;
+
+    /// Parses a date in the `YYYY-MM-DD` form, the year can have a sign and more digits.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDateTimeError(s.to_owned());
+        let (negative, rest) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+
+        let mut parts = rest.splitn(3, '-');
+        let year = parse_digits(parts.next().ok_or_else(err)?, 4..=9).ok_or_else(err)? as i32;
+        let month = parse_digits(parts.next().ok_or_else(err)?, 2..=2).ok_or_else(err)? as u32;
+        let day = parse_digits(parts.next().ok_or_else(err)?, 2..=2).ok_or_else(err)? as u32;
+
+        Self::from_ymd(if negative { -year } else { year }, month, day).ok_or_else(err)
+    }
+}
+
+/// A time of day with microsecond precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Time {
+    /// Microseconds since midnight
+    micros: u64,
+}
+
+impl Time {
+    /// Creates the time some microseconds after midnight, if it is within the day.
+    /// # Arguments
+    /// * `micros` - The microseconds since midnight.
+    pub fn from_micros(micros: u64) -> Option<Self> {
+        if micros >= MICROS_PER_DAY as u64 {
+            return None;
+        }
+        Some(Time { micros })
+    }
+
+    /// Creates a time from its hour, minute, second and microsecond, if they are in range.
+    /// # Arguments
+    /// * `hour` - The hour, from 0 to 23.
+    /// * `minute` - The minute, from 0 to 59.
+    /// * `second` - The second, from 0 to 59.
+    /// * `micro` - The microsecond, from 0 to 999999.
+    pub fn from_hms_micro(hour: u32, minute: u32, second: u32, micro: u32) -> Option<Self> {
+        if hour > 23 || minute > 59 || second > 59 || micro as i64 >= MICROS_PER_SECOND {
+            return None;
+        }
+        Self::from_micros(hour as u64*MICROS_PER_HOUR as u64 + minute as u64*MICROS_PER_MINUTE as u64
+            + second as u64*MICROS_PER_SECOND as u64 + micro as u64)
+    }
+
+    /// The microseconds since midnight.
+    pub fn micros(&self) -> u64 {
+        self.micros
+    }
+
+    /// The hour, minute, second and microsecond of the time.
+    pub fn hms_micro(&self) -> (u32, u32, u32, u32) {
+        let m = self.micros as i64;
+        ((m/MICROS_PER_HOUR) as u32, (m % MICROS_PER_HOUR/MICROS_PER_MINUTE) as u32,
+            (m % MICROS_PER_MINUTE/MICROS_PER_SECOND) as u32, (m % MICROS_PER_SECOND) as u32)
+    }
+
+    /// The time an interval later, wrapping around midnight.
+    /// # Arguments
+    /// * `interval` - The interval to add, negative to go back.
+    pub fn wrapping_add(&self, interval: Interval) -> Self {
+        Time { micros: (self.micros as i64 + interval.micros.rem_euclid(MICROS_PER_DAY)).rem_euclid(MICROS_PER_DAY) as u64 }
+    }
+}
+
+impl fmt::Display for Time {
+    /// Formats the time as `HH:MM:SS`, followed by as many fractional digits as it needs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (hour, minute, second, micro) = self.hms_micro();
+        write!(f, "{:02}:{:02}:{:02}", hour, minute, second)?;
+        if micro != 0 {
+            write!(f, ".{}", format!("{:06}", micro).trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Time {
+    type Err = ParseDateTimeError;
+
+    /// Parses a time in the `HH:MM:SS` form with up to six fractional digits.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDateTimeError(s.to_owned());
+        let (hms, fraction) = match s.split_once('.') {
+            Some((hms, fraction)) => (hms, Some(fraction)),
+            None => (s, None),
+        };
+
+        let mut parts = hms.splitn(3, ':');
+        let hour = parse_digits(parts.next().ok_or_else(err)?, 2..=2).ok_or_else(err)?;
+        let minute = parse_digits(parts.next().ok_or_else(err)?, 2..=2).ok_or_else(err)?;
+        let second = parse_digits(parts.next().ok_or_else(err)?, 2..=2).ok_or_else(err)?;
+        let micro = match fraction {
+            Some(fraction) => parse_digits(fraction, 1..=6).ok_or_else(err)? * 10u64.pow(6 - fraction.len() as u32),
+            None => 0,
+        };
+
+        Self::from_hms_micro(hour as u32, minute as u32, second as u32, micro as u32).ok_or_else(err)
+    }
+}
+
+/// An instant with microsecond precision, optionally with the timezone offset it was recorded in.
+///
+/// The instant is kept in UTC, the offset only decides the local date and time it is shown as.
+/// Timestamps without an offset are shown as they are, with nothing to say which zone they are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    /// Microseconds since 1970-01-01T00:00:00Z
+    micros: i64,
+    /// Minutes east of UTC
+    offset: Option<i16>,
+}
+
+impl Timestamp {
+    /// Creates a timestamp some microseconds after the unix epoch, if the offset is in range and
+    /// the local time it gives can be represented.
+    /// # Arguments
+    /// * `micros` - The microseconds since 1970-01-01T00:00:00Z.
+    /// * `offset` - The timezone offset in minutes east of UTC, at most `MAX_OFFSET_MINUTES` either way.
+    pub fn from_micros(micros: i64, offset: Option<i16>) -> Option<Self> {
+        if offset.is_some_and(|o| o.abs() > MAX_OFFSET_MINUTES) {
+            return None;
+        }
+        micros.checked_add(offset.unwrap_or(0) as i64*MICROS_PER_MINUTE)?;
+        Some(Timestamp { micros, offset })
+    }
+
+    /// Creates a timestamp from the local date and time it has in a timezone offset.
+    /// # Arguments
+    /// * `date` - The local date.
+    /// * `time` - The local time.
+    /// * `offset` - The timezone offset in minutes east of UTC, if known.
+    pub fn from_date_time(date: Date, time: Time, offset: Option<i16>) -> Option<Self> {
+        // The first day's midnight is before the first instant, so this is summed in a wider type
+        let micros = date.days as i128*MICROS_PER_DAY as i128 + time.micros as i128
+            - offset.unwrap_or(0) as i128*MICROS_PER_MINUTE as i128;
+        Self::from_micros(i64::try_from(micros).ok()?, offset)
+    }
+
+    /// The current time in UTC.
+    pub fn now() -> Self {
+        let micros = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_micros() as i64,
+            Err(e) => -(e.duration().as_micros() as i64),
+        };
+        Timestamp { micros, offset: Some(0) }
+    }
+
+    /// The microseconds since 1970-01-01T00:00:00Z.
+    pub fn micros(&self) -> i64 {
+        self.micros
+    }
+
+    /// The timezone offset in minutes east of UTC, if known.
+    pub fn offset(&self) -> Option<i16> {
+        self.offset
+    }
+
+    /// The same instant shown in another timezone offset.
+    /// # Arguments
+    /// * `offset` - The timezone offset in minutes east of UTC, if known.
+    pub fn with_offset(&self, offset: Option<i16>) -> Option<Self> {
+        Self::from_micros(self.micros, offset)
+    }
+
+    /// The microseconds since the epoch of the local date and time
+    fn local_micros(&self) -> i64 {
+        self.micros + self.offset.unwrap_or(0) as i64*MICROS_PER_MINUTE
+    }
+
+    /// The local date of the timestamp.
+    pub fn date(&self) -> Date {
+        Date::from_days_since_epoch(self.local_micros().div_euclid(MICROS_PER_DAY) as i32)
+    }
+
+    /// The local time of the timestamp.
+    pub fn time(&self) -> Time {
+        Time { micros: self.local_micros().rem_euclid(MICROS_PER_DAY) as u64 }
+    }
+
+    /// The timestamp an interval later, or earlier for negative intervals, if it can be represented.
+    /// # Arguments
+    /// * `interval` - The interval to add.
+    pub fn checked_add(&self, interval: Interval) -> Option<Self> {
+        Self::from_micros(self.micros.checked_add(interval.micros)?, self.offset)
+    }
+
+    /// The timestamp an interval later, or earlier for negative intervals, stopping at the first
+    /// or last instant that can be represented with the timestamp's offset.
+    /// # Arguments
+    /// * `interval` - The interval to add.
+    pub fn saturating_add(&self, interval: Interval) -> Self {
+        let offset = self.offset.unwrap_or(0) as i64*MICROS_PER_MINUTE;
+        let (min, max) = if offset < 0 { (i64::MIN - offset, i64::MAX) } else { (i64::MIN, i64::MAX - offset) };
+        Timestamp { micros: self.micros.saturating_add(interval.micros).clamp(min, max), offset: self.offset }
+    }
+
+    /// The interval from another timestamp to this one, negative if this one is earlier.
+    /// # Arguments
+    /// * `other` - The timestamp to measure from.
+    pub fn since(&self, other: Timestamp) -> Interval {
+        Interval::from_micros(self.micros - other.micros)
+    }
+}
+
+impl Add<Interval> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, interval: Interval) -> Timestamp {
+        self.saturating_add(interval)
+    }
+}
+
+impl Sub<Interval> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, interval: Interval) -> Timestamp {
+        self + -interval
+    }
+}
+
+impl Sub for Timestamp {
+    type Output = Interval;
+
+    fn sub(self, other: Timestamp) -> Interval {
+        self.since(other)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Formats the timestamp as `YYYY-MM-DDTHH:MM:SS` followed by its offset, `Z` for UTC.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}T{}", self.date(), self.time())?;
+        match self.offset {
+            None => Ok(()),
+            Some(0) => write!(f, "Z"),
+            Some(o) => write!(f, "{}{:02}:{:02}", if o < 0 { '-' } else { '+' }, o.abs()/60, o.abs() % 60),
+        }
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = ParseDateTimeError;
+
+    /// Parses a timestamp in the `YYYY-MM-DDTHH:MM:SS` form, optionally followed by `Z` or an
+    /// offset like `+02:00`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDateTimeError(s.to_owned());
+        let (date, rest) = s.split_once(['T', 't', ' ']).ok_or_else(err)?;
+        let date: Date = date.parse().map_err(|_| err())?;
+
+        let (time, offset) = if let Some(time) = rest.strip_suffix(['Z', 'z']) {
+            (time, Some(0))
+        } else if let Some(i) = rest.rfind(['+', '-']) {
+            let (time, offset) = rest.split_at(i);
+            let digits = offset[1..].replace(':', "");
+            if (digits.len() != 4 && digits.len() != 2) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(err());
+            }
+            let hours = parse_digits(&digits[..2], 2..=2).ok_or_else(err)? as i16;
+            let minutes = parse_digits(&digits[2..], 0..=2).ok_or_else(err)? as i16;
+            if minutes > 59 {
+                return Err(err());
+            }
+            let minutes = hours*60 + minutes;
+            (time, Some(if offset.starts_with('-') { -minutes } else { minutes }))
+        } else {
+            (rest, None)
+        };
+        let time: Time = time.parse().map_err(|_| err())?;
+
+        Self::from_date_time(date, time, offset).ok_or_else(err)
+    }
+}
+
+/// Whether a year of the proleptic Gregorian calendar has a leap day
+fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// The number of days in a month of a year
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses a string of only ascii digits whose length is in a range.
+fn parse_digits(s: &str, lens: std::ops::RangeInclusive<usize>) -> Option<u64> {
+    if !lens.contains(&s.len()) || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if s.is_empty() {
+        return Some(0);
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(Date::from_ymd(1970, 1, 1).unwrap().days_since_epoch(), 0);
+        assert_eq!(Date::from_ymd(2000, 3, 1).unwrap().days_since_epoch(), 11_017);
+        assert_eq!(Date::from_ymd(1969, 12, 31).unwrap().days_since_epoch(), -1);
+        assert_eq!(Date::from_ymd(2024, 2, 30), None);
+        assert_eq!(Date::from_ymd(1900, 2, 29), None);
+
+        for days in [-800_000, -1, 0, 59, 11_016, 19_782, 2_932_896].iter() {
+            let date = Date::from_days_since_epoch(*days);
+            let (y, m, d) = date.ymd();
+            assert_eq!(Date::from_ymd(y, m, d), Some(date));
+            assert_eq!(date.to_string().parse::<Date>(), Ok(date));
+        }
+
+        let leap = "2024-02-29".parse::<Date>().unwrap();
+        assert_eq!(leap.ymd(), (2024, 2, 29));
+        assert_eq!(leap.checked_add_days(1).unwrap().to_string(), "2024-03-01");
+        assert_eq!(leap.days_since("2023-02-28".parse().unwrap()), 366);
+        assert_eq!(Date::from_ymd(-44, 3, 15).unwrap().to_string(), "-0044-03-15");
+        assert!("2024-2-29".parse::<Date>().is_err());
+        assert!("2024-13-01".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn test_times() {
+        let time: Time = "23:59:59.5".parse().unwrap();
+        assert_eq!(time.hms_micro(), (23, 59, 59, 500_000));
+        assert_eq!(time.to_string(), "23:59:59.5");
+        assert_eq!("07:05:00".parse::<Time>().unwrap().to_string(), "07:05:00");
+        assert_eq!(time.wrapping_add(Interval::from_seconds(1)).to_string(), "00:00:00.5");
+        assert_eq!(Time::default().wrapping_add(-Interval::from_days(3) - Interval::from_hours(1)).to_string(), "23:00:00");
+        assert!("24:00:00".parse::<Time>().is_err());
+        assert!("12:00:00.1234567".parse::<Time>().is_err());
+        assert_eq!(Time::from_micros(MICROS_PER_DAY as u64), None);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let ts: Timestamp = "2024-03-10T23:30:00+02:00".parse().unwrap();
+        assert_eq!(ts.offset(), Some(120));
+        assert_eq!(ts.to_string(), "2024-03-10T23:30:00+02:00");
+        assert_eq!(ts.with_offset(Some(0)).unwrap().to_string(), "2024-03-10T21:30:00Z");
+        assert_eq!(ts.with_offset(Some(-570)).unwrap().to_string(), "2024-03-10T12:00:00-09:30");
+        assert_eq!("2024-03-10T21:30:00Z".parse::<Timestamp>().unwrap().micros(), ts.micros());
+
+        let naive: Timestamp = "1969-12-31 23:59:59.999999".parse().unwrap();
+        assert_eq!(naive.micros(), -1);
+        assert_eq!(naive.offset(), None);
+        assert_eq!(naive.to_string(), "1969-12-31T23:59:59.999999");
+
+        let later = ts + Interval::from_days(1) + Interval::from_minutes(45);
+        assert_eq!(later.to_string(), "2024-03-12T00:15:00+02:00");
+        assert_eq!(later - ts, Interval::from_minutes(24*60 + 45));
+        assert_eq!((later - Interval::from_hours(25)).date().to_string(), "2024-03-10");
+
+        assert!("2024-03-10T23:30:00+19:00".parse::<Timestamp>().is_err());
+        assert!("2024-03-10".parse::<Timestamp>().is_err());
+        assert!(Timestamp::now() > ts);
+    }
+
+    #[test]
+    fn test_timestamp_limits() {
+        assert!("2024-01-01T00:00:00+1é1".parse::<Timestamp>().is_err());
+        assert!("2024-01-01T00:00:00+é".parse::<Timestamp>().is_err());
+        assert!("5000000-01-01T00:00:00Z".parse::<Timestamp>().is_err());
+        assert!("-5000000-01-01T00:00:00Z".parse::<Timestamp>().is_err());
+
+        assert_eq!(Timestamp::from_micros(i64::MIN, Some(-60)), None);
+        assert_eq!(Timestamp::from_micros(i64::MAX, Some(60)), None);
+        let first = Timestamp::from_micros(i64::MIN, Some(60)).unwrap();
+        assert_eq!(first.to_string().parse::<Timestamp>(), Ok(first));
+
+        let last = Timestamp::from_micros(i64::MAX - MICROS_PER_HOUR, Some(60)).unwrap();
+        assert_eq!(last.checked_add(Interval::from_micros(1)), None);
+        assert_eq!(last + Interval::from_days(1), last);
+        assert_eq!(first - Interval::from_days(1), first);
+        let _ = (last + Interval::from_micros(i64::MAX)).to_string();
+    }
+}
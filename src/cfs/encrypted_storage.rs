@@ -0,0 +1,225 @@
+use std::{cmp::min, sync::{Arc, RwLock}};
+
+use super::{FSError, encryption::{EncryptionKind, SectorCipher}, storage::Storage};
+
+/// A storage that encrypts every sector of the storage it wraps, authenticating it on every read.
+///
+/// Like `ChecksumStorage`, the first `base` sectors are passed through as they are and the rest of
+/// the wrapped storage is split into groups, each starting with a sector holding the nonces and
+/// tags of the data sectors that follow it. Every sector is bound to its number, so sectors can't
+/// be swapped around without it being noticed. Offsets given to this storage only count the
+/// data sectors.
+pub struct EncryptedStorage {
+    inner: Arc<RwLock<dyn Storage>>,
+    cipher: SectorCipher,
+    sector_length: u64,
+    base: u64,
+}
+
+impl EncryptedStorage {
+    /// Wraps a storage.
+    /// # Arguments
+    /// * `inner` - The storage to encrypt.
+    /// * `cipher` - The cipher created from the disk's key.
+    /// * `sector_length` - The byte length of the sectors being encrypted.
+    /// * `base` - How many sectors at the start of the storage aren't encrypted.
+    pub fn new(inner: Arc<RwLock<dyn Storage>>, cipher: SectorCipher, sector_length: u64, base: u64) -> Self {
+        EncryptedStorage {
+            inner,
+            cipher,
+            sector_length,
+            base,
+        }
+    }
+
+    /// The number of data sectors covered by each seal sector
+    fn group_len(&self) -> u64 {
+        self.sector_length/EncryptionKind::SEAL_LEN
+    }
+
+    /// The sector of the wrapped storage a data sector is kept in
+    fn physical_sector(&self, sector: u64) -> u64 {
+        if sector < self.base {
+            return sector;
+        }
+        let j = sector - self.base;
+        self.base + (j/self.group_len())*(self.group_len() + 1) + 1 + j % self.group_len()
+    }
+
+    /// The byte of the wrapped storage a data sector's nonce and tag are kept at
+    fn seal_byte(&self, sector: u64) -> u64 {
+        let j = sector - self.base;
+        (self.base + (j/self.group_len())*(self.group_len() + 1))*self.sector_length + (j % self.group_len())*EncryptionKind::SEAL_LEN
+    }
+
+    /// Reads and decrypts a whole data sector.
+    fn read_sector(&self, sector: u64) -> Result<Vec<u8>, FSError> {
+        let mut inner = self.inner.write().unwrap();
+        let mut bytes = inner.read_at(self.physical_sector(sector)*self.sector_length, self.sector_length)?;
+        let seal = inner.read_at(self.seal_byte(sector), EncryptionKind::SEAL_LEN)?;
+
+        if bytes.len() as u64 != self.sector_length || !self.cipher.open(&sector.to_be_bytes(), &seal, &mut bytes) {
+            return Err(FSError::AuthenticationFailed { sector });
+        }
+
+        Ok(bytes)
+    }
+
+    /// Encrypts a whole data sector and writes its nonce and tag followed by its bytes.
+    fn write_sector(&self, sector: u64, mut bytes: Vec<u8>) -> Result<(), FSError> {
+        let seal = self.cipher.seal(&sector.to_be_bytes(), &mut bytes);
+        let mut inner = self.inner.write().unwrap();
+
+        // The seal sector comes first so a storage growing sector by sector never has a gap
+        inner.write_at(self.seal_byte(sector), &seal)?;
+        inner.write_at(self.physical_sector(sector)*self.sector_length, &bytes)
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        let end = min(byte + len, self.len()?);
+        let sl = self.sector_length;
+        let mut buffer = Vec::with_capacity(end.saturating_sub(byte) as usize);
+
+        let mut curr = byte;
+        while curr < end {
+            let sector = curr / sl;
+            let s = curr % sl;
+            let e = min(end, (sector + 1)*sl) - sector*sl;
+
+            if sector < self.base {
+                buffer.append(&mut self.inner.write().unwrap().read_at(curr, e - s)?);
+            } else {
+                buffer.extend_from_slice(&self.read_sector(sector)?[(s as usize)..(e as usize)]);
+            }
+            curr = sector*sl + e;
+        }
+
+        Ok(buffer)
+    }
+
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let end = byte + bytes.len() as u64;
+        let sl = self.sector_length;
+        let old_len = self.len()?;
+
+        let mut curr = byte;
+        while curr < end {
+            let sector = curr / sl;
+            let s = curr % sl;
+            let e = min(end, (sector + 1)*sl) - sector*sl;
+            let from = (curr - byte) as usize;
+            let part = &bytes[from..(from + (e - s) as usize)];
+
+            if sector < self.base {
+                self.inner.write().unwrap().write_at(curr, part)?;
+            } else {
+                // Only whole sectors can be sealed, so partial writes are merged into the old bytes
+                let mut whole = if e - s < sl && sector*sl < old_len {
+                    self.read_sector(sector)?
+                } else {
+                    vec![0u8; sl as usize]
+                };
+                whole[(s as usize)..(e as usize)].copy_from_slice(part);
+
+                self.write_sector(sector, whole)?;
+            }
+            curr = sector*sl + e;
+        }
+
+        Ok(())
+    }
+
+    fn len(&self) -> Result<u64, FSError> {
+        let len = self.inner.read().unwrap().len()?;
+        let sectors = len/self.sector_length;
+        if sectors <= self.base {
+            return Ok(len);
+        }
+
+        let rest = sectors - self.base;
+        let data = (rest/(self.group_len() + 1))*self.group_len() + (rest % (self.group_len() + 1)).saturating_sub(1);
+
+        Ok((self.base + data)*self.sector_length)
+    }
+
+    fn flush(&mut self) -> Result<(), FSError> {
+        self.inner.write().unwrap().flush()
+    }
+
+    fn sync(&mut self) -> Result<(), FSError> {
+        self.inner.write().unwrap().sync()
+    }
+
+    /// Sectors can only be encrypted whole, so the length is rounded up to a whole sector.
+    fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+        let sectors = len.div_ceil(self.sector_length);
+        let physical = match sectors {
+            0 => 0,
+            s => (self.physical_sector(s - 1) + 1)*self.sector_length,
+        };
+
+        self.inner.write().unwrap().truncate(physical)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cfs::{encryption::EncryptionKey, memory_storage::MemoryStorage};
+
+    use super::*;
+
+    const SL: u64 = 128;
+
+    fn create_storage() -> (Arc<RwLock<MemoryStorage>>, EncryptedStorage) {
+        let inner = Arc::new(RwLock::new(MemoryStorage::new()));
+        let cipher = SectorCipher::new(EncryptionKind::Aes256Gcm, &EncryptionKey::new([9; 32])).unwrap();
+        let storage = EncryptedStorage::new(inner.clone(), cipher, SL, 1);
+
+        (inner, storage)
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let (inner, mut storage) = create_storage();
+
+        // Fill a whole group of 4 data sectors and start the next
+        storage.write_at(0, &vec![0u8; (SL*8) as usize]).unwrap();
+        storage.write_at(SL*5 + 10, b"secret").unwrap();
+
+        assert_eq!(storage.len().unwrap(), SL*8);
+        assert_eq!(inner.read().unwrap().len().unwrap(), SL*10);
+        assert_eq!(storage.read_at(SL*5 + 9, 8).unwrap(), b"\0secret\0".to_vec());
+
+        let raw = inner.write().unwrap().read_at(0, SL*10).unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+    }
+
+    #[test]
+    fn test_detect_tampering() {
+        let (inner, mut storage) = create_storage();
+        storage.write_at(0, &vec![7u8; (SL*5) as usize]).unwrap();
+
+        // Sector 2 is kept after the plain sector, the seal sector and sector 1
+        let byte = SL*3 + 5;
+        let old = inner.write().unwrap().read_at(byte, 1).unwrap()[0];
+        inner.write().unwrap().write_at(byte, &[old ^ 1]).unwrap();
+
+        assert_eq!(storage.read_at(0, SL*2).unwrap().len() as u64, SL*2);
+        assert!(matches!(storage.read_at(SL*2, 1), Err(FSError::AuthenticationFailed { sector: 2 })));
+
+        // Swapping sectors 3 and 4 along with their seals is noticed too
+        let (a, b) = (SL*4, SL*5);
+        let mut raw = inner.write().unwrap();
+        let (sector_a, sector_b) = (raw.read_at(a, SL).unwrap(), raw.read_at(b, SL).unwrap());
+        let seals = raw.read_at(SL + 2*EncryptionKind::SEAL_LEN, 2*EncryptionKind::SEAL_LEN).unwrap();
+        let (seal_a, seal_b) = seals.split_at(EncryptionKind::SEAL_LEN as usize);
+        raw.write_at(a, &sector_b).unwrap();
+        raw.write_at(b, &sector_a).unwrap();
+        raw.write_at(SL + 2*EncryptionKind::SEAL_LEN, &[seal_b, seal_a].concat()).unwrap();
+        drop(raw);
+
+        assert!(matches!(storage.read_at(SL*3, 1), Err(FSError::AuthenticationFailed { sector: 3 })));
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, Tag, aead::{AeadCore, AeadInPlace, OsRng}};
+
+/// The authenticated encryption a disk applies to every sector after its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionKind {
+    /// Sectors are stored as they are
+    #[default]
+    None,
+    /// AES-256 in Galois/Counter Mode
+    Aes256Gcm,
+}
+
+impl EncryptionKind {
+    /// The number of bytes of the nonce each sector is encrypted with
+    pub const NONCE_LEN: u64 = 12;
+    /// The number of bytes of the tag authenticating each sector
+    pub const TAG_LEN: u64 = 16;
+    /// The number of bytes kept for each sector next to its encrypted bytes, its nonce and tag
+    pub const SEAL_LEN: u64 = Self::NONCE_LEN + Self::TAG_LEN;
+
+    /// The id the kind is recorded as in the disk header.
+    pub fn id(&self) -> u32 {
+        match self {
+            EncryptionKind::None => 0,
+            EncryptionKind::Aes256Gcm => 1,
+        }
+    }
+
+    /// Gets the kind recorded in a disk header, if it is a known one.
+    /// # Arguments
+    /// * `id` - The id from the header.
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(EncryptionKind::None),
+            1 => Some(EncryptionKind::Aes256Gcm),
+            _ => None,
+        }
+    }
+}
+
+/// The 256 bit key a disk is encrypted with, supplied by the caller every time the disk is opened
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Wraps the bytes of a key.
+    /// # Arguments
+    /// * `bytes` - The key, for example derived from a passphrase or taken from a key store.
+    pub fn new(bytes: [u8; 32]) -> Self {
+        EncryptionKey(bytes)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// The nonce and tag kept for an encrypted run of bytes
+pub type Seal = [u8; EncryptionKind::SEAL_LEN as usize];
+
+/// Bound to the key check so it can't be confused with a sector's seal
+const KEY_CHECK_AAD: &[u8] = b"crane key check";
+
+/// Encrypts and decrypts sectors with a disk's key.
+pub struct SectorCipher {
+    cipher: Aes256Gcm,
+}
+
+impl SectorCipher {
+    /// Creates the cipher for a kind of encryption, `None` if the kind doesn't encrypt.
+    /// # Arguments
+    /// * `kind` - The encryption the disk uses.
+    /// * `key` - The key the disk is encrypted with.
+    pub fn new(kind: EncryptionKind, key: &EncryptionKey) -> Option<Self> {
+        match kind {
+            EncryptionKind::None => None,
+            EncryptionKind::Aes256Gcm => Some(SectorCipher {
+                cipher: Aes256Gcm::new(&key.0.into()),
+            }),
+        }
+    }
+
+    /// Encrypts bytes in place under a fresh random nonce, returning the nonce and tag.
+    /// # Arguments
+    /// * `aad` - Bytes the encryption is bound to without being stored, like the sector number.
+    /// * `bytes` - The bytes to encrypt.
+    pub fn seal(&self, aad: &[u8], bytes: &mut [u8]) -> Seal {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let tag = self.cipher.encrypt_in_place_detached(&nonce, aad, bytes)
+            .expect("Sectors are far shorter than the longest message AES-GCM can encrypt");
+
+        let mut seal = [0u8; EncryptionKind::SEAL_LEN as usize];
+        seal[..(EncryptionKind::NONCE_LEN as usize)].copy_from_slice(&nonce);
+        seal[(EncryptionKind::NONCE_LEN as usize)..].copy_from_slice(&tag);
+        seal
+    }
+
+    /// Decrypts bytes in place, returning whether they are authentic. Bytes that aren't are left
+    /// as they were.
+    /// # Arguments
+    /// * `aad` - The bytes the encryption was bound to.
+    /// * `seal` - The nonce and tag the bytes were sealed with.
+    /// * `bytes` - The bytes to decrypt.
+    pub fn open(&self, aad: &[u8], seal: &[u8], bytes: &mut [u8]) -> bool {
+        if seal.len() as u64 != EncryptionKind::SEAL_LEN {
+            return false;
+        }
+        let (nonce, tag) = seal.split_at(EncryptionKind::NONCE_LEN as usize);
+
+        self.cipher.decrypt_in_place_detached(Nonce::from_slice(nonce), aad, bytes, Tag::from_slice(tag)).is_ok()
+    }
+
+    /// Produces the value kept in the header that tells whether a key is the disk's key.
+    pub fn key_check(&self) -> Seal {
+        self.seal(KEY_CHECK_AAD, &mut [])
+    }
+
+    /// Whether the key this cipher was created with produced a key check.
+    /// # Arguments
+    /// * `check` - The key check from the header.
+    pub fn verify_key(&self, check: &[u8]) -> bool {
+        self.open(KEY_CHECK_AAD, check, &mut [])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = SectorCipher::new(EncryptionKind::Aes256Gcm, &EncryptionKey::new([7; 32])).unwrap();
+        let mut bytes = b"some customer data".to_vec();
+
+        let seal = cipher.seal(&5u64.to_be_bytes(), &mut bytes);
+        assert_ne!(&bytes[..], b"some customer data");
+
+        let mut moved = bytes.clone();
+        assert!(!cipher.open(&6u64.to_be_bytes(), &seal, &mut moved));
+        assert!(cipher.open(&5u64.to_be_bytes(), &seal, &mut bytes));
+        assert_eq!(&bytes[..], b"some customer data");
+    }
+
+    #[test]
+    fn test_key_check() {
+        let cipher = SectorCipher::new(EncryptionKind::Aes256Gcm, &EncryptionKey::new([1; 32])).unwrap();
+        let other = SectorCipher::new(EncryptionKind::Aes256Gcm, &EncryptionKey::new([2; 32])).unwrap();
+        let check = cipher.key_check();
+
+        assert!(cipher.verify_key(&check));
+        assert!(!other.verify_key(&check));
+        assert!(SectorCipher::new(EncryptionKind::None, &EncryptionKey::new([1; 32])).is_none());
+    }
+}
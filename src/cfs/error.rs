@@ -0,0 +1,115 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use super::header::HeaderError;
+
+/// Everything that can go wrong reading or writing a disk
+#[derive(Debug)]
+pub enum FSError {
+    /// Reading from or writing to the underlying file failed
+    Io(io::Error),
+    /// The storage behind a reader, writer or log has already been dropped
+    StorageClosed,
+    /// An access reached outside of the partition it was made through
+    OutOfBounds {
+        /// The id of the partition
+        partition: u64,
+        /// The first byte accessed, relative to the partition
+        start: u64,
+        /// The byte after the last one accessed, relative to the partition
+        end: u64,
+        /// The byte length of the partition
+        len: u64,
+    },
+    /// A sector's bytes don't match the checksum stored for them
+    ChecksumMismatch {
+        sector: u64,
+    },
+    /// A sector of an encrypted disk can't be decrypted, it was altered or moved since it was written
+    AuthenticationFailed {
+        sector: u64,
+    },
+    /// The disk is encrypted and can only be opened with its key
+    KeyRequired,
+    /// The key the disk was opened with isn't the one it is encrypted with
+    WrongKey,
+    /// A key was given for a disk that isn't encrypted
+    NotEncrypted,
+    /// The disk's own bookkeeping, like the partition map or the log, doesn't make sense
+    CorruptMetadata(String),
+    /// Bytes read back from a partition can't be parsed as the values they should hold
+    CorruptData(String),
+    /// The disk header is missing, invalid or from an unknown format
+    Header(HeaderError),
+    /// The writes made since the last save don't fit in the write ahead log
+    LogFull {
+        /// The bytes the batch needs
+        needed: u64,
+        /// The bytes the log has room for
+        available: u64,
+    },
+    /// The bytes being parsed ended before the value being read from them did
+    UnexpectedEnd {
+        /// The bytes the value needs
+        needed: u64,
+        /// The bytes that were left
+        remaining: u64,
+    },
+    /// The disk file is already locked by another handle, in this process or another one
+    Locked(PathBuf),
+    /// No partition has the given id
+    UnknownPartition(u64),
+    /// A job that commits as it goes was started while writes were waiting to be committed, they
+    /// would have been committed along with it
+    UncommittedWrites,
+    /// The write ahead log holds a committed batch that a read only disk can't replay
+    UnappliedLog,
+}
+
+impl fmt::Display for FSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FSError::Io(_) => write!(f, "disk i/o failed"),
+            FSError::StorageClosed => write!(f, "the disk's storage has been closed"),
+            FSError::OutOfBounds { partition, start, end, len } =>
+                write!(f, "access to bytes {}..{} of partition {} is outside its {} bytes", start, end, partition, len),
+            FSError::ChecksumMismatch { sector } => write!(f, "checksum mismatch in sector {}", sector),
+            FSError::AuthenticationFailed { sector } => write!(f, "sector {} failed authentication", sector),
+            FSError::KeyRequired => write!(f, "the disk is encrypted, a key is required to open it"),
+            FSError::WrongKey => write!(f, "the key doesn't match the one the disk is encrypted with"),
+            FSError::NotEncrypted => write!(f, "a key was given but the disk isn't encrypted"),
+            FSError::CorruptMetadata(what) => write!(f, "corrupt disk metadata: {}", what),
+            FSError::CorruptData(what) => write!(f, "corrupt data: {}", what),
+            FSError::Header(err) => write!(f, "invalid disk header: {}", err),
+            FSError::LogFull { needed, available } =>
+                write!(f, "{} bytes of unsaved writes don't fit in the {} byte write ahead log", needed, available),
+            FSError::UnexpectedEnd { needed, remaining } =>
+                write!(f, "expected {} more bytes but only {} remain", needed, remaining),
+            FSError::Locked(path) => write!(f, "{} is locked by another handle", path.display()),
+            FSError::UnknownPartition(id) => write!(f, "no partition has id {}", id),
+            FSError::UncommittedWrites => write!(f, "the disk has uncommitted writes, commit or save them first"),
+            FSError::UnappliedLog => write!(f, "the write ahead log holds a batch that isn't applied yet, open the disk for writing to replay it"),
+        }
+    }
+}
+
+impl Error for FSError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FSError::Io(err) => Some(err),
+            FSError::Header(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FSError {
+    fn from(err: io::Error) -> Self {
+        FSError::Io(err)
+    }
+}
+
+impl From<HeaderError> for FSError {
+    fn from(err: HeaderError) -> Self {
+        FSError::Header(err)
+    }
+}
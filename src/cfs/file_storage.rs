@@ -0,0 +1,61 @@
+use std::{fs::File, io::{Read, Seek, SeekFrom, Write}};
+
+use super::{FSError, storage::Storage};
+
+/// A storage backed by a file on disk
+pub struct FileStorage {
+    read_file: File,
+    write_file: File,
+}
+
+impl FileStorage {
+    /// Creates a storage over a file.
+    /// # Arguments
+    /// * `read_file` - The file to read from.
+    /// * `write_file` - The file to write to.
+    pub fn new(read_file: File, write_file: File) -> Self {
+        FileStorage {
+            read_file,
+            write_file,
+        }
+    }
+
+    /// Creates a storage over a single file handle, opened for both reading and writing.
+    /// # Arguments
+    /// * `file` - The file to read from and write to.
+    pub fn from_file(file: File) -> Result<Self, FSError> {
+        Ok(Self::new(file.try_clone()?, file))
+    }
+}
+
+impl Storage for FileStorage {
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        self.read_file.seek(SeekFrom::Start(byte))?;
+
+        let mut buffer = Vec::with_capacity(len as usize);
+        (&self.read_file).take(len).read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        self.write_file.seek(SeekFrom::Start(byte))?;
+
+        Ok(self.write_file.write_all(bytes)?)
+    }
+
+    fn len(&self) -> Result<u64, FSError> {
+        Ok(self.read_file.metadata()?.len())
+    }
+
+    fn sync(&mut self) -> Result<(), FSError> {
+        Ok(self.write_file.sync_data()?)
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+        if len < self.len()? {
+            self.write_file.set_len(len)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use std::{convert::TryInto, error::Error, fmt, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::SECTOR_LENGTH;
+
+use super::{FSError, buffer::Buffer, checksum::ChecksumKind, encryption::{EncryptionKind, Seal}, storage::Storage};
+
+/// Marks the start of a crane disk, "CRANEDSK" in ascii
+const DISK_MAGIC: u64 = 0x4352_414e_4544_534b;
+/// The on disk format this version of crane reads and writes
+pub const FORMAT_VERSION: u32 = 1;
+/// The number of sectors at the start of the disk holding the header
+pub const HEADER_SECTORS: u64 = 1;
+/// The number of bytes reserved for the name of the program that created the disk
+const CREATOR_LEN: usize = 32;
+/// The number of bytes in the header before its checksum
+const HEADER_LEN: usize = 8 + 4 + 4 + 4 + 8 + CREATOR_LEN + 4 + EncryptionKind::SEAL_LEN as usize;
+/// The smallest sector length a disk can be initialized with
+pub const MIN_SECTOR_LENGTH: u64 = 128;
+/// The largest sector length a disk can be initialized with
+pub const MAX_SECTOR_LENGTH: u64 = 65536;
+
+/// Whether a disk can use sectors of the given byte length, it has to be a power of two
+/// between `MIN_SECTOR_LENGTH` and `MAX_SECTOR_LENGTH`.
+pub fn is_valid_sector_length(sector_length: u64) -> bool {
+    sector_length.is_power_of_two() && (MIN_SECTOR_LENGTH..=MAX_SECTOR_LENGTH).contains(&sector_length)
+}
+
+/// Why a storage couldn't be opened as a crane disk
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    /// The storage doesn't start with a crane header, it is empty or holds some other file
+    NotCraneDisk,
+    /// The disk was written in a format this version of crane doesn't know about
+    UnsupportedVersion(u32),
+    /// The header records a sector length no disk can have
+    InvalidSectorLength(u32),
+    /// The header records a sector checksum this version of crane doesn't know about
+    UnknownChecksum(u32),
+    /// The header records an encryption this version of crane doesn't know about
+    UnknownEncryption(u32),
+    /// The header has the crane magic but its checksum doesn't match
+    Corrupt,
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::NotCraneDisk => write!(f, "not a crane disk"),
+            HeaderError::UnsupportedVersion(v) => write!(f, "unsupported disk format version {}, expected {}", v, FORMAT_VERSION),
+            HeaderError::InvalidSectorLength(l) => write!(f, "invalid sector length {}", l),
+            HeaderError::UnknownChecksum(c) => write!(f, "unknown sector checksum {}", c),
+            HeaderError::UnknownEncryption(e) => write!(f, "unknown encryption {}", e),
+            HeaderError::Corrupt => write!(f, "disk header checksum mismatch"),
+        }
+    }
+}
+
+impl Error for HeaderError {}
+
+/// The block at the start of every disk describing how the rest of it is laid out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskHeader {
+    /// The on disk format version
+    pub version: u32,
+    /// The byte length of every sector
+    pub sector_length: u32,
+    /// The checksum kept for every sector after the header
+    pub checksum: ChecksumKind,
+    /// The encryption of every sector after the header
+    pub encryption: EncryptionKind,
+    /// Tells whether a key is the one the disk is encrypted with, zeroed if it isn't encrypted
+    pub key_check: Seal,
+    /// When the disk was initialized, in seconds since the unix epoch
+    pub created_at: u64,
+    /// The name and version of the program that initialized the disk
+    pub creator: String,
+}
+
+impl DiskHeader {
+    /// Creates the header for a disk being initialized now.
+    /// # Arguments
+    /// * `sector_length` - The byte length of the disk's sectors.
+    /// * `checksum` - The checksum to keep for the disk's sectors.
+    pub fn new(sector_length: u64, checksum: ChecksumKind) -> Self {
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        DiskHeader {
+            version: FORMAT_VERSION,
+            sector_length: sector_length as u32,
+            checksum,
+            encryption: EncryptionKind::None,
+            key_check: [0; EncryptionKind::SEAL_LEN as usize],
+            created_at,
+            creator: format!("crane {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    /// Reads and validates the header at the start of a storage.
+    /// # Arguments
+    /// * `storage` - The storage holding the disk.
+    pub fn read_from(storage: &mut dyn Storage) -> Result<Self, FSError> {
+        Ok(Self::parse(storage.read_at(0, (HEADER_LEN + 4) as u64)?)?)
+    }
+
+    /// Parses and validates the header at the start of a disk.
+    /// # Arguments
+    /// * `bytes` - The bytes of the header sectors.
+    pub fn parse(bytes: Vec<u8>) -> Result<Self, HeaderError> {
+        if bytes.len() < 12 || u64::from_be_bytes(bytes[..8].try_into().unwrap()) != DISK_MAGIC {
+            return Err(HeaderError::NotCraneDisk);
+        }
+        let version = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(HeaderError::UnsupportedVersion(version));
+        }
+
+        let len = HEADER_LEN;
+        if bytes.len() < len + 4 {
+            return Err(HeaderError::NotCraneDisk);
+        }
+        if u32::from_be_bytes(bytes[len..(len + 4)].try_into().unwrap()) != crc32fast::hash(&bytes[..len]) {
+            return Err(HeaderError::Corrupt);
+        }
+
+        let mut buffer = Buffer::borrowed(&bytes[12..len]);
+        let truncated = |_| HeaderError::Corrupt;
+        let sector_length = buffer.read_u32().map_err(truncated)?;
+        let id = buffer.read_u32().map_err(truncated)?;
+        let checksum = ChecksumKind::from_id(id).ok_or(HeaderError::UnknownChecksum(id))?;
+        let created_at = buffer.read_u64().map_err(truncated)?;
+        let creator = buffer.read_bytes(CREATOR_LEN as u64).map_err(truncated)?;
+        let creator = String::from_utf8_lossy(creator).trim_end_matches('\0').to_string();
+        let id = buffer.read_u32().map_err(truncated)?;
+        let encryption = EncryptionKind::from_id(id).ok_or(HeaderError::UnknownEncryption(id))?;
+        let mut key_check = [0; EncryptionKind::SEAL_LEN as usize];
+        key_check.copy_from_slice(buffer.read_bytes(EncryptionKind::SEAL_LEN).map_err(truncated)?);
+
+        if !is_valid_sector_length(sector_length as u64) {
+            return Err(HeaderError::InvalidSectorLength(sector_length));
+        }
+
+        Ok(DiskHeader {
+            version,
+            sector_length,
+            checksum,
+            encryption,
+            key_check,
+            created_at,
+            creator,
+        })
+    }
+
+    /// Produces the bytes of the header followed by their checksum.
+    pub fn produce_bytes(&self) -> Vec<u8> {
+        let mut creator = self.creator.as_bytes().to_vec();
+        creator.resize(CREATOR_LEN, 0);
+
+        let mut bytes = DISK_MAGIC.to_be_bytes().to_vec();
+        bytes.append(&mut self.version.to_be_bytes().to_vec());
+        bytes.append(&mut self.sector_length.to_be_bytes().to_vec());
+        bytes.append(&mut self.checksum.id().to_be_bytes().to_vec());
+        bytes.append(&mut self.created_at.to_be_bytes().to_vec());
+        bytes.append(&mut creator);
+        bytes.append(&mut self.encryption.id().to_be_bytes().to_vec());
+        bytes.extend_from_slice(&self.key_check);
+        bytes.append(&mut crc32fast::hash(&bytes).to_be_bytes().to_vec());
+
+        bytes
+    }
+}
+
+impl Default for DiskHeader {
+    fn default() -> Self {
+        Self::new(SECTOR_LENGTH as u64, ChecksumKind::None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = DiskHeader::new(4096, ChecksumKind::XxHash32);
+        let parsed = DiskHeader::parse(header.produce_bytes()).unwrap();
+
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.version, FORMAT_VERSION);
+        assert!(parsed.creator.starts_with("crane"));
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        assert_eq!(DiskHeader::parse(vec![]), Err(HeaderError::NotCraneDisk));
+        assert_eq!(DiskHeader::parse(vec![7u8; SECTOR_LENGTH]), Err(HeaderError::NotCraneDisk));
+
+        let mut bytes = DiskHeader::default().produce_bytes();
+        bytes[20] ^= 1;
+        assert_eq!(DiskHeader::parse(bytes), Err(HeaderError::Corrupt));
+
+        let mut header = DiskHeader::new(SECTOR_LENGTH as u64, ChecksumKind::Crc32);
+        header.version = FORMAT_VERSION + 1;
+        assert_eq!(DiskHeader::parse(header.produce_bytes()), Err(HeaderError::UnsupportedVersion(FORMAT_VERSION + 1)));
+
+        header.version = FORMAT_VERSION;
+        header.sector_length = 300;
+        assert_eq!(DiskHeader::parse(header.produce_bytes()), Err(HeaderError::InvalidSectorLength(300)));
+    }
+
+    #[test]
+    fn test_encryption_header() {
+        let mut header = DiskHeader::new(512, ChecksumKind::None);
+        header.encryption = EncryptionKind::Aes256Gcm;
+        header.key_check = [3; EncryptionKind::SEAL_LEN as usize];
+        assert_eq!(DiskHeader::parse(header.produce_bytes()), Ok(header));
+
+        let mut bytes = DiskHeader::default().produce_bytes();
+        bytes[HEADER_LEN - EncryptionKind::SEAL_LEN as usize - 1] = 9;
+        let len = bytes.len() - 4;
+        let checksum = crc32fast::hash(&bytes[..len]).to_be_bytes();
+        bytes[len..].copy_from_slice(&checksum);
+        assert_eq!(DiskHeader::parse(bytes), Err(HeaderError::UnknownEncryption(9)));
+    }
+}
@@ -0,0 +1,86 @@
+use std::cmp::min;
+
+use super::{FSError, storage::Storage};
+
+/// A storage that keeps the whole disk image in memory
+#[derive(Default)]
+pub struct MemoryStorage {
+    bytes: Vec<u8>,
+}
+
+impl MemoryStorage {
+    /// Creates an empty storage.
+    pub fn new() -> Self {
+        MemoryStorage {
+            bytes: vec![],
+        }
+    }
+
+    /// Creates a storage from an existing disk image.
+    /// # Arguments
+    /// * `bytes` - The image to start from.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        MemoryStorage {
+            bytes,
+        }
+    }
+
+    /// Returns the disk image.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        let start = min(byte as usize, self.bytes.len());
+        let end = min(start + len as usize, self.bytes.len());
+
+        Ok(self.bytes[start..end].to_vec())
+    }
+
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let start = byte as usize;
+        let end = start + bytes.len();
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+        }
+
+        self.bytes[start..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&self) -> Result<u64, FSError> {
+        Ok(self.bytes.len() as u64)
+    }
+
+    fn sync(&mut self) -> Result<(), FSError> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+        self.bytes.truncate(len as usize);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new();
+
+        storage.write_at(4, &[1, 2, 3]).unwrap();
+
+        assert_eq!(storage.len().unwrap(), 7);
+        assert_eq!(storage.read_at(0, 7).unwrap(), vec![0, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(storage.read_at(5, 10).unwrap(), vec![2, 3]);
+        assert_eq!(storage.read_at(10, 10).unwrap(), Vec::<u8>::new());
+
+        storage.truncate(5).unwrap();
+        storage.truncate(6).unwrap();
+        assert_eq!(storage.bytes(), &[0, 0, 0, 0, 1]);
+    }
+}
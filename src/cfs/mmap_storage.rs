@@ -0,0 +1,130 @@
+use std::{cmp::min, fs::File};
+
+use memmap2::MmapMut;
+
+use super::{FSError, storage::Storage};
+
+/// A storage backed by a memory mapped file, reads and writes are copies to and from the map
+/// instead of a seek and a syscall. The file is remapped whenever it grows or shrinks.
+pub struct MmapStorage {
+    file: File,
+    /// The map over the whole file, none while the file is empty since empty maps aren't portable
+    map: Option<MmapMut>,
+}
+
+impl MmapStorage {
+    /// Creates a storage mapping a file, the file must be opened for both reading and writing.
+    /// # Arguments
+    /// * `file` - The file to map.
+    pub fn new(file: File) -> Result<Self, FSError> {
+        let mut storage = MmapStorage {
+            file,
+            map: None,
+        };
+        storage.remap()?;
+        Ok(storage)
+    }
+
+    /// Maps the file again after its length changed.
+    fn remap(&mut self) -> Result<(), FSError> {
+        if let Some(map) = self.map.take() {
+            map.flush()?;
+        }
+
+        if self.file.metadata()?.len() > 0 {
+            // Safety: the map is only valid while nothing else truncates the file, the disk
+            // owns the file for as long as the storage is open
+            self.map = Some(unsafe { MmapMut::map_mut(&self.file)? });
+        }
+        Ok(())
+    }
+
+    /// Sets the length of the file and maps it again.
+    /// # Arguments
+    /// * `len` - The new byte length of the file.
+    fn resize(&mut self, len: u64) -> Result<(), FSError> {
+        if let Some(map) = self.map.take() {
+            map.flush()?;
+        }
+        self.file.set_len(len)?;
+        self.remap()
+    }
+
+    fn mapped(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or(&[])
+    }
+}
+
+impl Storage for MmapStorage {
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        let bytes = self.mapped();
+        let start = min(byte as usize, bytes.len());
+        let end = min(start + len as usize, bytes.len());
+
+        Ok(bytes[start..end].to_vec())
+    }
+
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let end = byte + bytes.len() as u64;
+        if end > self.len()? {
+            self.resize(end)?;
+        }
+
+        if let Some(map) = self.map.as_mut() {
+            map[(byte as usize)..(end as usize)].copy_from_slice(bytes);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> Result<u64, FSError> {
+        Ok(self.mapped().len() as u64)
+    }
+
+    fn sync(&mut self) -> Result<(), FSError> {
+        if let Some(map) = self.map.as_ref() {
+            map.flush()?;
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+        if len < self.len()? {
+            self.resize(len)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::OpenOptions;
+
+    use super::*;
+
+    fn open(path: &str) -> File {
+        OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path).unwrap()
+    }
+
+    #[test]
+    fn test_mmap_storage() {
+        let path = "./test/disk/mmap_storage.db";
+        let mut storage = MmapStorage::new(open(path)).unwrap();
+
+        assert_eq!(storage.len().unwrap(), 0);
+        assert_eq!(storage.read_at(0, 4).unwrap(), Vec::<u8>::new());
+
+        storage.write_at(4, &[1, 2, 3]).unwrap();
+        assert_eq!(storage.len().unwrap(), 7);
+        assert_eq!(storage.read_at(0, 7).unwrap(), vec![0, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(storage.read_at(5, 10).unwrap(), vec![2, 3]);
+
+        storage.write_at(9, &[4]).unwrap();
+        assert_eq!(storage.read_at(4, 6).unwrap(), vec![1, 2, 3, 0, 0, 4]);
+
+        storage.truncate(5).unwrap();
+        assert_eq!(storage.read_at(0, 10).unwrap(), vec![0, 0, 0, 0, 1]);
+
+        storage.sync().unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), vec![0, 0, 0, 0, 1]);
+    }
+}
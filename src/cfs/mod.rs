@@ -7,16 +7,42 @@ mod root_partition;
 mod buffer;
 mod crane_disk;
 mod schema;
+mod datetime;
+mod blob;
+mod storage;
+mod file_storage;
+mod memory_storage;
+mod mmap_storage;
+mod page_cache;
+mod wal;
+mod wal_reader;
+mod wal_writer;
+mod header;
+mod error;
+mod checksum;
+mod checksum_storage;
+mod encryption;
+mod encrypted_storage;
+mod bounds;
+mod partition_kind;
 
-#[derive(Debug)]
-pub struct FSError {
-
-}
-
+pub use error::FSError;
 pub use writer::Writer;
-pub use crane_writer::*;
 pub use reader::Reader;
-pub use crane_disk::CraneDisk;
+pub use crane_disk::{CraneDisk, DiskOptions, Durability, OpenOptions};
 pub use crane_partition::CranePartition;
 pub use schema::*;
-pub use buffer::Buffer;
\ No newline at end of file
+pub use blob::{Blob, BlobRef, BlobReader, BlobWriter, MAX_INLINE_BLOB_LEN};
+pub use datetime::{Date, Time, Timestamp, Interval, ParseDateTimeError, MAX_OFFSET_MINUTES};
+pub use buffer::Buffer;
+pub use wal::max_row_len;
+pub use storage::Storage;
+pub use file_storage::FileStorage;
+pub use memory_storage::MemoryStorage;
+pub use mmap_storage::MmapStorage;
+pub use page_cache::CacheStats;
+pub use checksum::ChecksumKind;
+pub use encryption::{EncryptionKey, EncryptionKind};
+pub use root_partition::FREE_PARTITION_TYPE;
+pub use partition_kind::{PartitionKind, PartitionType, MAX_PARTITION_OWNER};
+pub use header::{DiskHeader, HeaderError, FORMAT_VERSION, MIN_SECTOR_LENGTH, MAX_SECTOR_LENGTH};
\ No newline at end of file
@@ -0,0 +1,319 @@
+use std::{cmp::{max, min}, collections::{BTreeMap, HashMap}, sync::{Arc, RwLock}};
+
+use super::{FSError, storage::Storage};
+
+/// The number of sectors a disk caches unless told otherwise
+pub const DEFAULT_CACHE_SECTORS: usize = 1024;
+
+/// Counters describing how well the cache has been doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Sector reads and writes served from the cache
+    pub hits: u64,
+    /// Sector reads and writes that had to load the sector from the storage
+    pub misses: u64,
+    /// Sectors dropped to make room for others
+    pub evictions: u64,
+    /// Dirty sectors written back to the storage
+    pub writebacks: u64,
+}
+
+struct Page {
+    bytes: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// A buffer pool of sectors kept in front of a storage.
+///
+/// Sectors are evicted least recently used first, dirty sectors are only written back
+/// when evicted or flushed.
+pub struct PageCache {
+    inner: Arc<RwLock<dyn Storage>>,
+    sector_length: u64,
+    capacity: usize,
+    pages: HashMap<u64, Page>,
+    /// The sector used at each tick, the first entry is the least recently used
+    usage: BTreeMap<u64, u64>,
+    tick: u64,
+    len: u64,
+    stats: CacheStats,
+}
+
+impl PageCache {
+    /// Creates a cache in front of a storage.
+    /// # Arguments
+    /// * `inner` - The storage to cache.
+    /// * `sector_length` - The byte length of the cached sectors.
+    /// * `capacity` - The maximum number of sectors to keep.
+    pub fn new(inner: Arc<RwLock<dyn Storage>>, sector_length: u64, capacity: usize) -> Result<Self, FSError> {
+        let len = inner.read().unwrap().len()?;
+
+        Ok(PageCache {
+            inner,
+            sector_length,
+            capacity,
+            pages: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+            len,
+            stats: CacheStats::default(),
+        })
+    }
+
+    /// Changes the maximum number of cached sectors, evicting sectors if there are too many.
+    pub fn set_capacity(&mut self, capacity: usize) -> Result<(), FSError> {
+        self.capacity = capacity;
+        self.evict()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Gets a sector into the cache and marks it as the most recently used.
+    fn page(&mut self, sector: u64, overwrite: bool) -> Result<&mut Page, FSError> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some(page) = self.pages.get_mut(&sector) {
+            self.stats.hits += 1;
+            self.usage.remove(&page.last_used);
+        } else {
+            self.stats.misses += 1;
+            let mut bytes = if overwrite {
+                vec![]
+            } else {
+                self.inner.write().unwrap().read_at(sector*self.sector_length, self.sector_length)?
+            };
+            bytes.resize(self.sector_length as usize, 0);
+
+            self.pages.insert(sector, Page {
+                bytes,
+                dirty: false,
+                last_used: tick,
+            });
+        }
+
+        self.usage.insert(tick, sector);
+        let page = self.pages.get_mut(&sector).unwrap();
+        page.last_used = tick;
+
+        Ok(page)
+    }
+
+    fn write_back(&mut self, sector: u64) -> Result<(), FSError> {
+        let start = sector*self.sector_length;
+        let end = min(start + self.sector_length, self.len);
+
+        if let Some(page) = self.pages.get_mut(&sector) {
+            if page.dirty && end > start {
+                self.inner.write().unwrap().write_at(start, &page.bytes[..((end - start) as usize)])?;
+                self.stats.writebacks += 1;
+            }
+            page.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn evict(&mut self) -> Result<(), FSError> {
+        while self.pages.len() > self.capacity {
+            let (tick, sector) = match self.usage.iter().next() {
+                Some((t, s)) => (*t, *s),
+                None => break,
+            };
+
+            self.write_back(sector)?;
+            self.usage.remove(&tick);
+            self.pages.remove(&sector);
+            self.stats.evictions += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for PageCache {
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        let end = min(byte + len, self.len);
+        let sl = self.sector_length;
+        let mut buffer = Vec::with_capacity(end.saturating_sub(byte) as usize);
+
+        let mut curr = byte;
+        while curr < end {
+            let sector = curr / sl;
+            let s = (curr % sl) as usize;
+            let e = (min(end, (sector + 1)*sl) - sector*sl) as usize;
+
+            let page = self.page(sector, false)?;
+            buffer.extend_from_slice(&page.bytes[s..e]);
+            curr = sector*sl + e as u64;
+        }
+
+        self.evict()?;
+        Ok(buffer)
+    }
+
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let end = byte + bytes.len() as u64;
+        let sl = self.sector_length;
+        let old_len = self.len;
+
+        let mut curr = byte;
+        while curr < end {
+            let sector = curr / sl;
+            let s = (curr % sl) as usize;
+            let e = (min(end, (sector + 1)*sl) - sector*sl) as usize;
+            let from = (curr - byte) as usize;
+
+            // A sector that is fully overwritten, or lies past the end of the storage, doesn't need loading
+            let overwrite = (s == 0 && e as u64 == sl) || sector*sl >= old_len;
+            let page = self.page(sector, overwrite)?;
+            page.bytes[s..e].copy_from_slice(&bytes[from..(from + e - s)]);
+            page.dirty = true;
+
+            curr = sector*sl + e as u64;
+        }
+
+        self.len = max(self.len, end);
+        self.evict()
+    }
+
+    fn len(&self) -> Result<u64, FSError> {
+        Ok(self.len)
+    }
+
+    /// Writes every dirty sector back to the storage.
+    fn flush(&mut self) -> Result<(), FSError> {
+        let mut dirty: Vec<u64> = self.pages.iter()
+            .filter(|(_, p)| p.dirty)
+            .map(|(s, _)| *s)
+            .collect();
+        dirty.sort_unstable();
+
+        for sector in dirty {
+            self.write_back(sector)?;
+        }
+        self.inner.write().unwrap().flush()
+    }
+
+    fn sync(&mut self) -> Result<(), FSError> {
+        self.flush()?;
+        self.inner.write().unwrap().sync()
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+        if len >= self.len {
+            return Ok(());
+        }
+        let sl = self.sector_length;
+
+        let dropped: Vec<u64> = self.pages.keys().filter(|s| **s*sl >= len).copied().collect();
+        for sector in dropped {
+            if let Some(page) = self.pages.remove(&sector) {
+                self.usage.remove(&page.last_used);
+            }
+        }
+        // The cut off end of the last sector reads as zeros if the storage grows again
+        if let Some(page) = self.pages.get_mut(&(len/sl)) {
+            page.bytes[((len % sl) as usize)..].iter_mut().for_each(|b| *b = 0);
+        }
+
+        self.len = len;
+        self.inner.write().unwrap().truncate(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{SECTOR_LENGTH, cfs::memory_storage::MemoryStorage};
+
+    use super::*;
+
+    fn create_cache(capacity: usize) -> (Arc<RwLock<MemoryStorage>>, PageCache) {
+        let storage = Arc::new(RwLock::new(MemoryStorage::from_bytes(vec![0u8; SECTOR_LENGTH*8])));
+        let cache = PageCache::new(storage.clone(), SECTOR_LENGTH as u64, capacity).unwrap();
+
+        (storage, cache)
+    }
+
+    #[test]
+    fn test_cache_hits() {
+        let (_, mut cache) = create_cache(4);
+
+        cache.read_at(0, 16).unwrap();
+        cache.read_at(8, 16).unwrap();
+        cache.read_at(SECTOR_LENGTH as u64 - 4, 8).unwrap();
+
+        assert_eq!(cache.stats(), CacheStats {
+            hits: 2,
+            misses: 2,
+            evictions: 0,
+            writebacks: 0,
+        });
+    }
+
+    #[test]
+    fn test_dirty_pages_flushed() {
+        let (storage, mut cache) = create_cache(4);
+
+        cache.write_at(10, &[1, 2, 3]).unwrap();
+
+        assert_eq!(cache.read_at(10, 3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(storage.read().unwrap().bytes()[10], 0);
+
+        cache.flush().unwrap();
+
+        assert_eq!(&storage.read().unwrap().bytes()[10..13], &[1, 2, 3]);
+        assert_eq!(cache.stats().writebacks, 1);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let (storage, mut cache) = create_cache(2);
+        let sl = SECTOR_LENGTH as u64;
+
+        cache.write_at(0, &[5]).unwrap();
+        cache.read_at(sl, 1).unwrap();
+        cache.read_at(0, 1).unwrap();
+        cache.read_at(2*sl, 1).unwrap();
+
+        // Sector 1 was the least recently used, so sector 0 is still cached and dirty
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(storage.read().unwrap().bytes()[0], 0);
+
+        cache.read_at(3*sl, 1).unwrap();
+        cache.read_at(4*sl, 1).unwrap();
+
+        assert_eq!(storage.read().unwrap().bytes()[0], 5);
+    }
+
+    #[test]
+    fn test_write_past_end() {
+        let (storage, mut cache) = create_cache(4);
+        let end = (SECTOR_LENGTH*8) as u64;
+
+        cache.write_at(end, &[1, 2]).unwrap();
+
+        assert_eq!(cache.len().unwrap(), end + 2);
+        assert_eq!(cache.read_at(end, 8).unwrap(), vec![1, 2]);
+
+        cache.sync().unwrap();
+
+        assert_eq!(storage.read().unwrap().len().unwrap(), end + 2);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let (storage, mut cache) = create_cache(4);
+        let sl = SECTOR_LENGTH as u64;
+
+        cache.write_at(0, &vec![7u8; (sl*3) as usize]).unwrap();
+        cache.truncate(sl + 10).unwrap();
+
+        assert_eq!(cache.len().unwrap(), sl + 10);
+        assert_eq!(storage.read().unwrap().len().unwrap(), sl + 10);
+
+        cache.write_at(sl*2, &[1]).unwrap();
+        assert_eq!(cache.read_at(sl + 8, 4).unwrap(), vec![7, 7, 0, 0]);
+    }
+}
@@ -0,0 +1,127 @@
+use super::root_partition::FREE_PARTITION_TYPE;
+
+/// How far the kind of a partition is shifted into its type, the bits below hold its owner
+const KIND_SHIFT: u32 = 48;
+/// The largest owner a partition type can hold
+pub const MAX_PARTITION_OWNER: u64 = (1 << KIND_SHIFT) - 1;
+
+/// The role a partition plays on a disk.
+///
+/// A partition's type keeps its kind in the top 16 bits and the id of what owns it, like the slot
+/// of a schema, in the rest. Kinds this version of crane doesn't know about are kept as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PartitionKind {
+    /// No kind was given, the whole type is up to whoever made the partition
+    Untyped,
+    /// The name and column types of a schema
+    Schema,
+    /// The tree mapping a schema's keys to where their values are
+    Index,
+    /// The values of a schema
+    Data,
+    /// A write ahead log
+    Wal,
+    /// A list of free sectors
+    FreeList,
+    /// An index over a column of a schema other than its key
+    SecondaryIndex,
+    /// The bytes of a single blob of a schema too long to keep in its row
+    Overflow,
+    /// A released partition whose sectors are free to be reused
+    Free,
+    /// A kind added by a newer version of crane
+    Other(u16),
+}
+
+impl PartitionKind {
+    /// The id the kind is stored as.
+    pub fn id(&self) -> u16 {
+        match self {
+            Self::Untyped => 0,
+            Self::Schema => 1,
+            Self::Index => 2,
+            Self::Data => 3,
+            Self::Wal => 4,
+            Self::FreeList => 5,
+            Self::SecondaryIndex => 6,
+            Self::Overflow => 7,
+            Self::Free => u16::MAX,
+            Self::Other(id) => *id,
+        }
+    }
+
+    /// Gets the kind stored as an id.
+    /// # Arguments
+    /// * `id` - The id of the kind.
+    pub fn from_id(id: u16) -> Self {
+        match id {
+            0 => Self::Untyped,
+            1 => Self::Schema,
+            2 => Self::Index,
+            3 => Self::Data,
+            4 => Self::Wal,
+            5 => Self::FreeList,
+            6 => Self::SecondaryIndex,
+            7 => Self::Overflow,
+            u16::MAX => Self::Free,
+            id => Self::Other(id),
+        }
+    }
+}
+
+/// The kind of a partition together with what owns it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PartitionType {
+    pub kind: PartitionKind,
+    pub owner: u64,
+}
+
+impl PartitionType {
+    /// Creates a partition type.
+    /// # Arguments
+    /// * `kind` - The role of the partition.
+    /// * `owner` - What owns the partition, at most `MAX_PARTITION_OWNER`.
+    pub fn new(kind: PartitionKind, owner: u64) -> Self {
+        PartitionType {
+            kind,
+            owner: owner & MAX_PARTITION_OWNER,
+        }
+    }
+
+    /// Parses the type stored in a partition's entry.
+    /// # Arguments
+    /// * `raw` - The stored type.
+    pub fn from_raw(raw: u64) -> Self {
+        if raw == FREE_PARTITION_TYPE {
+            return Self::new(PartitionKind::Free, 0);
+        }
+        Self::new(PartitionKind::from_id((raw >> KIND_SHIFT) as u16), raw)
+    }
+
+    /// The type as it is stored in a partition's entry.
+    pub fn raw(&self) -> u64 {
+        match self.kind {
+            PartitionKind::Free => FREE_PARTITION_TYPE,
+            kind => ((kind.id() as u64) << KIND_SHIFT) | self.owner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_partition_types() {
+        let kinds = [PartitionKind::Untyped, PartitionKind::Schema, PartitionKind::Index, PartitionKind::Data,
+            PartitionKind::Wal, PartitionKind::FreeList, PartitionKind::SecondaryIndex, PartitionKind::Overflow, PartitionKind::Other(77)];
+        for kind in kinds.iter() {
+            let t = PartitionType::new(*kind, 42);
+            assert_eq!(PartitionType::from_raw(t.raw()), t);
+        }
+
+        assert_eq!(PartitionType::from_raw(FREE_PARTITION_TYPE).kind, PartitionKind::Free);
+        assert_eq!(PartitionType::new(PartitionKind::Free, 0).raw(), FREE_PARTITION_TYPE);
+        assert_eq!(PartitionType::from_raw(299), PartitionType::new(PartitionKind::Untyped, 299));
+    }
+}
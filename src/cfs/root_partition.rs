@@ -1,9 +1,9 @@
-use std::{convert::TryInto, vec};
+use std::{collections::HashSet, vec};
 use lazy_static::lazy_static;
 
 use crate::{cfs::{buffer::Buffer, reader::Reader, schema::DataValue}};
 
-use super::{crane_partition::CranePartition, schema::CraneSchema, writer::Writer};
+use super::{FSError, crane_partition::CranePartition, schema::CraneSchema, writer::Writer};
 
 lazy_static! {
     static ref PARTITION_SCHEMA: CraneSchema = CraneSchema::new(vec![
@@ -14,10 +14,26 @@ lazy_static! {
     ]);
 }
 
+/// The type given to released partitions, whose sectors are free to be reused
+pub const FREE_PARTITION_TYPE: u64 = u64::MAX;
+/// The type of map entries continuing the partition before them in another extent,
+/// their third field holds the id of that partition instead of an initialized length
+pub const EXTENT_ENTRY_TYPE: u64 = u64::MAX - 1;
 
+/// The number of bytes at the start of each root block linking it to the next one
+const LINK_LEN: u64 = 32;
+
+/// The partition map of a disk.
+///
+/// The map starts in a fixed head block and continues in a chain of blocks, each block
+/// starts with a link holding the next block's start and length and how many entries
+/// the block holds, followed by the entries themselves. A partition spread over several
+/// extents has an entry for its first extent followed by an extent entry for every other.
 pub struct RootPartition {
     /// The underlying partition for the disk metadata
     partition: CranePartition,
+    /// The blocks the map continues in after the head
+    chain: Vec<CranePartition>,
     /// The sector starts of each of the partitions
     pub partition_starts: Vec<u64>,
     /// Sector ends of each of the partitions
@@ -26,24 +42,23 @@ pub struct RootPartition {
     pub init_lens: Vec<u64>,
     /// The type of each partition
     pub partition_types: Vec<u64>,
+    /// The start and end sectors of the extents each partition continues in after its first
+    pub extra_extents: Vec<Vec<(u64, u64)>>,
+    /// The bytes last read from or written to each block, so only blocks that changed are rewritten
+    written: Vec<Vec<u8>>,
 }
 
 impl RootPartition {
     /// Loads root partition data from the given partition
     /// # Arguments
     /// * `partition` - The partition to load the root partition from
-    pub fn import_from(partition: CranePartition) -> Self {
-        let mut root = RootPartition {
-            partition,
-            partition_starts: vec![],
-            partition_ends: vec![],
-            init_lens: vec![],
-            partition_types: vec![],
-        };
+    /// * `open_block` - Opens a chained block given its start and length in sectors
+    pub fn import_from(partition: CranePartition, open_block: impl FnMut(u64, u64) -> Result<CranePartition, FSError>) -> Result<Self, FSError> {
+        let mut root = Self::new(partition);
 
-        root.read();
+        root.read(open_block)?;
 
-        root
+        Ok(root)
     }
 
     /// Initializes a root partition in the given partition
@@ -52,78 +67,205 @@ impl RootPartition {
     pub fn new(partition: CranePartition) -> Self {
         RootPartition {
             partition,
+            chain: vec![],
             partition_starts: vec![],
             partition_ends: vec![],
             init_lens: vec![],
             partition_types: vec![],
+            extra_extents: vec![],
+            written: vec![],
         }
     }
 
-    /// Helper method to caclulate the sector lengths of each partition respectively
-    pub fn compute_lens(&self) -> Vec<u64> {
-        self.partition_starts.iter().zip(self.partition_ends.iter()).map(|(s, e)| *e-*s).collect()
+    /// The start sector and sector length of every extent of a partition
+    /// # Arguments
+    /// * `i` - The index of the partition in the map.
+    pub fn extents(&self, i: usize) -> Vec<(u64, u64)> {
+        let first = (self.partition_starts[i], self.partition_ends[i]);
+        let extra = self.extra_extents.get(i).map(|e| &e[..]).unwrap_or(&[]);
+
+        std::iter::once(&first).chain(extra.iter()).map(|(s, e)| (*s, e - s)).collect()
+    }
+
+    /// The number of entries the map needs for its partitions
+    pub fn entry_count(&self) -> u64 {
+        (self.partition_starts.len() + self.extra_extents.iter().map(|e| e.len()).sum::<usize>()) as u64
+    }
+
+    /// The number of partition entries that fit in the blocks of the map
+    pub fn capacity(&self) -> u64 {
+        self.blocks().map(Self::block_capacity).sum()
+    }
+
+    /// The start and sector length of every chained block
+    pub fn chain_extents(&self) -> Vec<(u64, u64)> {
+        self.chain.iter().map(|b| (b.offset(), b.total_len())).collect()
+    }
+
+    /// Adds a block to the end of the chain
+    /// # Arguments
+    /// * `block` - The partition for the block, it should be empty
+    pub fn extend(&mut self, block: CranePartition) {
+        self.chain.push(block);
     }
 
     /// Reads the partition to get root data
-    pub fn read(&mut self) {
+    /// # Arguments
+    /// * `open_block` - Opens a chained block given its start and length in sectors
+    pub fn read(&mut self, mut open_block: impl FnMut(u64, u64) -> Result<CranePartition, FSError>) -> Result<(), FSError> {
         let mut new_starts: Vec<u64> = vec![];
         let mut new_ends: Vec<u64> = vec![];
         let mut init_lens: Vec<u64> = vec![];
         let mut partition_types: Vec<u64> = vec![];
-        let mut bytes = Buffer::new(self.partition.read_sectors(0, 12).expect("Couldn't read partition map"));
-
-        while !bytes.empty() {
-            let values = PARTITION_SCHEMA.parse_bytes(&mut bytes);
-            
-            if let (DataValue::UInt64(start), DataValue::UInt64(end), DataValue::UInt64(init_len), DataValue::UInt64(p_type)) 
-                = (&values[0], &values[1], &values[2], &values[3]) {
-                if *start == 0 && *end == 0 {
-                    break;
+        let mut extra_extents: Vec<Vec<(u64, u64)>> = vec![];
+        let mut written: Vec<Vec<u8>> = vec![];
+        self.chain.clear();
+        let mut seen = HashSet::new();
+
+        let mut block_index = 0;
+        loop {
+            let block = if block_index == 0 { &mut self.partition } else { &mut self.chain[block_index - 1] };
+            let len = block.total_len();
+            let capacity = Self::block_capacity(block);
+            let raw = block.read_sectors(0, len)?;
+            let mut bytes = Buffer::new(raw.clone());
+            if bytes.is_empty() {
+                break;
+            }
+
+            let link = Self::parse_entry(&mut bytes)?;
+            if link[2] > capacity {
+                return Err(FSError::CorruptMetadata(format!("partition map block {} claims {} entries but has room for {}", block_index, link[2], capacity)));
+            }
+            written.push(raw[..((LINK_LEN + link[2]*Self::entry_len()) as usize)].to_vec());
+            for _ in 0..link[2] {
+                let values = Self::parse_entry(&mut bytes)?;
+
+                if values[3] == EXTENT_ENTRY_TYPE {
+                    match extra_extents.last_mut() {
+                        Some(extents) if values[2] == new_starts.len() as u64 => extents.push((values[0], values[1])),
+                        _ => return Err(FSError::CorruptMetadata(format!("extent entry for partition {} is out of place", values[2]))),
+                    }
+                    continue;
                 }
-                new_starts.push(*start);
-                new_ends.push(*end);
-                init_lens.push(*init_len);
-                partition_types.push(*p_type);
+
+                new_starts.push(values[0]);
+                new_ends.push(values[1]);
+                init_lens.push(values[2]);
+                partition_types.push(values[3]);
+                extra_extents.push(vec![]);
             }
-       }
 
+            if link[0] == 0 {
+                break;
+            }
+            if !seen.insert(link[0]) {
+                return Err(FSError::CorruptMetadata(format!("partition map loops back to sector {}", link[0])));
+            }
+            self.chain.push(open_block(link[0], link[1])?);
+            block_index += 1;
+        }
 
         self.partition_starts = new_starts;
         self.partition_ends = new_ends;
         self.init_lens = init_lens;
         self.partition_types = partition_types;
+        self.extra_extents = extra_extents;
+        self.written = written;
+
+        Ok(())
     }
 
-    /// Write root data to the partition
-    pub fn write(&mut self) {
+    /// Write root data to the partition, skipping the blocks whose bytes haven't changed since
+    /// they were last read or written so that a large map doesn't fill the write ahead log
+    pub fn write(&mut self) -> Result<(), FSError> {
         assert_eq!(self.partition_starts.len(), self.partition_ends.len());
         assert_eq!(self.partition_starts.len(), self.init_lens.len());
         assert_eq!(self.partition_starts.len(), self.partition_types.len());
-        let len = PARTITION_SCHEMA.len();
-
-        for i in 0..(self.partition_starts.len()) {
-            let bytes = PARTITION_SCHEMA.produce_bytes(&vec![
-                DataValue::UInt64(self.partition_starts[i]),
-                DataValue::UInt64(self.partition_ends[i]),
-                DataValue::UInt64(self.init_lens[i]),
-                DataValue::UInt64(self.partition_types[i]),
-            ]);
-            self.partition.write_sectors(0, len*(i as u64), &bytes).unwrap();
+        self.extra_extents.resize(self.partition_starts.len(), vec![]);
+        let total = self.entry_count();
+
+        if total > self.capacity() {
+            return Err(FSError::CorruptMetadata(format!("{} partition entries don't fit in a map with room for {}", total, self.capacity())));
+        }
+
+        let mut entries = Vec::with_capacity(total as usize);
+        for j in 0..self.partition_starts.len() {
+            entries.push([self.partition_starts[j], self.partition_ends[j], self.init_lens[j], self.partition_types[j]]);
+            for (s, e) in self.extra_extents[j].iter() {
+                entries.push([*s, *e, (j + 1) as u64, EXTENT_ENTRY_TYPE]);
+            }
         }
+
+        let len = Self::entry_len();
+        let links: Vec<(u64, u64)> = self.chain_extents();
+        let mut i = 0u64;
+
+        for (b, block) in std::iter::once(&mut self.partition).chain(self.chain.iter_mut()).enumerate() {
+            let count = u64::min(Self::block_capacity(block), total - i);
+            let (next_start, next_len) = links.get(b).copied().unwrap_or((0, 0));
+
+            let mut bytes = Self::produce_entry([next_start, next_len, count, 0]);
+            for entry in entries[(i as usize)..((i + count) as usize)].iter() {
+                bytes.append(&mut Self::produce_entry(*entry));
+            }
+            debug_assert_eq!(bytes.len() as u64, LINK_LEN + count*len);
+            i += count;
+
+            if self.written.get(b) == Some(&bytes) {
+                continue;
+            }
+            block.write_sectors(0, 0, &bytes)?;
+            if self.written.len() <= b {
+                self.written.resize(b + 1, vec![]);
+            }
+            self.written[b] = bytes;
+        }
+
+        Ok(())
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = &CranePartition> {
+        std::iter::once(&self.partition).chain(self.chain.iter())
+    }
+
+    /// The byte length of an entry of the map
+    fn entry_len() -> u64 {
+        PARTITION_SCHEMA.fixed_len().expect("Partition entries only have fixed length columns")
+    }
+
+    fn block_capacity(block: &CranePartition) -> u64 {
+        block.total_bytes().saturating_sub(LINK_LEN)/Self::entry_len()
+    }
+
+    fn parse_entry(bytes: &mut Buffer) -> Result<[u64; 4], FSError> {
+        let values = PARTITION_SCHEMA.parse_bytes(bytes)?;
+        let mut entry = [0u64; 4];
+
+        for (i, v) in values.iter().enumerate() {
+            if let DataValue::UInt64(v) = v {
+                entry[i] = *v;
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn produce_entry(entry: [u64; 4]) -> Vec<u8> {
+        PARTITION_SCHEMA.produce_bytes(&entry.iter().map(|v| DataValue::UInt64(*v)).collect::<Vec<_>>())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{cell::RefCell, fs::File, rc::Rc};
+    use std::sync::{Arc, RwLock};
 
-    use super::*;
+    use crate::{SECTOR_LENGTH, cfs::{memory_storage::MemoryStorage, storage::Storage}};
 
-    #[test]
-    fn test_partition_write() {
-        let file = Rc::new(RefCell::new(File::create("./test/partitions/write.db").unwrap()));
+    use super::*;
 
-        let partition = CranePartition::new(1, 0, 24, 0, Rc::downgrade(&file), Rc::downgrade(&file));
+    fn write_partitions(storage: &Arc<RwLock<dyn Storage>>) {
+        let partition = CranePartition::new(1, 0, 24, 0, SECTOR_LENGTH as u64, Arc::downgrade(storage));
 
         let mut root_partition = RootPartition::new(partition);
 
@@ -132,20 +274,80 @@ mod tests {
         root_partition.init_lens.append(&mut vec![40, 100, 18]);
         root_partition.partition_types.append(&mut vec![1, 2, 4]);
 
-        root_partition.write();
+        root_partition.write().unwrap();
+    }
+
+    fn open_block(storage: &Arc<RwLock<dyn Storage>>) -> impl FnMut(u64, u64) -> Result<CranePartition, FSError> + '_ {
+        move |offset, len| Ok(CranePartition::new(0, offset, len, 0, SECTOR_LENGTH as u64, Arc::downgrade(storage)))
+    }
+
+    #[test]
+    fn test_partition_write() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+
+        write_partitions(&storage);
+
+        assert_eq!(storage.read().unwrap().len().unwrap(), 128);
     }
 
     #[test]
     fn test_partition_read() {
-        let file = Rc::new(RefCell::new(File::open("./test/partitions/read.db").unwrap()));
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        write_partitions(&storage);
 
-        let partition = CranePartition::new(1, 0, 12, 0, Rc::downgrade(&file), Rc::downgrade(&file));
+        let partition = CranePartition::new(1, 0, 12, 0, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
 
-        let root_partition = RootPartition::import_from(partition);
+        let root_partition = RootPartition::import_from(partition, open_block(&storage)).unwrap();
 
         assert_eq!(root_partition.partition_starts.len(), 3);
         assert_eq!(root_partition.partition_starts, vec![20, 120, 282]);
         assert_eq!(root_partition.partition_ends, vec![119, 281, 300]);
         assert_eq!(root_partition.init_lens, vec![40, 100, 18]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_chained_partition_map() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut root_partition = RootPartition::new(CranePartition::new(0, 0, 1, 0, SECTOR_LENGTH as u64, Arc::downgrade(&storage)));
+
+        root_partition.partition_starts = (1..=10).collect();
+        root_partition.partition_ends = (2..=11).collect();
+        root_partition.init_lens = vec![0; 10];
+        root_partition.partition_types = vec![3; 10];
+
+        assert!(root_partition.write().is_err());
+
+        root_partition.extend(CranePartition::new(0, 4, 1, 0, SECTOR_LENGTH as u64, Arc::downgrade(&storage)));
+        assert_eq!(root_partition.capacity(), 14);
+        root_partition.write().unwrap();
+
+        let partition = CranePartition::new(0, 0, 1, 0, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+        let root_partition = RootPartition::import_from(partition, open_block(&storage)).unwrap();
+
+        assert_eq!(root_partition.chain_extents(), vec![(4, 1)]);
+        assert_eq!(root_partition.partition_starts, (1..=10).collect::<Vec<u64>>());
+        assert_eq!(root_partition.partition_types, vec![3; 10]);
+    }
+
+    #[test]
+    fn test_partition_extents() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut root_partition = RootPartition::new(CranePartition::new(0, 0, 1, 0, SECTOR_LENGTH as u64, Arc::downgrade(&storage)));
+
+        root_partition.partition_starts = vec![20, 30];
+        root_partition.partition_ends = vec![22, 32];
+        root_partition.init_lens = vec![10, 0];
+        root_partition.partition_types = vec![1, 2];
+        root_partition.extra_extents = vec![vec![(40, 44), (50, 51)], vec![]];
+
+        assert_eq!(root_partition.entry_count(), 4);
+        root_partition.write().unwrap();
+
+        let partition = CranePartition::new(0, 0, 1, 0, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+        let root_partition = RootPartition::import_from(partition, open_block(&storage)).unwrap();
+
+        assert_eq!(root_partition.partition_starts, vec![20, 30]);
+        assert_eq!(root_partition.extents(0), vec![(20, 2), (40, 4), (50, 1)]);
+        assert_eq!(root_partition.extents(1), vec![(30, 2)]);
+    }
+}
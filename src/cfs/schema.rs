@@ -1,6 +1,6 @@
-use std::{convert::TryInto, fmt::Debug};
+use std::{cmp::Ordering, convert::TryInto, fmt::Debug};
 
-use super::buffer::{self, Buffer};
+use super::{FSError, blob::{Blob, BlobRef}, buffer::Buffer, datetime::{Date, Time, Timestamp}};
 
 
 #[derive(Clone, PartialEq, Debug)]
@@ -13,30 +13,80 @@ pub enum DataValue {
     UInt64(u64),
     Varchar(String),
     Fixchar(String, u64),
+    Float32(f32),
+    Float64(f64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    Date(Date),
+    Time(Time),
+    Timestamp(Timestamp),
+    Blob(Blob),
 }
 
 impl DataValue {
+    /// Produces the bytes the value is stored as, exactly `len()` of them for types of a fixed length.
+    /// Fixed strings are padded with zeros up to their capacity and cut off at it.
     pub fn to_bytes(&self) -> Vec<u8> {
         match &self {
-            Self::Int16(i) => ((*i).to_be_bytes().to_vec()),
-            Self::Int32(i) => ((*i).to_be_bytes().to_vec()),
-            Self::Int64(i) => ((*i).to_be_bytes().to_vec()),
-            Self::UInt64(i) => ((*i).to_be_bytes().to_vec()),
-            Self::Varchar(s) => (s.as_bytes().to_vec()),
+            Self::Bool(b) => vec![*b as u8],
+            Self::Int8(i) => (*i).to_be_bytes().to_vec(),
+            Self::Int16(i) => (*i).to_be_bytes().to_vec(),
+            Self::Int32(i) => (*i).to_be_bytes().to_vec(),
+            Self::Int64(i) => (*i).to_be_bytes().to_vec(),
+            Self::UInt64(i) => (*i).to_be_bytes().to_vec(),
+            Self::Float32(f) => (*f).to_be_bytes().to_vec(),
+            Self::Float64(f) => (*f).to_be_bytes().to_vec(),
+            Self::UInt8(i) => (*i).to_be_bytes().to_vec(),
+            Self::UInt16(i) => (*i).to_be_bytes().to_vec(),
+            Self::UInt32(i) => (*i).to_be_bytes().to_vec(),
+            Self::Date(d) => d.days_since_epoch().to_be_bytes().to_vec(),
+            Self::Time(t) => t.micros().to_be_bytes().to_vec(),
+            Self::Timestamp(t) => {
+                let mut v = t.micros().to_be_bytes().to_vec();
+                v.extend_from_slice(&t.offset().unwrap_or(NO_OFFSET).to_be_bytes());
+                v
+            },
+            Self::Varchar(s) => {
+                let mut v = (s.len() as u32).to_be_bytes().to_vec();
+                v.extend_from_slice(s.as_bytes());
+                v
+            },
+            Self::Blob(b) => {
+                let mut v = match b {
+                    Blob::Inline(bytes) => [&[INLINE_BLOB][..], bytes].concat(),
+                    Blob::Overflow(r) => [&[OVERFLOW_BLOB][..], &r.partition.to_be_bytes(), &r.len.to_be_bytes()].concat(),
+                };
+                let mut prefixed = (v.len() as u32).to_be_bytes().to_vec();
+                prefixed.append(&mut v);
+                prefixed
+            },
             Self::Fixchar(s, i) => {
-                let mut v = s.as_bytes().to_vec();
-                while v.len() < ((*i) as usize) {
-                    v.push(0u8);
-                }
+                let mut v = Self::fit_fixchar(s, *i).as_bytes().to_vec();
+                v.resize(*i as usize, 0u8);
                 v.append(&mut i.to_be_bytes().to_vec());
                 v
             },
-            _ => (vec![]),
         }
     }
 
+    /// Cuts a string off at the last character boundary within a fixed string's capacity.
+    /// # Arguments
+    /// * `s` - The string to cut off.
+    /// * `capacity` - The byte capacity of the fixed string.
+    fn fit_fixchar(s: &str, capacity: u64) -> &str {
+        let mut end = s.len().min(capacity as usize);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        &s[..end]
+    }
+
+    /// The byte length every value of the type takes up, `None` for types whose values are
+    /// prefixed with their own length.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> Option<u64> {
-        return match &self {
+        match &self {
             Self::Int8(_) => Some(1),
             Self::Int16(_) => Some(2),
             Self::Int32(_) => Some(4),
@@ -44,7 +94,16 @@ impl DataValue {
             Self::UInt64(_) => Some(8),
             Self::Fixchar(_, i) => Some(*i + 8),
             Self::Bool(_) => Some(1),
+            Self::Float32(_) => Some(4),
+            Self::Float64(_) => Some(8),
+            Self::UInt8(_) => Some(1),
+            Self::UInt16(_) => Some(2),
+            Self::UInt32(_) => Some(4),
+            Self::Date(_) => Some(4),
+            Self::Time(_) => Some(8),
+            Self::Timestamp(_) => Some(10),
             Self::Varchar(_) => None,
+            Self::Blob(_) => None,
         }
     }
 
@@ -58,48 +117,202 @@ impl DataValue {
             Self::Fixchar(_, _) => 6,
             Self::Bool(_) => 7,
             Self::Varchar(_) => 8,
+            Self::Float32(_) => 9,
+            Self::Float64(_) => 10,
+            Self::UInt8(_) => 11,
+            Self::UInt16(_) => 12,
+            Self::UInt32(_) => 13,
+            Self::Date(_) => 14,
+            Self::Time(_) => 15,
+            Self::Timestamp(_) => 16,
+            Self::Blob(_) => 17,
         }
     }
 
-    pub fn from_id(id: u16, metadata: u64) -> Self {
+    /// Gets an empty value of the type with the given id, if it is a known one.
+    /// # Arguments
+    /// * `id` - The id of the type.
+    /// * `metadata` - Extra information about the type, the length of fixed strings.
+    pub fn from_id(id: u16, metadata: u64) -> Option<Self> {
         match id {
-            1 => Self::Int8(0),
-            2 => Self::Int16(0),
-            3 => Self::Int32(0),
-            4 => Self::Int64(0),
-            5 => Self::UInt64(0),
-            6 => Self::Fixchar("".to_string(), metadata),
-            7 => Self::Bool(false),
-            _ => unimplemented!(),
+            1 => Some(Self::Int8(0)),
+            2 => Some(Self::Int16(0)),
+            3 => Some(Self::Int32(0)),
+            4 => Some(Self::Int64(0)),
+            5 => Some(Self::UInt64(0)),
+            6 => Some(Self::Fixchar("".to_string(), metadata)),
+            7 => Some(Self::Bool(false)),
+            8 => Some(Self::Varchar(String::new())),
+            9 => Some(Self::Float32(0.0)),
+            10 => Some(Self::Float64(0.0)),
+            11 => Some(Self::UInt8(0)),
+            12 => Some(Self::UInt16(0)),
+            13 => Some(Self::UInt32(0)),
+            14 => Some(Self::Date(Date::default())),
+            15 => Some(Self::Time(Time::default())),
+            16 => Some(Self::Timestamp(Timestamp::default())),
+            17 => Some(Self::Blob(Blob::Inline(vec![]))),
+            _ => None,
         }
     }
 
-    pub fn from_bytes(bytes: Vec<u8>, d_type: &mut DataValue) {
-        let parse_err = "Couldn't parse value from bytes";
+    /// Parses bytes into a value of the type `d_type` already has, replacing it.
+    /// # Arguments
+    /// * `bytes` - The bytes of the value.
+    /// * `d_type` - The value to replace, it decides the type parsed.
+    pub fn from_bytes(bytes: &[u8], d_type: &mut DataValue) -> Result<(), FSError> {
+        let parse_err = || FSError::CorruptData(format!("{} bytes can't hold a value of type {}", bytes.len(), d_type.id()));
         let new_val = match d_type {
-            Self::Int8(_) => Self::Int8(i8::from_be_bytes(bytes[..].try_into().expect(parse_err))),
-            Self::Int16(_) => Self::Int16(i16::from_be_bytes(bytes[..].try_into().expect(parse_err))),
-            Self::Int32(_) => Self::Int32(i32::from_be_bytes(bytes[..].try_into().expect(parse_err))),
-            Self::Int64(_) => Self::Int64(i64::from_be_bytes(bytes[..].try_into().expect(parse_err))),
-            Self::UInt64(_) => Self::UInt64(u64::from_be_bytes(bytes[..].try_into().expect(parse_err))),
-            Self::Bool(_) => unimplemented!(),
-            Self::Varchar(_) => unimplemented!(),
+            Self::Int8(_) => Self::Int8(i8::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::Int16(_) => Self::Int16(i16::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::Int32(_) => Self::Int32(i32::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::Int64(_) => Self::Int64(i64::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::UInt64(_) => Self::UInt64(u64::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::Float32(_) => Self::Float32(f32::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::Float64(_) => Self::Float64(f64::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::UInt8(_) => Self::UInt8(u8::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::UInt16(_) => Self::UInt16(u16::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::UInt32(_) => Self::UInt32(u32::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?)),
+            Self::Date(_) => Self::Date(Date::from_days_since_epoch(i32::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?))),
+            Self::Time(_) => {
+                let micros = u64::from_be_bytes(bytes[..].try_into().map_err(|_| parse_err())?);
+                Self::Time(Time::from_micros(micros).ok_or_else(|| FSError::CorruptData(format!("{} microseconds is past the end of a day", micros)))?)
+            },
+            Self::Timestamp(_) => {
+                if bytes.len() != 10 {
+                    return Err(parse_err());
+                }
+                let micros = i64::from_be_bytes(bytes[..8].try_into().map_err(|_| parse_err())?);
+                let offset = match i16::from_be_bytes(bytes[8..].try_into().map_err(|_| parse_err())?) {
+                    NO_OFFSET => None,
+                    o => Some(o),
+                };
+                Self::Timestamp(Timestamp::from_micros(micros, offset).ok_or_else(|| FSError::CorruptData(format!("{} microseconds at {:?} minutes isn't a timestamp", micros, offset)))?)
+            },
+            Self::Bool(_) => match bytes {
+                [0] => Self::Bool(false),
+                [1] => Self::Bool(true),
+                _ => return Err(parse_err()),
+            },
+            Self::Varchar(_) | Self::Blob(_) => {
+                let mut buffer = Buffer::borrowed(bytes);
+                let len = buffer.read_u32().map_err(|_| parse_err())?;
+                if len as u64 != buffer.remaining() {
+                    return Err(parse_err());
+                }
+
+                Self::parse_prefixed(buffer.read_bytes(len as u64)?, d_type)?
+            },
             Self::Fixchar(_, _) => {
+                if bytes.len() < 8 {
+                    return Err(parse_err());
+                }
                 let (s, e) = (bytes.len()-8, bytes.len());
                 let len_bytes = &bytes[s..e];
-                let len = u64::from_be_bytes(len_bytes.try_into().expect(parse_err)) as usize;
-                let str_bytes = &bytes[0..len];
-                let str = String::from_utf8_lossy(str_bytes).to_string();
-                let str = str.replace("\0", "");
+                let len = u64::from_be_bytes(len_bytes.try_into().map_err(|_| parse_err())?) as usize;
+                if len != s {
+                    return Err(parse_err());
+                }
+                // The zeros after the string only pad it out to its capacity
+                let str = String::from_utf8_lossy(&bytes[0..len]).trim_end_matches('\0').to_string();
 
                 Self::Fixchar(str, len as u64)
             },
         };
 
         *d_type = new_val;
+        Ok(())
+    }
+
+    /// Parses the next value out of a buffer into a value of the type `d_type` already has,
+    /// replacing it. Values prefixed with their own length take up as many bytes as the prefix says.
+    /// # Arguments
+    /// * `bytes` - The buffer the value is next in.
+    /// * `d_type` - The value to replace, it decides the type parsed.
+    pub fn read_from(bytes: &mut Buffer, d_type: &mut DataValue) -> Result<(), FSError> {
+        match d_type.len() {
+            Some(len) => Self::from_bytes(bytes.read_bytes(len)?, d_type),
+            None => {
+                let len = bytes.read_u32()?;
+                *d_type = Self::parse_prefixed(bytes.read_bytes(len as u64)?, d_type)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Parses the bytes of a value prefixed with its own length, after the prefix.
+    /// # Arguments
+    /// * `bytes` - The bytes after the prefix.
+    /// * `d_type` - A value of the type to parse.
+    fn parse_prefixed(bytes: &[u8], d_type: &DataValue) -> Result<DataValue, FSError> {
+        match d_type {
+            Self::Varchar(_) => String::from_utf8(bytes.to_vec()).map(Self::Varchar)
+                .map_err(|_| FSError::CorruptData("a varchar isn't valid utf-8".to_owned())),
+            Self::Blob(_) => match bytes.split_first() {
+                Some((&INLINE_BLOB, rest)) => Ok(Self::Blob(Blob::Inline(rest.to_vec()))),
+                Some((&OVERFLOW_BLOB, rest)) if rest.len() == 16 => {
+                    let mut buffer = Buffer::borrowed(rest);
+                    Ok(Self::Blob(Blob::Overflow(BlobRef { partition: buffer.read_u64()?, len: buffer.read_u64()? })))
+                },
+                _ => Err(FSError::CorruptData("a blob is neither stored inline nor in an overflow partition".to_owned())),
+            },
+            _ => Err(FSError::CorruptData(format!("values of type {} aren't prefixed with their length", d_type.id()))),
+        }
+    }
+
+    /// Orders values of any type, first by their type id and then by value, so that values can
+    /// be sorted and indexed. Unlike `partial_cmp` every pair of values is ordered, floats follow
+    /// `f64::total_cmp` which puts negative zero before zero and NaNs at the ends.
+    /// # Arguments
+    /// * `other` - The value to compare to.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Float32(a), Self::Float32(b)) => a.total_cmp(b),
+            (Self::Float64(a), Self::Float64(b)) => a.total_cmp(b),
+            (a, b) => a.partial_cmp(b).unwrap_or_else(|| a.id().cmp(&b.id())),
+        }
+    }
+}
+
+/// Values of the same type are ordered by value, fixed strings by their string and then their
+/// capacity. Values of different types, and NaN floats, aren't ordered.
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a.partial_cmp(b),
+            (Self::Int8(a), Self::Int8(b)) => a.partial_cmp(b),
+            (Self::Int16(a), Self::Int16(b)) => a.partial_cmp(b),
+            (Self::Int32(a), Self::Int32(b)) => a.partial_cmp(b),
+            (Self::Int64(a), Self::Int64(b)) => a.partial_cmp(b),
+            (Self::UInt64(a), Self::UInt64(b)) => a.partial_cmp(b),
+            (Self::Varchar(a), Self::Varchar(b)) => a.partial_cmp(b),
+            (Self::Fixchar(a, i), Self::Fixchar(b, j)) => (a, i).partial_cmp(&(b, j)),
+            (Self::Float32(a), Self::Float32(b)) => a.partial_cmp(b),
+            (Self::Float64(a), Self::Float64(b)) => a.partial_cmp(b),
+            (Self::UInt8(a), Self::UInt8(b)) => a.partial_cmp(b),
+            (Self::UInt16(a), Self::UInt16(b)) => a.partial_cmp(b),
+            (Self::UInt32(a), Self::UInt32(b)) => a.partial_cmp(b),
+            (Self::Date(a), Self::Date(b)) => a.partial_cmp(b),
+            (Self::Time(a), Self::Time(b)) => a.partial_cmp(b),
+            (Self::Timestamp(a), Self::Timestamp(b)) => a.partial_cmp(b),
+            (Self::Blob(a), Self::Blob(b)) => a.partial_cmp(b),
+            _ => None,
+        }
     }
 }
 
+/// Marks a blob whose bytes follow in the row
+const INLINE_BLOB: u8 = 0;
+/// Marks a blob whose bytes are in an overflow partition
+const OVERFLOW_BLOB: u8 = 1;
+
+/// Stored in place of the offset of timestamps without one
+const NO_OFFSET: i16 = i16::MIN;
+
+/// The number of bytes before every row of a schema with variable length columns, holding the
+/// byte length of the values that follow
+pub const ROW_PREFIX_LEN: u64 = 4;
+
 #[derive(Clone)]
 pub struct CraneSchema {
     pub types: Vec<DataValue>,
@@ -114,27 +327,74 @@ impl CraneSchema {
         }
     }
 
-    pub fn parse_bytes(&self, bytes: &mut Buffer) -> Vec<DataValue> {
+    pub fn parse_bytes(&self, bytes: &mut Buffer) -> Result<Vec<DataValue>, FSError> {
         let mut values = self.types.clone();
 
+        for v in values.iter_mut() {
+            DataValue::read_from(bytes, v)?;
+        }
 
-        values.iter_mut()
-            // .rev() // Last types written are first types parsed
-            .for_each(|v| DataValue::from_bytes(bytes.consume(v.len().unwrap()), v));
+        Ok(values)
+    }
 
-        values
+    /// The byte length of every row of the schema, `None` if it has columns whose values vary in length.
+    pub fn fixed_len(&self) -> Option<u64> {
+        self.types.iter().map(|v| v.len()).sum()
     }
 
-    pub fn len(&self) -> u64 {
-        self.types.iter().map(|v| v.len().unwrap()).sum()
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
     }
 
-    pub fn produce_bytes(&self, values: &Vec<DataValue>) -> Vec<u8> {
+    pub fn produce_bytes(&self, values: &[DataValue]) -> Vec<u8> {
         values.iter()
-            .map(|v| v.to_bytes().iter().copied().collect::<Vec<u8>>())
-            .flatten()
+            .flat_map(|v| v.to_bytes())
             .collect()
     }
+
+    /// Produces the bytes a row of values is stored as. Rows of a schema with variable length
+    /// columns are prefixed with the byte length of their values, other rows are just their values.
+    /// # Arguments
+    /// * `values` - The values of the row.
+    pub fn produce_row(&self, values: &[DataValue]) -> Vec<u8> {
+        let bytes = self.produce_bytes(values);
+        if self.fixed_len().is_some() {
+            return bytes;
+        }
+
+        let mut row = (bytes.len() as u32).to_be_bytes().to_vec();
+        row.extend_from_slice(&bytes);
+        row
+    }
+
+    /// Parses a row stored by `produce_row`.
+    /// # Arguments
+    /// * `bytes` - The buffer the row is next in.
+    pub fn parse_row(&self, bytes: &mut Buffer) -> Result<Vec<DataValue>, FSError> {
+        if self.fixed_len().is_some() {
+            return self.parse_bytes(bytes);
+        }
+
+        let len = bytes.read_u32()? as u64;
+        let start = bytes.position();
+        let values = self.parse_bytes(bytes)?;
+        if bytes.position() - start != len {
+            return Err(FSError::CorruptData(format!("a row of {} bytes holds {} bytes of values", len, bytes.position() - start)));
+        }
+
+        Ok(values)
+    }
+
+    /// The byte length of a stored row.
+    /// # Arguments
+    /// * `prefix` - The bytes the row starts with, at least `ROW_PREFIX_LEN` of them if the
+    ///   schema has variable length columns.
+    pub fn row_len(&self, prefix: &[u8]) -> Result<u64, FSError> {
+        match self.fixed_len() {
+            Some(len) => Ok(len),
+            None => Ok(ROW_PREFIX_LEN + Buffer::borrowed(prefix).read_u32()? as u64),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -154,8 +414,171 @@ mod test {
 
         let mut bytes = Buffer::new(schema.produce_bytes(&values));
 
-        let back_to_values = schema.parse_bytes(&mut bytes);
+        let back_to_values = schema.parse_bytes(&mut bytes).unwrap();
 
         assert_eq!(values, back_to_values);
     }
+
+    /// Values of every type, at the edges of what they can hold
+    fn every_value() -> Vec<DataValue> {
+        vec![
+            DataValue::Bool(true),
+            DataValue::Bool(false),
+            DataValue::Int8(i8::MIN),
+            DataValue::Int8(-1),
+            DataValue::Int8(i8::MAX),
+            DataValue::Int16(i16::MIN),
+            DataValue::Int16(i16::MAX),
+            DataValue::Int32(i32::MIN),
+            DataValue::Int32(i32::MAX),
+            DataValue::Int64(i64::MIN),
+            DataValue::Int64(i64::MAX),
+            DataValue::UInt64(0),
+            DataValue::UInt64(u64::MAX),
+            DataValue::Varchar(String::new()),
+            DataValue::Varchar("ünïcode \0 inside".to_owned()),
+            DataValue::Fixchar(String::new(), 0),
+            DataValue::Fixchar(String::new(), 16),
+            DataValue::Fixchar("a\0b".to_owned(), 16),
+            DataValue::Fixchar("exactly 16 bytes".to_owned(), 16),
+            DataValue::Float32(f32::MIN),
+            DataValue::Float32(-0.0),
+            DataValue::Float32(f32::INFINITY),
+            DataValue::Float64(f64::MIN_POSITIVE),
+            DataValue::Float64(f64::NEG_INFINITY),
+            DataValue::Float64(f64::MAX),
+            DataValue::UInt8(u8::MAX),
+            DataValue::UInt16(u16::MAX),
+            DataValue::UInt32(u32::MAX),
+            DataValue::Date(Date::from_days_since_epoch(i32::MIN)),
+            DataValue::Date("2024-02-29".parse().unwrap()),
+            DataValue::Time(Time::default()),
+            DataValue::Time("23:59:59.999999".parse().unwrap()),
+            DataValue::Timestamp(Timestamp::from_micros(i64::MIN, None).unwrap()),
+            DataValue::Timestamp("2024-03-10T23:30:00.25-09:30".parse().unwrap()),
+            DataValue::Timestamp("1969-12-31T23:59:59Z".parse().unwrap()),
+            DataValue::Blob(Blob::Inline(vec![])),
+            DataValue::Blob(Blob::Inline(vec![0, 1, 255])),
+            DataValue::Blob(Blob::Overflow(BlobRef { partition: 12, len: 5_000_000 })),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_every_type() {
+        for value in every_value() {
+            let bytes = value.to_bytes();
+            if let Some(len) = value.len() {
+                assert_eq!(bytes.len() as u64, len, "{:?}", value);
+            }
+
+            let metadata = match value {
+                DataValue::Fixchar(_, capacity) => capacity,
+                _ => 0,
+            };
+            let mut parsed = DataValue::from_id(value.id(), metadata).unwrap();
+            DataValue::from_bytes(&bytes, &mut parsed).unwrap();
+            assert_eq!(parsed, value);
+        }
+
+        // Every column has to take up exactly its bytes for the ones after it to line up
+        let values = every_value();
+        let schema = CraneSchema::new(values.clone());
+        let mut bytes = Buffer::new(schema.produce_bytes(&values));
+
+        assert_eq!(schema.parse_bytes(&mut bytes).unwrap(), values);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_nan_round_trip() {
+        let mut parsed = DataValue::Float64(0.0);
+        DataValue::from_bytes(&DataValue::Float64(f64::NAN).to_bytes(), &mut parsed).unwrap();
+
+        assert!(matches!(parsed, DataValue::Float64(f) if f.to_bits() == f64::NAN.to_bits()));
+    }
+
+    #[test]
+    fn test_ordering() {
+        assert!(DataValue::UInt8(3) < DataValue::UInt8(200));
+        assert!(DataValue::UInt32(70000) > DataValue::UInt32(5));
+        assert!(DataValue::Float32(-1.5) < DataValue::Float32(0.25));
+        assert!(DataValue::Varchar("apple".to_owned()) < DataValue::Varchar("banana".to_owned()));
+        assert_eq!(DataValue::Int8(1).partial_cmp(&DataValue::UInt8(1)), None);
+        assert_eq!(DataValue::Float64(f64::NAN).partial_cmp(&DataValue::Float64(1.0)), None);
+        assert!(DataValue::Date("1999-12-31".parse().unwrap()) < DataValue::Date("2000-01-01".parse().unwrap()));
+        assert!(DataValue::Timestamp("2024-03-10T23:00:00+02:00".parse().unwrap()) < DataValue::Timestamp("2024-03-10T22:00:00Z".parse().unwrap()));
+
+        let mut values = [
+            DataValue::Float64(f64::NAN),
+            DataValue::Float64(1.0),
+            DataValue::UInt16(4),
+            DataValue::Float64(-0.0),
+            DataValue::Float64(0.0),
+            DataValue::Int8(-3),
+        ];
+        values.sort_by(DataValue::total_cmp);
+        assert!(matches!(values[..], [DataValue::Int8(-3), DataValue::Float64(z), DataValue::Float64(_), DataValue::Float64(_), DataValue::Float64(n), DataValue::UInt16(4)]
+            if z.is_sign_negative() && n.is_nan()));
+    }
+
+    #[test]
+    fn test_fixchar_capacity() {
+        let parse = |value: DataValue| {
+            let bytes = value.to_bytes();
+            assert_eq!(Some(bytes.len() as u64), value.len());
+            let mut parsed = DataValue::Fixchar(String::new(), 0);
+            DataValue::from_bytes(&bytes, &mut parsed).unwrap();
+            parsed
+        };
+
+        assert_eq!(parse(DataValue::Fixchar("four".to_owned(), 4)), DataValue::Fixchar("four".to_owned(), 4));
+        assert_eq!(parse(DataValue::Fixchar("too long".to_owned(), 3)), DataValue::Fixchar("too".to_owned(), 3));
+        // Strings are only cut off between characters
+        assert_eq!(parse(DataValue::Fixchar("aé".to_owned(), 2)), DataValue::Fixchar("a".to_owned(), 2));
+        assert_eq!(parse(DataValue::Fixchar("gone".to_owned(), 0)), DataValue::Fixchar(String::new(), 0));
+
+        let mut bytes = DataValue::Fixchar("abc".to_owned(), 4).to_bytes();
+        bytes[11] = 5;
+        assert!(matches!(DataValue::from_bytes(&bytes, &mut DataValue::Fixchar(String::new(), 4)), Err(FSError::CorruptData(_))));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let mut value = DataValue::Bool(false);
+        assert!(matches!(DataValue::from_bytes(&[2], &mut value), Err(FSError::CorruptData(_))));
+        assert!(matches!(DataValue::from_bytes(&[], &mut value), Err(FSError::CorruptData(_))));
+        assert!(matches!(DataValue::from_bytes(&[1, 2], &mut DataValue::Int8(0)), Err(FSError::CorruptData(_))));
+        assert!(matches!(DataValue::from_bytes(&[0, 0, 0, 2, b'a'], &mut DataValue::Varchar(String::new())), Err(FSError::CorruptData(_))));
+        assert!(matches!(DataValue::from_bytes(&[0, 0, 0, 2, 1, 0], &mut DataValue::Blob(Blob::Inline(vec![]))), Err(FSError::CorruptData(_))));
+        assert!(matches!(DataValue::from_bytes(&u64::MAX.to_be_bytes(), &mut DataValue::Time(Time::default())), Err(FSError::CorruptData(_))));
+        assert!(matches!(DataValue::from_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 8, 0], &mut DataValue::Timestamp(Timestamp::default())), Err(FSError::CorruptData(_))));
+    }
+
+    #[test]
+    fn test_varchar_rows() {
+        let values = vec![
+            DataValue::Varchar("héllo".to_owned()),
+            DataValue::Int32(7),
+            DataValue::Varchar(String::new()),
+        ];
+        let schema = CraneSchema::new(vec![
+            DataValue::from_id(8, 0).unwrap(),
+            DataValue::Int32(0),
+            DataValue::Varchar(String::new()),
+        ]);
+        assert_eq!(schema.fixed_len(), None);
+
+        let row = schema.produce_row(&values);
+        assert_eq!(row.len() as u64, ROW_PREFIX_LEN + 4 + 6 + 4 + 4);
+        assert_eq!(schema.row_len(&row[..4]).unwrap(), row.len() as u64);
+        assert_eq!(schema.parse_row(&mut Buffer::new(row.clone())).unwrap(), values);
+
+        let mut short = row.clone();
+        short.truncate(row.len() - 1);
+        assert!(matches!(schema.parse_row(&mut Buffer::new(short)), Err(FSError::UnexpectedEnd { .. })));
+
+        let mut invalid = row;
+        invalid[ROW_PREFIX_LEN as usize + 4] = 0xff;
+        assert!(matches!(schema.parse_row(&mut Buffer::new(invalid)), Err(FSError::CorruptData(_))));
+    }
 }
\ No newline at end of file
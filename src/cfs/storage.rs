@@ -0,0 +1,33 @@
+use super::FSError;
+
+
+/// A storage is a flat, growable run of bytes that a disk lives in, shareable between threads
+pub trait Storage: Send + Sync {
+    /// Reads bytes, stopping early if the storage ends before `len` bytes
+    /// # Arguments
+    /// * `byte` - The byte to start reading at
+    /// * `len` - The number of bytes to read
+    fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError>;
+    /// Writes bytes, growing the storage if they go past its end
+    /// # Arguments
+    /// * `byte` - The byte to start writing at
+    /// * `bytes` - The bytes to write
+    fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError>;
+    /// The number of bytes in the storage
+    fn len(&self) -> Result<u64, FSError>;
+    /// Whether the storage holds no bytes
+    fn is_empty(&self) -> Result<bool, FSError> {
+        Ok(self.len()? == 0)
+    }
+    /// Hands everything written so far down to the storage below, without waiting for it to be persisted.
+    /// Storages that don't buffer writes have nothing to do.
+    fn flush(&mut self) -> Result<(), FSError> {
+        Ok(())
+    }
+    /// Makes sure everything written so far is persisted
+    fn sync(&mut self) -> Result<(), FSError>;
+    /// Cuts the storage down to a length, storages that are already shorter are left alone
+    /// # Arguments
+    /// * `len` - The number of bytes to keep
+    fn truncate(&mut self, len: u64) -> Result<(), FSError>;
+}
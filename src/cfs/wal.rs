@@ -0,0 +1,484 @@
+use std::{cmp::{max, min}, collections::{BTreeMap, BTreeSet}, mem, sync::{RwLock, Weak}};
+
+use super::{FSError, buffer::Buffer, storage::Storage};
+
+/// Marks the header of a committed batch, "CRANEWAL" in ascii
+const WAL_MAGIC: u64 = 0x4352_414e_4557_414c;
+/// The number of sectors reserved for the log after the root partition
+pub const WAL_SECTORS: u64 = 256;
+
+/// The byte length of the longest row a disk with the given sector length can store. A row is
+/// written in one batch of the log with the other writes of its command, so it may take up at
+/// most half of the log.
+/// # Arguments
+/// * `sector_length` - The byte length of the disk's sectors.
+pub fn max_row_len(sector_length: u64) -> u64 {
+    WAL_SECTORS*sector_length/2
+}
+/// The number of bytes in the batch header
+const HEADER_LEN: u64 = 28;
+
+/// A write that has been logged but not yet applied to the disk
+struct PendingWrite {
+    byte: u64,
+    bytes: Vec<u8>,
+}
+
+/// What the log region holds
+enum LogContents {
+    /// No batch
+    Empty,
+    /// A batch whose writes didn't all reach the log, so it never committed
+    Torn,
+    /// A committed batch and its sequence number
+    Committed(Vec<PendingWrite>, u64),
+}
+
+/// A redo log stored in a fixed region of the disk's storage.
+///
+/// Writes are held in memory until `commit`, which first persists the whole batch
+/// to the log region and only then applies it to the rest of the storage. A crash
+/// before the batch header is written loses the batch, a crash after it gets
+/// the batch replayed by `recover`.
+///
+/// Batches are logged as whole sectors, so replaying one never depends on the old
+/// contents of a sector that may have been torn by the crash.
+///
+/// Between the steps of a commit the storage is synced, or only flushed if the log is told
+/// not to sync writes, which keeps the order of the steps for a crashing process but not for
+/// a power loss.
+pub struct WriteAheadLog {
+    start_byte: u64,
+    total_bytes: u64,
+    sector_length: u64,
+    sequence: u64,
+    /// Pending bytes keyed by the byte they start at, overlapping and touching writes are merged
+    pending: BTreeMap<u64, Vec<u8>>,
+    /// Whether commits sync the storage between their steps instead of only flushing it
+    sync_writes: bool,
+    storage: Weak<RwLock<dyn Storage>>,
+}
+
+impl WriteAheadLog {
+    /// Creates a log over a region of the storage.
+    /// # Arguments
+    /// * `start` - The sector the log region starts at.
+    /// * `len` - How many sectors the log region is.
+    /// * `sector_length` - The byte length of the storage's sectors.
+    /// * `storage` - The storage the log lives in.
+    pub fn new(start: u64, len: u64, sector_length: u64, storage: Weak<RwLock<dyn Storage>>) -> Self {
+        WriteAheadLog {
+            start_byte: start*sector_length,
+            total_bytes: len*sector_length,
+            sector_length,
+            sequence: 0,
+            pending: BTreeMap::new(),
+            sync_writes: true,
+            storage,
+        }
+    }
+
+    /// The byte length of the sectors of the storage the log lives in.
+    pub fn sector_length(&self) -> u64 {
+        self.sector_length
+    }
+
+    /// Sets whether the next commits sync the storage between their steps or only flush it.
+    /// # Arguments
+    /// * `sync_writes` - Whether to sync the storage.
+    pub fn set_sync_writes(&mut self, sync_writes: bool) {
+        self.sync_writes = sync_writes;
+    }
+
+    /// An upper bound on the bytes the pending writes take up in the log once widened to whole sectors.
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending.values().map(|b| 16 + b.len() as u64 + 2*self.sector_length).sum()
+    }
+
+    /// The number of bytes a batch can take up in the log.
+    pub fn capacity(&self) -> u64 {
+        self.total_bytes - self.body_offset()
+    }
+
+    /// Queues bytes to be written at an absolute byte of the storage.
+    ///
+    /// The bytes are merged with any pending write they overlap or touch, so rewriting
+    /// the same bytes before a commit doesn't grow the batch.
+    pub fn log(&mut self, byte: u64, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let end = byte + bytes.len() as u64;
+        let merged: Vec<u64> = self.pending.range(..=end)
+            .filter(|(s, b)| **s + b.len() as u64 >= byte)
+            .map(|(s, _)| *s)
+            .collect();
+
+        let start = merged.first().map_or(byte, |s| min(*s, byte));
+        let mut combined: Vec<u8> = vec![];
+        for s in merged {
+            let old = self.pending.remove(&s).unwrap();
+            let from = (s - start) as usize;
+            if combined.len() < from + old.len() {
+                combined.resize(from + old.len(), 0);
+            }
+            combined[from..(from + old.len())].copy_from_slice(&old);
+        }
+
+        let from = (byte - start) as usize;
+        if combined.len() < from + bytes.len() {
+            combined.resize(from + bytes.len(), 0);
+        }
+        combined[from..(from + bytes.len())].copy_from_slice(bytes);
+
+        self.pending.insert(start, combined);
+    }
+
+    /// Patches bytes read from the storage with the pending writes that overlap them.
+    /// # Arguments
+    /// * `start` - The absolute byte the buffer was read from.
+    /// * `end` - The absolute byte the read was meant to end at.
+    /// * `buffer` - The bytes read from the storage, extended if a pending write goes past them.
+    pub fn overlay(&self, start: u64, end: u64, buffer: &mut Vec<u8>) {
+        for (byte, bytes) in self.pending.range(..end) {
+            let write_end = byte + bytes.len() as u64;
+            if write_end <= start {
+                continue;
+            }
+
+            let s = max(*byte, start);
+            let e = min(write_end, end);
+            if (buffer.len() as u64) < e - start {
+                buffer.resize((e - start) as usize, 0);
+            }
+
+            buffer[((s - start) as usize)..((e - start) as usize)]
+                .copy_from_slice(&bytes[((s - byte) as usize)..((e - byte) as usize)]);
+        }
+    }
+
+    /// Drops the pending writes without logging them.
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Persists the pending writes to the log, applies them to the storage and clears the log.
+    ///
+    /// A batch that doesn't fit in the log is dropped with `FSError::LogFull` before anything is
+    /// written, so that the writes after it can still be committed.
+    pub fn commit(&mut self) -> Result<(), FSError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(err) = self.write_log() {
+            if let FSError::LogFull { .. } = err {
+                self.pending.clear();
+            }
+            return Err(err);
+        }
+        let writes: Vec<PendingWrite> = mem::take(&mut self.pending).into_iter()
+            .map(|(byte, bytes)| PendingWrite { byte, bytes })
+            .collect();
+        self.apply(&writes)?;
+        self.clear_log()
+    }
+
+    /// Replays a batch that was committed to the log but possibly not applied,
+    /// discarding it if it is incomplete. Returns the number of writes replayed.
+    pub fn recover(&mut self) -> Result<usize, FSError> {
+        match self.read_log()? {
+            LogContents::Empty => Ok(0),
+            LogContents::Torn => self.clear_log().map(|_| 0),
+            LogContents::Committed(writes, sequence) => {
+                self.apply(&writes)?;
+                self.clear_log()?;
+                self.sequence = sequence;
+                Ok(writes.len())
+            }
+        }
+    }
+
+    /// Whether the log holds a committed batch that `recover` would replay, without changing anything.
+    pub fn has_committed_batch(&self) -> Result<bool, FSError> {
+        Ok(matches!(self.read_log()?, LogContents::Committed(..)))
+    }
+
+    /// Reads the batch in the log region, if there is one.
+    fn read_log(&self) -> Result<LogContents, FSError> {
+        let header = match self.read_at(self.start_byte, HEADER_LEN) {
+            Err(FSError::ChecksumMismatch { .. }) | Err(FSError::AuthenticationFailed { .. }) => return Ok(LogContents::Torn),
+            header => header?,
+        };
+        if header.len() < HEADER_LEN as usize {
+            return Ok(LogContents::Empty);
+        }
+
+        let mut header = Buffer::new(header);
+        let magic = header.read_u64()?;
+        let sequence = header.read_u64()?;
+        let body_len = header.read_u64()?;
+        let checksum = header.read_u32()?;
+
+        if magic != WAL_MAGIC {
+            return Ok(LogContents::Empty);
+        }
+
+        let body = if body_len + self.body_offset() <= self.total_bytes {
+            match self.read_at(self.start_byte + self.body_offset(), body_len) {
+                Err(FSError::ChecksumMismatch { .. }) | Err(FSError::AuthenticationFailed { .. }) => vec![],
+                body => body?,
+            }
+        } else {
+            vec![]
+        };
+
+        if body.len() as u64 != body_len || crc32fast::hash(&body) != checksum {
+            return Ok(LogContents::Torn);
+        }
+
+        Ok(LogContents::Committed(Self::parse_body(body)?, sequence))
+    }
+
+    /// Writes the pending batch to the log region, followed by the header that marks it as committed.
+    fn write_log(&mut self) -> Result<(), FSError> {
+        self.align_pending()?;
+
+        let mut body: Vec<u8> = self.pending.iter().flat_map(|(byte, bytes)| {
+            let mut v = byte.to_be_bytes().to_vec();
+            v.append(&mut (bytes.len() as u64).to_be_bytes().to_vec());
+            v.extend_from_slice(bytes);
+            v
+        }).collect();
+
+        let body_len = body.len() as u64;
+        let checksum = crc32fast::hash(&body);
+        body.resize(self.sector_align(body_len) as usize, 0);
+
+        if body.len() as u64 + self.body_offset() > self.total_bytes {
+            return Err(FSError::LogFull {
+                needed: body.len() as u64,
+                available: self.capacity(),
+            });
+        }
+
+        self.write_at(self.start_byte + self.body_offset(), &body)?;
+        self.barrier()?;
+
+        self.sequence += 1;
+        let mut header = WAL_MAGIC.to_be_bytes().to_vec();
+        header.append(&mut self.sequence.to_be_bytes().to_vec());
+        header.append(&mut body_len.to_be_bytes().to_vec());
+        header.append(&mut checksum.to_be_bytes().to_vec());
+        header.resize(self.sector_length as usize, 0);
+
+        self.write_at(self.start_byte, &header)?;
+        self.barrier()
+    }
+
+    /// Widens the pending writes to whole sectors, filling them in with the sectors' current bytes.
+    fn align_pending(&mut self) -> Result<(), FSError> {
+        let sl = self.sector_length;
+        let partial: BTreeSet<u64> = self.pending.iter()
+            .flat_map(|(byte, bytes)| {
+                let end = byte + bytes.len() as u64;
+                vec![(*byte, byte / sl), (end, (end - 1) / sl)]
+            })
+            .filter(|(edge, _)| edge % sl != 0)
+            .map(|(_, sector)| sector)
+            .collect();
+
+        for sector in partial {
+            let start = sector*sl;
+            let mut bytes = self.read_at(start, sl)?;
+            self.overlay(start, start + sl, &mut bytes);
+            bytes.resize(sl as usize, 0);
+
+            self.log(start, &bytes);
+        }
+        Ok(())
+    }
+
+    /// Rounds a byte length up to a whole number of sectors.
+    fn sector_align(&self, len: u64) -> u64 {
+        len.div_ceil(self.sector_length)*self.sector_length
+    }
+
+    /// Writes a batch to its final place in the storage.
+    fn apply(&self, writes: &[PendingWrite]) -> Result<(), FSError> {
+        for write in writes {
+            self.write_at(write.byte, &write.bytes)?;
+        }
+        self.barrier()
+    }
+
+    /// Marks the log as empty, replaying an already applied batch is harmless so this is not synced.
+    fn clear_log(&self) -> Result<(), FSError> {
+        self.write_at(self.start_byte, &vec![0u8; self.sector_length as usize])
+    }
+
+    fn parse_body(body: Vec<u8>) -> Result<Vec<PendingWrite>, FSError> {
+        let mut buffer = Buffer::new(body);
+        let mut writes = vec![];
+        let truncated = |_| FSError::CorruptMetadata("write ahead log entry is truncated".to_owned());
+
+        while !buffer.is_empty() {
+            let byte = buffer.read_u64().map_err(truncated)?;
+            let len = buffer.read_u64().map_err(truncated)?;
+            writes.push(PendingWrite {
+                byte,
+                bytes: buffer.read_bytes(len).map_err(truncated)?.to_vec(),
+            });
+        }
+
+        Ok(writes)
+    }
+
+    /// Where the batch body starts relative to the log region
+    fn body_offset(&self) -> u64 {
+        self.sector_length
+    }
+
+    fn read_at(&self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+        let storage = self.storage.upgrade().ok_or(FSError::StorageClosed)?;
+        let mut storage = storage.write().unwrap();
+
+        storage.read_at(byte, len)
+    }
+
+    fn write_at(&self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let storage = self.storage.upgrade().ok_or(FSError::StorageClosed)?;
+        let mut storage = storage.write().unwrap();
+
+        storage.write_at(byte, bytes)
+    }
+
+    /// Flushes everything written so far, so that nothing written after it can reach the disk first.
+    fn barrier(&self) -> Result<(), FSError> {
+        let storage = self.storage.upgrade().ok_or(FSError::StorageClosed)?;
+        let mut storage = storage.write().unwrap();
+
+        if self.sync_writes {
+            storage.sync()
+        } else {
+            storage.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{SECTOR_LENGTH, cfs::memory_storage::MemoryStorage};
+
+    use super::*;
+
+    const LOG_START: u64 = 4;
+
+    fn create_storage() -> Arc<RwLock<dyn Storage>> {
+        Arc::new(RwLock::new(MemoryStorage::from_bytes(vec![0u8; SECTOR_LENGTH*8])))
+    }
+
+    fn read_byte(storage: &Arc<RwLock<dyn Storage>>, byte: u64) -> u8 {
+        storage.write().unwrap().read_at(byte, 1).unwrap()[0]
+    }
+
+    #[test]
+    fn test_commit() {
+        let storage = create_storage();
+        let mut wal = WriteAheadLog::new(LOG_START, 4, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+        wal.log(10, &[1, 2, 3]);
+
+        let mut buffer = vec![0u8; 16];
+        wal.overlay(0, 16, &mut buffer);
+        assert_eq!(&buffer[10..13], &[1, 2, 3]);
+        assert_eq!(read_byte(&storage, 11), 0);
+
+        wal.commit().unwrap();
+
+        assert!(wal.pending.is_empty());
+        assert_eq!(read_byte(&storage, 11), 2);
+        assert_eq!(wal.recover().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_recover_committed_batch() {
+        let storage = create_storage();
+        {
+            let mut wal = WriteAheadLog::new(LOG_START, 4, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+            wal.log(100, &[7, 7]);
+            wal.log(200, &[8]);
+            wal.write_log().unwrap();
+        }
+
+        let mut wal = WriteAheadLog::new(LOG_START, 4, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+        // Both writes land in the same sector, which is logged as a whole
+        assert_eq!(wal.recover().unwrap(), 1);
+        assert_eq!(read_byte(&storage, 100), 7);
+        assert_eq!(read_byte(&storage, 200), 8);
+        assert_eq!(wal.recover().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rewrites_coalesced() {
+        let storage = create_storage();
+        let mut wal = WriteAheadLog::new(LOG_START, 4, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+        wal.log(10, &[1, 2, 3]);
+        wal.log(13, &[4]);
+        wal.log(8, &[5, 6, 7]);
+        wal.log(40, &[9]);
+
+        assert_eq!(wal.pending.len(), 2);
+        assert_eq!(wal.pending[&8], vec![5, 6, 7, 2, 3, 4]);
+
+        // Rewriting the same bytes many times must still fit in the log
+        for i in 0..1000u32 {
+            wal.log(64, &i.to_be_bytes());
+        }
+        assert_eq!(wal.pending.len(), 3);
+
+        wal.commit().unwrap();
+        assert_eq!(read_byte(&storage, 67), (999 % 256) as u8);
+    }
+
+    #[test]
+    fn test_discard_torn_batch() {
+        let storage = create_storage();
+        {
+            let mut wal = WriteAheadLog::new(LOG_START, 4, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+            wal.log(100, &[7, 7]);
+            wal.write_log().unwrap();
+
+            let body = LOG_START*(SECTOR_LENGTH as u64) + wal.body_offset();
+            wal.write_at(body + 16, &[9]).unwrap();
+        }
+
+        let mut wal = WriteAheadLog::new(LOG_START, 4, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+        assert_eq!(wal.recover().unwrap(), 0);
+        assert_eq!(read_byte(&storage, 100), 0);
+    }
+
+    #[test]
+    fn test_batch_too_large() {
+        let storage = create_storage();
+        let mut wal = WriteAheadLog::new(LOG_START, 3, SECTOR_LENGTH as u64, Arc::downgrade(&storage));
+
+        wal.log(0, &[1u8; 2*SECTOR_LENGTH]);
+
+        assert!(matches!(wal.commit(), Err(FSError::LogFull { .. })));
+        assert!(wal.pending.is_empty());
+
+        // The log is still usable for batches that fit
+        wal.log(10, &[1]);
+        wal.commit().unwrap();
+        assert_eq!(read_byte(&storage, 0), 0);
+        assert_eq!(read_byte(&storage, 10), 1);
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::{RwLock, Weak};
+
+use super::{FSError, crane_reader::CraneReader, reader::Reader, wal::WriteAheadLog};
+
+/// A reader that sees the writes still waiting in the write ahead log on top of the file
+pub struct WalReader {
+    reader: CraneReader,
+    log: Weak<RwLock<WriteAheadLog>>
+}
+
+impl WalReader {
+    pub fn new(reader: CraneReader, log: Weak<RwLock<WriteAheadLog>>) -> Self {
+        WalReader {
+            reader,
+            log
+        }
+    }
+}
+
+impl Reader for WalReader {
+    fn sector_length(&self) -> u64 {
+        self.reader.sector_length()
+    }
+
+    fn read_sectors(&mut self, start: u64, end: u64) -> Result<Vec<u8>, FSError> {
+        let mut buffer = self.reader.read_sectors(start, end)?;
+
+        if let Some(log) = self.log.upgrade() {
+            let sector_length = self.sector_length();
+            log.read().unwrap().overlay(start*sector_length, end*sector_length, &mut buffer);
+            return Ok(buffer);
+        }
+        Err(FSError::StorageClosed)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.reader.capacity()
+    }
+}
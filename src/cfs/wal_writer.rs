@@ -0,0 +1,47 @@
+use std::sync::{RwLock, Weak};
+
+use super::{FSError, bounds::Bounds, wal::WriteAheadLog, writer::Writer};
+
+/// A writer that logs its writes to the write ahead log instead of writing them to the file
+pub struct WalWriter {
+    sector_length: u64,
+    bounds: Bounds,
+    log: Weak<RwLock<WriteAheadLog>>
+}
+
+impl WalWriter {
+    /// Creates a writer over a partition.
+    /// # Arguments
+    /// * `partition` - The id of the partition, reported in out of bounds errors.
+    /// * `extents` - The start sector and sector length of every extent of the partition.
+    /// * `sector_length` - The byte length of the sectors.
+    /// * `log` - The log the writes are made to.
+    pub fn new(partition: u64, extents: &[(u64, u64)], sector_length: u64, log: Weak<RwLock<WriteAheadLog>>) -> Self {
+        WalWriter {
+            sector_length,
+            bounds: Bounds::new(partition, extents, sector_length),
+            log
+        }
+    }
+}
+
+impl Writer for WalWriter {
+    fn sector_length(&self) -> u64 {
+        self.sector_length
+    }
+
+    fn write_sectors(&mut self, start: u64, offset: u64, bytes: &[u8]) -> Result<(), FSError> {
+        let start_byte = start*self.sector_length + offset;
+        self.bounds.check(start_byte, start_byte + bytes.len() as u64)?;
+
+        if let Some(log) = self.log.upgrade() {
+            log.write().unwrap().log(start_byte, bytes);
+            return Ok(());
+        }
+        Err(FSError::StorageClosed)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.bounds.len()/self.sector_length
+    }
+}
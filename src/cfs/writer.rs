@@ -2,7 +2,7 @@ use super::FSError;
 
 
 /// A writer is an object who can write bytes to sectors inside of it
-pub trait Writer {
+pub trait Writer: Send + Sync {
     /// The number of bytes a sector takes up
     fn sector_length(&self) -> u64;
     /// Writes bytes
@@ -0,0 +1,573 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt, path::Path, sync::{Arc, RwLock}};
+
+use crate::cfs::{Blob, Buffer, CraneDisk, DataValue, FSError, OpenOptions, PartitionKind, PartitionType, ROW_PREFIX_LEN, Storage};
+
+use super::{Crane, data_command::{read_row, row_len_at}, data_manager::DataManager};
+
+/// Something wrong with a disk, or worth knowing about it, found while checking it.
+/// Partition 0 stands for the disk's own metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The disk can't be opened at all
+    Unreadable(String),
+    /// The write ahead log holds a committed batch that isn't applied yet, the disk can only be
+    /// checked once it was opened for writing and the batch replayed
+    UnappliedLog,
+    /// An extent of a partition reaches past the end of the disk
+    ExtentOutOfBounds {
+        partition: u64,
+        start: u64,
+        end: u64,
+        disk_len: u64,
+    },
+    /// Extents of two partitions share sectors
+    OverlappingExtents {
+        first: u64,
+        second: u64,
+        /// The first sector they share
+        sector: u64,
+    },
+    /// A schema is missing one of its partitions
+    MissingPartition {
+        schema: u64,
+        kind: PartitionKind,
+    },
+    /// The schema or tree partition of a schema can't be parsed
+    UnreadableSchema {
+        schema: u64,
+        error: String,
+    },
+    /// A key points into a partition that isn't one of its schema's data partitions
+    ForeignPosition {
+        schema: u64,
+        key: u64,
+        partition: u64,
+    },
+    /// A key points to an offset that isn't the start of a row
+    MisalignedPosition {
+        schema: u64,
+        key: u64,
+        offset: u64,
+    },
+    /// A key points past the bytes written to its partition
+    PositionOutOfBounds {
+        schema: u64,
+        key: u64,
+        offset: u64,
+        initialized_len: u64,
+    },
+    /// The length of the row a key points to can't be read
+    UnreadableRow {
+        schema: u64,
+        key: u64,
+        error: String,
+    },
+    /// Two keys point to rows that partly overlap
+    OverlappingRows {
+        schema: u64,
+        partition: u64,
+        first_key: u64,
+        second_key: u64,
+    },
+    /// A row refers to a blob that isn't in one of its schema's overflow partitions
+    DanglingBlob {
+        schema: u64,
+        key: u64,
+        partition: u64,
+    },
+    /// Rows no key points to, left behind by removed values until their slots are reused
+    OrphanedRows {
+        schema: u64,
+        partition: u64,
+        rows: u64,
+    },
+    /// An overflow partition no row refers to, a blob that was never stored in a row or whose
+    /// writer was dropped before it finished, until compaction drops it
+    OrphanedBlob {
+        schema: u64,
+        partition: u64,
+    },
+}
+
+impl Issue {
+    /// Whether the issue means the disk is corrupt, rather than just worth knowing about.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Issue::UnappliedLog | Issue::OrphanedRows { .. } | Issue::OrphanedBlob { .. })
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Unreadable(err) => write!(f, "the disk can't be opened: {}", err),
+            Issue::UnappliedLog => write!(f, "the write ahead log holds a batch that isn't applied yet, open the disk for writing to replay it and check it again"),
+            Issue::ExtentOutOfBounds { partition, start, end, disk_len } =>
+                write!(f, "partition {} spans sectors {}..{} of a {} sector disk", partition, start, end, disk_len),
+            Issue::OverlappingExtents { first, second, sector } =>
+                write!(f, "partitions {} and {} overlap from sector {}", first, second, sector),
+            Issue::MissingPartition { schema, kind } => write!(f, "schema {} has no {:?} partition", schema, kind),
+            Issue::UnreadableSchema { schema, error } => write!(f, "schema {} can't be read: {}", schema, error),
+            Issue::ForeignPosition { schema, key, partition } =>
+                write!(f, "key {} of schema {} points into partition {} which doesn't hold its values", key, schema, partition),
+            Issue::MisalignedPosition { schema, key, offset } =>
+                write!(f, "key {} of schema {} points to byte {} which doesn't start a row", key, schema, offset),
+            Issue::PositionOutOfBounds { schema, key, offset, initialized_len } =>
+                write!(f, "key {} of schema {} points to byte {} past the {} bytes written", key, schema, offset, initialized_len),
+            Issue::UnreadableRow { schema, key, error } => write!(f, "the row of key {} of schema {} can't be read: {}", key, schema, error),
+            Issue::OverlappingRows { schema, partition, first_key, second_key } =>
+                write!(f, "keys {} and {} of schema {} point to overlapping rows in partition {}", first_key, second_key, schema, partition),
+            Issue::DanglingBlob { schema, key, partition } =>
+                write!(f, "key {} of schema {} refers to a blob in partition {} which doesn't hold it", key, schema, partition),
+            Issue::OrphanedRows { schema, partition, rows } =>
+                write!(f, "{} rows of schema {} in partition {} aren't pointed to by any key", rows, schema, partition),
+            Issue::OrphanedBlob { schema, partition } =>
+                write!(f, "the blob of schema {} in partition {} isn't referred to by any row", schema, partition),
+        }
+    }
+}
+
+/// What checking a disk found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// The number of partitions checked
+    pub partitions: u64,
+    /// The number of schemas checked
+    pub schemas: u64,
+    /// The number of keys checked
+    pub keys: u64,
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    /// Whether none of the issues found mean the disk is corrupt.
+    pub fn is_clean(&self) -> bool {
+        !self.issues.iter().any(Issue::is_error)
+    }
+}
+
+/// Checks a disk for corruption without changing it.
+///
+/// Partition extents must lie within the disk and not overlap each other or the disk's metadata.
+/// Every schema must have its partitions and parse, and every key of a schema must point to the
+/// start of a written row in one of its data partitions without overlapping another row, and
+/// the blobs of the row must be in the schema's overflow partitions.
+/// # Arguments
+/// * `disk` - The disk to check.
+pub fn check_disk(disk: &CraneDisk) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    check_extents(disk, &mut report);
+
+    report.schemas = Crane::count_schemas(&disk.partitions);
+    for slot in 0..report.schemas {
+        let mut missing = false;
+        for kind in [PartitionKind::Schema, PartitionKind::Index, PartitionKind::Data].iter() {
+            if disk.get_partition_by_type(PartitionType::new(*kind, slot).raw()).is_empty() {
+                report.issues.push(Issue::MissingPartition { schema: slot, kind: *kind });
+                missing = true;
+            }
+        }
+        if missing {
+            continue;
+        }
+
+        match DataManager::from_disk(disk, slot) {
+            Ok(manager) => check_rows(disk, slot, &manager, &mut report),
+            Err(err) => report.issues.push(Issue::UnreadableSchema { schema: slot, error: err.to_string() }),
+        }
+    }
+
+    report
+}
+
+/// Opens a disk from a storage and checks it, see `check_disk`. A disk that can't be opened is
+/// reported as unreadable.
+/// # Arguments
+/// * `storage` - The storage holding the disk.
+pub fn check_storage(storage: Arc<RwLock<dyn Storage>>) -> CheckReport {
+    match CraneDisk::from_storage(storage) {
+        Ok(disk) => check_disk(&disk),
+        Err(err) => CheckReport {
+            issues: vec![Issue::Unreadable(err.to_string())],
+            ..Default::default()
+        },
+    }
+}
+
+/// Opens the disk at a path for reading and checks it, see `check_disk`. A disk that can't be
+/// parsed is reported as unreadable and one with a batch left in its write ahead log as
+/// `Issue::UnappliedLog` without checking it further, as the batch can't be replayed without
+/// writing. Fails if the file can't be read or is locked by a writer.
+/// # Arguments
+/// * `path` - The path of the disk file.
+pub fn check_file<P: AsRef<Path>>(path: P) -> Result<CheckReport, FSError> {
+    let options = OpenOptions { create: false, read_only: true, ..Default::default() };
+    match CraneDisk::open(path, options) {
+        Ok(disk) => Ok(check_disk(&disk)),
+        Err(FSError::UnappliedLog) => Ok(CheckReport {
+            issues: vec![Issue::UnappliedLog],
+            ..Default::default()
+        }),
+        Err(err @ FSError::Io(_)) | Err(err @ FSError::Locked(_)) | Err(err @ FSError::StorageClosed) => Err(err),
+        Err(err) => Ok(CheckReport {
+            issues: vec![Issue::Unreadable(err.to_string())],
+            ..Default::default()
+        }),
+    }
+}
+
+/// Checks that every extent lies within the disk and that no two of them overlap.
+fn check_extents(disk: &CraneDisk, report: &mut CheckReport) {
+    let disk_len = disk.len();
+    // The start, end and partition of every extent
+    let mut extents: Vec<(u64, u64, u64)> = disk.reserved_extents().iter()
+        .map(|(s, l)| (*s, s + l, 0))
+        .collect();
+
+    for partition in disk.partitions.iter() {
+        let partition = partition.read().unwrap();
+        report.partitions += 1;
+
+        for (start, len) in partition.extents().iter().filter(|(_, l)| *l > 0) {
+            let end = start + len;
+            if end > disk_len {
+                report.issues.push(Issue::ExtentOutOfBounds { partition: partition.id(), start: *start, end, disk_len });
+            }
+            extents.push((*start, end, partition.id()));
+        }
+    }
+
+    extents.sort_unstable();
+    let mut furthest: Option<(u64, u64)> = None;
+    for (start, end, id) in extents {
+        match furthest {
+            Some((furthest_end, owner)) if start < furthest_end => {
+                report.issues.push(Issue::OverlappingExtents { first: owner, second: id, sector: start });
+                if end > furthest_end {
+                    furthest = Some((end, id));
+                }
+            }
+            _ => furthest = Some((end, id)),
+        }
+    }
+}
+
+/// Checks that the keys of a schema point to rows that were written and don't overlap,
+/// and counts the rows and blobs nothing points to.
+fn check_rows(disk: &CraneDisk, slot: u64, manager: &DataManager, report: &mut CheckReport) {
+    let schema = manager.get_schema();
+    let fixed_len = schema.fixed_len();
+    let has_blobs = schema.types.iter().any(|t| matches!(t, DataValue::Blob(_)));
+    let overflow_type = PartitionType::new(PartitionKind::Overflow, slot).raw();
+    // The bytes written to each of the schema's overflow partitions
+    let blobs: HashMap<u64, u64> = disk.get_partition_by_type(overflow_type).iter()
+        .map(|p| {
+            let p = p.read().unwrap();
+            (p.id(), p.initialized_len)
+        })
+        .collect();
+    let initialized: HashMap<u64, u64> = manager.get_data_partitions().iter()
+        .map(|p| {
+            let p = p.read().unwrap();
+            (p.id(), p.initialized_len)
+        })
+        .collect();
+    // The first key pointing to each row and the row's length, by partition and offset
+    let mut rows: HashMap<u64, BTreeMap<u64, (u64, u64)>> = HashMap::new();
+    let mut referenced: HashSet<u64> = HashSet::new();
+
+    let tree = manager.get_tree().read().unwrap();
+    for (key, position) in tree.tree.iter() {
+        report.keys += 1;
+        let initialized_len = match initialized.get(&position.partition) {
+            Some(initialized_len) => *initialized_len,
+            None => {
+                report.issues.push(Issue::ForeignPosition { schema: slot, key: *key, partition: position.partition });
+                continue;
+            }
+        };
+
+        let len = match fixed_len {
+            Some(len) => {
+                if len > 0 && position.offset % len != 0 {
+                    report.issues.push(Issue::MisalignedPosition { schema: slot, key: *key, offset: position.offset });
+                }
+                len
+            }
+            // Rows of variable length start with their length, which has to be written to be read
+            None if position.offset + ROW_PREFIX_LEN > initialized_len => ROW_PREFIX_LEN,
+            None => {
+                let partition = manager.get_data_partitions().iter().find(|p| p.read().unwrap().id() == position.partition)
+                    .expect("Only positions in the schema's data partitions get here");
+                match row_len_at(partition, position.offset, schema) {
+                    Ok(len) => len,
+                    Err(err) => {
+                        report.issues.push(Issue::UnreadableRow { schema: slot, key: *key, error: err.to_string() });
+                        continue;
+                    }
+                }
+            }
+        };
+        if position.offset + len > initialized_len {
+            report.issues.push(Issue::PositionOutOfBounds { schema: slot, key: *key, offset: position.offset, initialized_len });
+            continue;
+        }
+        if has_blobs {
+            let partition = manager.get_data_partitions().iter().find(|p| p.read().unwrap().id() == position.partition)
+                .expect("Only positions in the schema's data partitions get here");
+            let values = read_row(partition, position.offset, schema)
+                .and_then(|row| Ok(schema.parse_row(&mut Buffer::new(row))?));
+            match values {
+                Ok(values) => {
+                    for value in values.iter() {
+                        if let DataValue::Blob(Blob::Overflow(blob)) = value {
+                            referenced.insert(blob.partition);
+                            if blobs.get(&blob.partition).is_none_or(|written| *written < blob.len) {
+                                report.issues.push(Issue::DanglingBlob { schema: slot, key: *key, partition: blob.partition });
+                            }
+                        }
+                    }
+                }
+                Err(err) => report.issues.push(Issue::UnreadableRow { schema: slot, key: *key, error: err.to_string() }),
+            }
+        }
+        rows.entry(position.partition).or_default().entry(position.offset).or_insert((*key, len));
+    }
+
+    for (partition, initialized_len) in initialized.iter() {
+        let offsets = rows.remove(partition).unwrap_or_default();
+
+        let mut previous: Option<(u64, u64, u64)> = None;
+        for (offset, (key, len)) in offsets.iter() {
+            if let Some((previous_offset, previous_key, previous_len)) = previous {
+                if previous_offset + previous_len > *offset {
+                    report.issues.push(Issue::OverlappingRows { schema: slot, partition: *partition, first_key: previous_key, second_key: *key });
+                }
+            }
+            previous = Some((*offset, *key, *len));
+        }
+
+        // Rows of variable length aren't laid out in slots, so only fixed length ones can be
+        // counted. Inserts never hand out the first slot of a partition, so it isn't counted
+        let slots = fixed_len.and_then(|len| initialized_len.checked_div(len).map(|slots| (len, slots)));
+        if let Some((len, slots)) = slots {
+            let orphaned = (1..slots).filter(|i| !offsets.contains_key(&(i*len))).count() as u64;
+            if orphaned > 0 {
+                report.issues.push(Issue::OrphanedRows { schema: slot, partition: *partition, rows: orphaned });
+            }
+        }
+    }
+
+    let mut orphaned: Vec<u64> = blobs.keys().filter(|p| !referenced.contains(p)).cloned().collect();
+    orphaned.sort_unstable();
+    for partition in orphaned {
+        report.issues.push(Issue::OrphanedBlob { schema: slot, partition });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{cfs::{BlobRef, CranePartition, CraneSchema, MemoryStorage}, db::{DataError, data_command::{InsertValueCommand, RemoveValueCommand}}};
+
+    use super::*;
+
+    fn gen_schema() -> CraneSchema {
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Int32(0)]);
+        schema.names = vec!["Id".to_owned(), "Count".to_owned()];
+        schema
+    }
+
+    fn insert(manager: &mut DataManager, count: u64) {
+        for i in 0..count {
+            let mut command = InsertValueCommand::new(vec![DataValue::UInt64(i), DataValue::Int32(i as i32)]);
+            manager.execute(&mut command).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_clean_disk() {
+        let mut crane = Crane::new(CraneDisk::in_memory());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+        for i in 0..5 {
+            let mut command = InsertValueCommand::new(vec![DataValue::UInt64(i), DataValue::Int32(7)]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+
+        let report = crane.check();
+        assert_eq!(report.issues, vec![]);
+        assert_eq!(report.schemas, 1);
+        assert_eq!(report.keys, 5);
+        assert_eq!(report.partitions, 3);
+    }
+
+    #[test]
+    fn test_bad_positions() {
+        let mut disk = CraneDisk::in_memory();
+        let mut manager = DataManager::create_to_disk(&mut disk, 0, gen_schema()).unwrap();
+        insert(&mut manager, 4);
+        manager.execute(&mut RemoveValueCommand::new(2)).unwrap();
+
+        let len = manager.get_schema().fixed_len().unwrap();
+        let data = manager.get_data_partitions()[0].read().unwrap().id();
+        {
+            let mut tree = manager.get_tree().write().unwrap();
+            tree.insert(10, data, len + 3);
+            tree.insert(11, 999, 0);
+            tree.insert(12, data, 40*len);
+        }
+        manager.save().unwrap();
+        disk.save().unwrap();
+
+        let report = check_disk(&disk);
+        assert!(!report.is_clean());
+        assert_eq!(report.issues, vec![
+            Issue::MisalignedPosition { schema: 0, key: 10, offset: len + 3 },
+            Issue::ForeignPosition { schema: 0, key: 11, partition: 999 },
+            Issue::PositionOutOfBounds { schema: 0, key: 12, offset: 40*len, initialized_len: 5*len },
+            Issue::OverlappingRows { schema: 0, partition: data, first_key: 1, second_key: 10 },
+            Issue::OrphanedRows { schema: 0, partition: data, rows: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_dangling_blobs() {
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Blob(Blob::Inline(vec![]))]);
+        schema.names = vec!["Id".to_owned(), "Payload".to_owned()];
+        let mut crane = Crane::new(CraneDisk::in_memory());
+        let slot = crane.add_schema(schema).unwrap();
+        let mut writer = crane.blob_writer(slot).unwrap();
+        writer.append(&[5; 3000]).unwrap();
+        let blob = writer.finish().unwrap();
+
+        let blobs = [blob, BlobRef { len: 3001, ..blob }, BlobRef { partition: 2, len: 1 }];
+        for (i, blob) in blobs.iter().enumerate() {
+            let mut command = InsertValueCommand::new(vec![DataValue::UInt64(i as u64), DataValue::Blob(Blob::Overflow(*blob))]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+
+        let report = crane.check();
+        assert_eq!(report.issues, vec![
+            Issue::DanglingBlob { schema: 0, key: 2, partition: blob.partition },
+            Issue::DanglingBlob { schema: 0, key: 3, partition: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_orphaned_blobs() {
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Blob(Blob::Inline(vec![]))]);
+        schema.names = vec!["Id".to_owned(), "Payload".to_owned()];
+        let mut crane = Crane::new(CraneDisk::in_memory());
+        let slot = crane.add_schema(schema).unwrap();
+
+        let mut writer = crane.blob_writer(slot).unwrap();
+        writer.append(&[5; 3000]).unwrap();
+        let stored = writer.finish().unwrap();
+        let mut writer = crane.blob_writer(slot).unwrap();
+        writer.append(&[6; 3000]).unwrap();
+        let unstored = writer.finish().unwrap();
+        let mut command = InsertValueCommand::new(vec![DataValue::UInt64(0), DataValue::Blob(Blob::Overflow(stored))]);
+        crane.execute(slot, &mut command).unwrap();
+
+        // A writer dropped before it finished leaves its partition behind, uncommitted
+        let mut writer = crane.blob_writer(slot).unwrap();
+        writer.append(&[7; 10]).unwrap();
+        drop(writer);
+        assert!(matches!(crane.blob_writer(slot), Err(DataError::Disk(FSError::UncommittedWrites))));
+        crane.save().unwrap();
+        let dropped = unstored.partition + 1;
+
+        let report = crane.check();
+        assert!(report.is_clean());
+        assert_eq!(report.issues, vec![
+            Issue::OrphanedBlob { schema: 0, partition: unstored.partition },
+            Issue::OrphanedBlob { schema: 0, partition: dropped },
+        ]);
+    }
+
+    #[test]
+    fn test_overlapping_extents() {
+        let mut disk = CraneDisk::in_memory();
+        let first = disk.append_partition(4, 0).unwrap();
+        disk.append_partition(4, 0).unwrap();
+        disk.save().unwrap();
+
+        let offset = disk.get_partition_with_id(first).unwrap().read().unwrap().offset();
+        let (len, sl) = (disk.len(), disk.sector_length());
+        let storage = Arc::downgrade(&disk.storage());
+        disk.partitions[1] = Arc::new(RwLock::new(CranePartition::new(2, offset + 2, 4, 0, sl, storage.clone())));
+        disk.partitions.push(Arc::new(RwLock::new(CranePartition::new(3, len, 4, 0, sl, storage))));
+
+        let report = check_disk(&disk);
+        assert_eq!(report.issues, vec![
+            Issue::ExtentOutOfBounds { partition: 3, start: len, end: len + 4, disk_len: len },
+            Issue::OverlappingExtents { first, second: 2, sector: offset + 2 },
+        ]);
+    }
+
+    /// Keeps a copy of the disk as it was when a batch was first written to the write ahead log
+    /// after it was armed, as if the process stopped before applying it
+    struct StopAfterLog {
+        inner: MemoryStorage,
+        armed: Arc<RwLock<bool>>,
+        copy: Arc<RwLock<Option<Vec<u8>>>>,
+    }
+
+    impl Storage for StopAfterLog {
+        fn read_at(&mut self, byte: u64, len: u64) -> Result<Vec<u8>, FSError> {
+            self.inner.read_at(byte, len)
+        }
+
+        fn write_at(&mut self, byte: u64, bytes: &[u8]) -> Result<(), FSError> {
+            self.inner.write_at(byte, bytes)?;
+            let mut copy = self.copy.write().unwrap();
+            if *self.armed.read().unwrap() && copy.is_none() && bytes.starts_with(b"CRANEWAL") {
+                *copy = Some(self.inner.read_at(0, self.inner.len()?)?);
+            }
+            Ok(())
+        }
+
+        fn len(&self) -> Result<u64, FSError> {
+            self.inner.len()
+        }
+
+        fn sync(&mut self) -> Result<(), FSError> {
+            Ok(())
+        }
+
+        fn truncate(&mut self, len: u64) -> Result<(), FSError> {
+            self.inner.truncate(len)
+        }
+    }
+
+    #[test]
+    fn test_unapplied_log() {
+        let (armed, copy) = (Arc::new(RwLock::new(false)), Arc::new(RwLock::new(None)));
+        let storage = StopAfterLog { inner: MemoryStorage::new(), armed: armed.clone(), copy: copy.clone() };
+        let mut crane = Crane::new(CraneDisk::init_storage(Arc::new(RwLock::new(storage))).unwrap());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+        *armed.write().unwrap() = true;
+        let mut command = InsertValueCommand::new(vec![DataValue::UInt64(1), DataValue::Int32(7)]);
+        crane.execute(slot, &mut command).unwrap();
+
+        let path = "./test/crane/unapplied_log.cdb";
+        std::fs::write(path, copy.read().unwrap().as_ref().unwrap()).unwrap();
+        let report = check_file(path).unwrap();
+        assert_eq!(report.issues, vec![Issue::UnappliedLog]);
+        assert!(report.is_clean());
+
+        // Opening the disk for writing replays the batch
+        drop(CraneDisk::open(path, OpenOptions { create: false, ..Default::default() }).unwrap());
+        let report = check_file(path).unwrap();
+        assert_eq!(report.issues, vec![]);
+        assert_eq!(report.keys, 1);
+    }
+
+    #[test]
+    fn test_unreadable_storage() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::from_bytes(vec![1u8; 4096])));
+        let report = check_storage(storage);
+
+        assert!(!report.is_clean());
+        assert!(matches!(report.issues[..], [Issue::Unreadable(_)]));
+    }
+}
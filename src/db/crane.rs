@@ -1,40 +1,285 @@
-use std::{cell::RefCell, process::Command, rc::Rc, vec};
+use std::{ffi::OsString, fs::{self, File}, mem, path::{Path, PathBuf}, sync::{Arc, RwLock}, vec};
 
-use crate::cfs::{CraneDisk, CranePartition, CraneSchema};
+use crate::cfs::{BlobReader, BlobRef, BlobWriter, CraneDisk, CranePartition, CraneSchema, FREE_PARTITION_TYPE, FSError, FileStorage, MemoryStorage, OpenOptions, PartitionKind, PartitionType, Storage};
 
-use super::{DataError, data_command::DataCommand, data_manager::{DataManager, OFFSET}};
+use super::{DataError, check::{CheckReport, check_disk}, data_command::DataCommand, data_manager::DataManager};
 
-type Partition = Rc<RefCell<CranePartition>>;
+type Partition = Arc<RwLock<CranePartition>>;
+
+/// How many sectors a table's data partition grows by when it runs out of room
+const DATA_GROWTH_SECTORS: u64 = 16;
 
 pub struct Crane {
     disk: CraneDisk,
-    managers: Vec<DataManager>
+    managers: Vec<DataManager>,
+    /// The path and options the database was opened with, if it was opened by path
+    file: Option<(PathBuf, OpenOptions)>,
+}
+
+/// What compacting a disk did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactionReport {
+    /// The byte length of the disk before compacting
+    pub bytes_before: u64,
+    /// The byte length of the compacted disk
+    pub bytes_after: u64,
+    /// The number of values copied over
+    pub values: u64,
+    /// The number of partitions in use before compacting
+    pub partitions_before: u64,
+    /// The number of partitions in use after compacting
+    pub partitions_after: u64,
+}
+
+impl CompactionReport {
+    /// The number of bytes the compaction freed up.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
 }
 
 impl Crane {
-    pub fn from_disk(disk: CraneDisk) -> Self {
+    /// Opens the database at a path, creating it if it doesn't exist. The file stays locked
+    /// exclusively until the database is dropped.
+    /// # Arguments
+    /// * `path` - The path of the database file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DataError> {
+        Self::open_with_options(path, OpenOptions::default())
+    }
+
+    /// Opens the database at a path, see `CraneDisk::open`.
+    /// # Arguments
+    /// * `path` - The path of the database file.
+    /// * `options` - Whether to create the database, whether to only read it and how to lay it out.
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: OpenOptions) -> Result<Self, DataError> {
+        let mut crane = Self::from_disk(CraneDisk::open(path.as_ref(), options.clone())?)?;
+        crane.file = Some((path.as_ref().to_path_buf(), options));
+        Ok(crane)
+    }
+
+    pub fn from_disk(disk: CraneDisk) -> Result<Self, DataError> {
         let mut res = Self::new(disk);
-        Self::generate_schemas(&mut res);
+        Self::generate_schemas(&mut res)?;
 
-        res
+        Ok(res)
     }
 
-    pub fn add_schema(&mut self, schema: CraneSchema) -> u64 {
+    pub fn add_schema(&mut self, schema: CraneSchema) -> Result<u64, DataError> {
         let slot = self.schema_count();
-        self.managers.push(
-            DataManager::create_to_disk(&mut self.disk, slot, schema)
-        );
+        self.disk.begin_command();
+        match DataManager::create_to_disk(&mut self.disk, slot, schema) {
+            Ok(manager) => self.managers.push(manager),
+            Err(err) => {
+                self.discard()?;
+                return Err(err);
+            }
+        }
 
-        self.save();
+        self.save()?;
 
-        slot
+        Ok(slot)
     }
 
-    pub fn save(&mut self) {
+    pub fn save(&mut self) -> Result<(), DataError> {
         for manager in &mut self.managers {
-            manager.save();
+            manager.save()?;
+        }
+        let saved = self.disk.save();
+        self.reload_if_rolled_back(saved)
+    }
+
+    /// Forces every saved value through to stable storage, for example before shutting down a
+    /// database whose durability only syncs now and then.
+    pub fn sync(&mut self) -> Result<(), DataError> {
+        self.disk.sync()?;
+        Ok(())
+    }
+
+    /// Commits the writes of a command, syncing them as the durability of the disk asks.
+    fn commit(&mut self) -> Result<(), DataError> {
+        if let Err(err) = self.managers.iter_mut().try_for_each(|m| m.save()) {
+            self.discard()?;
+            return Err(err);
+        }
+        let committed = self.disk.commit();
+        self.reload_if_rolled_back(committed)
+    }
+
+    /// Drops the writes of the command in flight and reloads the schemas as they were at the
+    /// last commit.
+    fn discard(&mut self) -> Result<(), DataError> {
+        self.disk.discard()?;
+        self.managers.clear();
+        Self::generate_schemas(self)
+    }
+
+    /// Reloads the schemas from the disk if the batch they wrote didn't fit in the write ahead
+    /// log and was rolled back, so they match the disk again.
+    fn reload_if_rolled_back(&mut self, committed: Result<(), FSError>) -> Result<(), DataError> {
+        if let Err(FSError::LogFull { .. }) = committed {
+            self.managers.clear();
+            Self::generate_schemas(self)?;
         }
-        self.disk.save();
+        Ok(committed?)
+    }
+
+    /// Writes a compacted copy of the disk to an empty storage, for example a fresh file that then
+    /// replaces the old one.
+    ///
+    /// Only live values are copied, packed in key order into one data partition per schema, keys
+    /// stay the same. Blobs no value refers to are dropped, the others get new partition ids in
+    /// the copy. Released extents are dropped and every partition ends up in a single extent.
+    /// # Arguments
+    /// * `target` - The empty storage to write the copy to.
+    pub fn compact_to(&mut self, target: Arc<RwLock<dyn Storage>>) -> Result<CompactionReport, DataError> {
+        self.save()?;
+        let mut disk = CraneDisk::init_storage_with_key(target.clone(), self.disk.options(), self.disk.key())?;
+        let mut values = 0;
+
+        for (slot, manager) in self.managers.iter().enumerate() {
+            let (_, copied) = manager.compact_into(&self.disk, &mut disk, slot as u64)?;
+            values += copied;
+        }
+
+        // Partitions that don't belong to a schema are copied as they are
+        let schemas = self.managers.len() as u64;
+        for partition in self.disk.partitions.iter() {
+            let t = PartitionType::from_raw(partition.read().unwrap().partition_type);
+            let skipped = match t.kind {
+                PartitionKind::Free => true,
+                // Blobs are copied by their schema if a value still refers to them
+                PartitionKind::Schema | PartitionKind::Index | PartitionKind::Data | PartitionKind::Overflow => t.owner < schemas,
+                _ => false,
+            };
+            if !skipped {
+                disk.copy_partition(&mut partition.write().unwrap())?;
+            }
+        }
+        disk.save()?;
+
+        let in_use = |d: &CraneDisk| d.partitions.iter().filter(|p| p.read().unwrap().partition_type != FREE_PARTITION_TYPE).count() as u64;
+        let bytes_after = target.read().unwrap().len()?;
+        Ok(CompactionReport {
+            bytes_before: self.disk.storage().read().unwrap().len()?,
+            bytes_after,
+            values,
+            partitions_before: in_use(&self.disk),
+            partitions_after: in_use(&disk),
+        })
+    }
+
+    /// Compacts the disk, see `compact_to`, and reopens the compacted copy in its place.
+    ///
+    /// A database opened by path is compacted into a file next to it, which is synced and then
+    /// renamed over the old file, so a crash leaves either the old or the compacted disk. Other
+    /// disks are compacted in memory and then written over their storage, a crash while it is
+    /// being written loses the disk, use `compact_to` with a fresh file to stay safe.
+    pub fn compact(&mut self) -> Result<CompactionReport, DataError> {
+        match self.file.clone() {
+            Some((path, options)) => self.compact_file(&path, options),
+            None => self.compact_storage(),
+        }
+    }
+
+    /// Compacts a database opened by path through a file next to it that replaces it.
+    /// # Arguments
+    /// * `path` - The path of the database file.
+    /// * `options` - The options it was opened with, to reopen it with.
+    fn compact_file(&mut self, path: &Path, options: OpenOptions) -> Result<CompactionReport, DataError> {
+        let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+        name.push(".compact");
+        let temp = path.with_file_name(name);
+
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&temp).map_err(FSError::from)?;
+        let image: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(FileStorage::from_file(file)?));
+        let report = self.compact_to(image.clone()).and_then(|report| {
+            image.write().unwrap().sync()?;
+            Ok(report)
+        });
+        drop(image);
+        let report = match report {
+            Ok(report) => report,
+            Err(err) => {
+                let _ = fs::remove_file(&temp);
+                return Err(err);
+            }
+        };
+
+        // The old disk is closed before its file is replaced, so nothing it still holds is written over the copy
+        let durability = self.disk.durability();
+        drop(mem::replace(self, Self::new(CraneDisk::in_memory())));
+        let renamed = fs::rename(&temp, path).and_then(|_| {
+            let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+            File::open(dir)?.sync_all()
+        });
+
+        // Reopened even if the rename failed, the old file is still whole then
+        *self = Self::open_with_options(path, options)?;
+        self.disk.set_durability(durability);
+        renamed.map_err(FSError::from)?;
+        Ok(report)
+    }
+
+    /// Compacts a database that wasn't opened by path by writing the compacted copy over its storage.
+    fn compact_storage(&mut self) -> Result<CompactionReport, DataError> {
+        let image: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let report = self.compact_to(image.clone())?;
+
+        let backing = self.disk.storage();
+        let key = self.disk.key().cloned();
+        let durability = self.disk.durability();
+        // The old disk is closed before its storage is written over, so nothing it still holds is written over the copy
+        drop(mem::replace(self, Self::new(CraneDisk::in_memory())));
+        {
+            let mut image = image.write().unwrap();
+            let bytes = image.read_at(0, report.bytes_after)?;
+            let mut backing = backing.write().unwrap();
+
+            backing.write_at(0, &bytes)?;
+            backing.truncate(report.bytes_after)?;
+            backing.sync()?;
+        }
+
+        *self = Self::from_disk(CraneDisk::from_storage_with_key(backing, key.as_ref())?)?;
+        self.disk.set_durability(durability);
+        Ok(report)
+    }
+
+    /// Starts streaming a blob of a schema into an overflow partition of its own. The reference
+    /// the writer finishes with is stored in a row as `Blob::Overflow`.
+    ///
+    /// Every blob takes up a partition, so an entry of the partition map and at least a few
+    /// sectors, blobs short enough to be stored inline are cheaper kept in their row.
+    /// Blobs are only dropped when the disk is compacted and no row refers to them anymore.
+    /// # Arguments
+    /// * `schema_slot` - The slot of the schema the blob belongs to.
+    pub fn blob_writer(&mut self, schema_slot: u64) -> Result<BlobWriter<'_>, DataError> {
+        if schema_slot >= self.managers.len() as u64 {
+            return Err(DataError::UnknownSchema(schema_slot));
+        }
+        let partition_type = PartitionType::new(PartitionKind::Overflow, schema_slot).raw();
+        Ok(BlobWriter::new(&mut self.disk, partition_type)?)
+    }
+
+    /// Opens a blob of a schema stored in an overflow partition for streaming.
+    /// # Arguments
+    /// * `schema_slot` - The slot of the schema the blob belongs to.
+    /// * `blob` - The blob, as read from a row.
+    pub fn blob_reader(&self, schema_slot: u64, blob: &BlobRef) -> Result<BlobReader, DataError> {
+        if schema_slot >= self.managers.len() as u64 {
+            return Err(DataError::UnknownSchema(schema_slot));
+        }
+        let partition_type = PartitionType::new(PartitionKind::Overflow, schema_slot).raw();
+        let partition = self.disk.get_partition_with_id(blob.partition).ok()
+            .filter(|p| p.read().unwrap().partition_type == partition_type)
+            .ok_or(DataError::UnknownBlob(blob.partition))?;
+
+        Ok(BlobReader::new(partition.clone(), blob)?)
+    }
+
+    /// Checks the disk and every schema on it for corruption, see `check_disk`.
+    pub fn check(&self) -> CheckReport {
+        check_disk(&self.disk)
     }
 
     pub fn schema_count(&self) -> u64 {
@@ -45,74 +290,85 @@ impl Crane {
         Self {
             disk,
             managers: vec![],
+            file: None,
         }
     }
 
-    fn count_schemas(partitions: &Vec<Partition>) -> u64 {
-        let max_type: u64 = partitions.iter().map(|v| v.borrow().partition_type).max().unwrap_or(0);
-
-        (max_type-OFFSET)/3
+    /// Counts the schema slots in use, slots are given out in order so this is one past the last
+    /// slot owning a schema partition. Partitions of other kinds don't count.
+    pub(super) fn count_schemas(partitions: &[Partition]) -> u64 {
+        partitions.iter()
+            .map(|v| PartitionType::from_raw(v.read().unwrap().partition_type))
+            .filter(|t| t.kind == PartitionKind::Schema)
+            .map(|t| t.owner + 1)
+            .max()
+            .unwrap_or(0)
     }
 
     pub fn execute(&mut self, schema_slot: u64, command: &mut dyn DataCommand) -> Result<(), DataError> {
-        let res = self.managers[schema_slot as usize].execute(command);
-        match res {
-            Ok(()) => {
-                self.save();
-                Ok(())
-            },
+        if schema_slot >= self.managers.len() as u64 {
+            return Err(DataError::UnknownSchema(schema_slot));
+        }
+        match self.execute_no_recur(schema_slot, command) {
             Err(DataError::OutOfStorage) => {
-                let data_type = schema_slot*3 + 3;
-                self.disk.append_partition(16, data_type);
-                Self::generate_schemas(self);
-                self.execute_no_recur(schema_slot, command)
+                // Grow the table's data partition in place, its positions stay valid
+                let (id, mut sectors) = {
+                    let manager = &self.managers[schema_slot as usize];
+                    let partition = manager.get_data_partitions().last()
+                        .ok_or_else(|| DataError::CorruptMetadata(format!("schema {} has no data partition", schema_slot)))?;
+                    let value_sectors = manager.get_schema().fixed_len().unwrap_or(0).div_ceil(self.disk.sector_length());
+                    (partition.read().unwrap().id(), u64::max(DATA_GROWTH_SECTORS, value_sectors))
+                };
+                // Rows with variable length columns can need more room than a single step gives
+                loop {
+                    self.disk.grow_partition(id, sectors)?;
+                    match self.execute_no_recur(schema_slot, command) {
+                        Err(DataError::OutOfStorage) => sectors *= 2,
+                        res => return res,
+                    }
+                }
             }
-            Err(err) => Err(err),
+            res => res,
         }
     }
 
+    /// Runs a command once as a command of the disk, a command that fails is discarded so the
+    /// partitions can be grown outside of it.
     fn execute_no_recur(&mut self, schema_slot: u64, command: &mut dyn DataCommand) -> Result<(), DataError> {
-        let res = self.managers[schema_slot as usize].execute(command);
-        if res.is_ok() {
-            self.save();
+        self.managers[schema_slot as usize].grow_tree(&mut self.disk)?;
+        self.disk.begin_command();
+        if let Err(err) = self.managers[schema_slot as usize].execute(command) {
+            self.discard()?;
+            return Err(err);
         }
-        res
+        self.commit()
     }
 
-    fn generate_schemas(res: &mut Crane) {
+    fn generate_schemas(res: &mut Crane) -> Result<(), DataError> {
         let schemas = Self::count_schemas(&res.disk.partitions);
         for i in 0..schemas {
             res.managers.push(
-                DataManager::from_disk(&res.disk, i)
+                DataManager::from_disk(&res.disk, i)?
             );
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs::{File, OpenOptions};
+    use std::io::Read;
 
-    use crate::{cfs::{CraneDisk, DataValue}, db::data_command::{GetKeyCommand, InsertValueCommand}};
+    use crate::{cfs::{Blob, CraneDisk, DataValue, DiskOptions, MemoryStorage, Reader, Storage, Writer}, db::data_command::{GetKeyCommand, InsertValueCommand, RemoveValueCommand, UpdateValueCommand}};
 
     use super::*;
 
     fn generate_disk() -> CraneDisk {
-        let write = File::create("test/crane/db.cdb").unwrap();
-        let read = File::open("test/crane/db.cdb").unwrap();
-
-        let crane = CraneDisk::init_file(read, write);
-
-        crane
+        CraneDisk::in_memory()
     }
 
-    fn load_disk() -> CraneDisk {
-        let read = File::open("test/crane/db.cdb").unwrap();
-        let write = OpenOptions::new().write(true).open("test/crane/db.cdb").unwrap();
-
-        let crane = CraneDisk::from_file(read, write);
-
-        crane
+    fn load_disk(storage: Arc<RwLock<dyn Storage>>) -> CraneDisk {
+        CraneDisk::from_storage(storage).unwrap()
     }
 
     fn gen_schema() -> CraneSchema {
@@ -131,13 +387,12 @@ mod test {
         schema
     }
 
-    #[test]
-    fn test_create_crane() {
+    fn create_crane() -> Arc<RwLock<dyn Storage>> {
         let disk = generate_disk();
 
         let mut crane = Crane::new(disk);
 
-        let slot = crane.add_schema(gen_schema());
+        let slot = crane.add_schema(gen_schema()).unwrap();
 
         let mut command = InsertValueCommand::new(vec![
             DataValue::UInt64(21),
@@ -150,15 +405,20 @@ mod test {
         crane.execute(slot, &mut command).unwrap();
         crane.execute(slot, &mut command).unwrap();
 
-        crane.save();
+        crane.save().unwrap();
+        crane.disk.storage()
+    }
+
+    #[test]
+    fn test_create_crane() {
+        create_crane();
     }
 
     #[test]
     fn test_load_crane() {
-        test_create_crane();
-        let disk = load_disk();
+        let disk = load_disk(create_crane());
 
-        let mut crane = Crane::from_disk(disk);
+        let mut crane = Crane::from_disk(disk).unwrap();
 
         let mut command = GetKeyCommand::new(1);
 
@@ -168,4 +428,398 @@ mod test {
 
         assert_ne!(res, None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_open_crane() {
+        let path = "./test/crane/open.cdb";
+        let _ = std::fs::remove_file(path);
+
+        let mut crane = Crane::open(path).unwrap();
+        let slot = crane.add_schema(gen_schema()).unwrap();
+        let mut command = InsertValueCommand::new(vec![
+            DataValue::UInt64(7),
+            DataValue::Int16(-5),
+            DataValue::Fixchar("Hello world".to_owned(), 64),
+        ]);
+        crane.execute(slot, &mut command).unwrap();
+
+        assert!(matches!(Crane::open(path), Err(DataError::Disk(crate::cfs::FSError::Locked(_)))));
+        drop(crane);
+
+        let mut crane = Crane::open(path).unwrap();
+        let mut command = GetKeyCommand::new(1);
+        crane.execute(slot, &mut command).unwrap();
+
+        assert_eq!(crane.schema_count(), 1);
+        assert_ne!(command.get_result(), None);
+    }
+
+    #[test]
+    fn test_unknown_schema() {
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+
+        let mut command = GetKeyCommand::new(1);
+        assert!(matches!(crane.execute(slot + 1, &mut command), Err(DataError::UnknownSchema(s)) if s == slot + 1));
+        crane.execute(slot, &mut command).unwrap();
+        assert_eq!(command.get_result(), None);
+    }
+
+    #[test]
+    fn test_other_partition_kinds() {
+        let mut crane = Crane::new(generate_disk());
+        crane.add_schema(gen_schema()).unwrap();
+        crane.disk.append_partition(4, PartitionType::new(PartitionKind::SecondaryIndex, 9).raw()).unwrap();
+        crane.disk.append_partition(4, PartitionType::new(PartitionKind::Other(40), 0).raw()).unwrap();
+        crane.save().unwrap();
+
+        let crane = Crane::from_disk(load_disk(crane.disk.storage())).unwrap();
+        assert_eq!(crane.schema_count(), 1);
+    }
+
+    #[test]
+    fn test_grow_data_partition() {
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+
+        for i in 0..80 {
+            let mut command = InsertValueCommand::new(vec![
+                DataValue::UInt64(i),
+                DataValue::Int16(-5),
+                DataValue::Fixchar("Hello world".to_owned(), 64),
+            ]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+
+        let disk = load_disk(crane.disk.storage());
+        assert_eq!(disk.get_partition_by_type(PartitionType::new(PartitionKind::Data, slot).raw()).len(), 1);
+
+        let mut crane = Crane::from_disk(disk).unwrap();
+        for key in [1, 50, 80].iter() {
+            let mut command = GetKeyCommand::new(*key);
+            crane.execute(0, &mut command).unwrap();
+
+            assert_eq!(command.get_result().unwrap()[0], DataValue::UInt64(key - 1));
+        }
+    }
+
+    #[test]
+    fn test_grow_tree_partition() {
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+        let tree_type = PartitionType::new(PartitionKind::Index, slot).raw();
+        let tree_len = crane.disk.get_partition_by_type(tree_type)[0].read().unwrap().total_len();
+
+        let keys = 3*tree_len*crane.disk.sector_length()/24;
+        for i in 0..keys {
+            let mut command = InsertValueCommand::new(vec![
+                DataValue::UInt64(i),
+                DataValue::Int16(-5),
+                DataValue::Fixchar("Hello world".to_owned(), 64),
+            ]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+
+        let disk = load_disk(crane.disk.storage());
+        assert!(disk.get_partition_by_type(tree_type)[0].read().unwrap().total_len() > 2*tree_len);
+        let mut crane = Crane::from_disk(disk).unwrap();
+        for key in [1, keys/2, keys].iter() {
+            assert_eq!(get_value(&mut crane, *key).unwrap()[0], DataValue::UInt64(key - 1));
+        }
+        assert!(crane.check().issues.is_empty());
+    }
+
+    #[test]
+    fn test_tree_larger_than_log() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut crane = Crane::new(CraneDisk::init_storage_with_options(storage.clone(), DiskOptions {
+            sector_length: 128,
+            ..DiskOptions::default()
+        }).unwrap());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+
+        // The write ahead log of a disk with 128 byte sectors holds about 1300 tree entries
+        for i in 0..1500 {
+            let mut command = InsertValueCommand::new(vec![
+                DataValue::UInt64(i),
+                DataValue::Int16(-5),
+                DataValue::Fixchar("Hello world".to_owned(), 64),
+            ]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+        for key in 1..=100 {
+            crane.execute(slot, &mut RemoveValueCommand::new(key)).unwrap();
+        }
+
+        let mut crane = Crane::from_disk(load_disk(storage)).unwrap();
+        assert_eq!(get_value(&mut crane, 100), None);
+        for key in [101, 800, 1500].iter() {
+            assert_eq!(get_value(&mut crane, *key).unwrap()[0], DataValue::UInt64(key - 1));
+        }
+
+        let report = crane.compact().unwrap();
+        assert_eq!(report.values, 1400);
+        assert_eq!(get_value(&mut crane, 1500).unwrap()[0], DataValue::UInt64(1499));
+        assert!(crane.check().issues.is_empty());
+    }
+
+    fn sparse_crane() -> Crane {
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+
+        for i in 0..60 {
+            let mut command = InsertValueCommand::new(vec![
+                DataValue::UInt64(i),
+                DataValue::Int16(-5),
+                DataValue::Fixchar("Hello world".to_owned(), 64),
+            ]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+        for key in 1..=40 {
+            crane.execute(slot, &mut RemoveValueCommand::new(key)).unwrap();
+        }
+
+        crane
+    }
+
+    fn get_value(crane: &mut Crane, key: u64) -> Option<Vec<DataValue>> {
+        let mut command = GetKeyCommand::new(key);
+        crane.execute(0, &mut command).unwrap();
+        command.get_result()
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut crane = sparse_crane();
+        let report = crane.compact().unwrap();
+
+        assert_eq!(report.values, 20);
+        assert!(report.bytes_reclaimed() > 0);
+        assert_eq!(crane.disk.storage().read().unwrap().len().unwrap(), report.bytes_after);
+
+        assert_eq!(get_value(&mut crane, 10), None);
+        assert_eq!(get_value(&mut crane, 41).unwrap()[0], DataValue::UInt64(40));
+        assert_eq!(get_value(&mut crane, 60).unwrap()[0], DataValue::UInt64(59));
+
+        let mut command = InsertValueCommand::new(vec![
+            DataValue::UInt64(100),
+            DataValue::Int16(-5),
+            DataValue::Fixchar("Hello world".to_owned(), 64),
+        ]);
+        crane.execute(0, &mut command).unwrap();
+        assert_eq!(get_value(&mut crane, 41).unwrap()[0], DataValue::UInt64(40));
+        assert_eq!(get_value(&mut crane, 61).unwrap()[0], DataValue::UInt64(100));
+    }
+
+    #[test]
+    fn test_compact_file() {
+        let path = "./test/crane/compact.cdb";
+        let _ = std::fs::remove_file(path);
+
+        let mut crane = Crane::open(path).unwrap();
+        let slot = crane.add_schema(gen_schema()).unwrap();
+        for i in 0..60 {
+            let mut command = InsertValueCommand::new(vec![
+                DataValue::UInt64(i),
+                DataValue::Int16(-5),
+                DataValue::Fixchar("Hello world".to_owned(), 64),
+            ]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+        for key in 1..=40 {
+            crane.execute(slot, &mut RemoveValueCommand::new(key)).unwrap();
+        }
+
+        let report = crane.compact().unwrap();
+        assert_eq!(report.values, 20);
+        assert_eq!(std::fs::metadata(path).unwrap().len(), report.bytes_after);
+        assert!(!Path::new("./test/crane/compact.cdb.compact").exists());
+        // The compacted file is locked like the old one was
+        assert!(matches!(Crane::open(path), Err(DataError::Disk(FSError::Locked(_)))));
+        assert_eq!(get_value(&mut crane, 41).unwrap()[0], DataValue::UInt64(40));
+        drop(crane);
+
+        let mut crane = Crane::open(path).unwrap();
+        assert_eq!(get_value(&mut crane, 10), None);
+        assert_eq!(get_value(&mut crane, 60).unwrap()[0], DataValue::UInt64(59));
+        assert!(crane.check().issues.is_empty());
+    }
+
+    #[test]
+    fn test_compact_to() {
+        let mut crane = sparse_crane();
+        crane.disk.append_partition(4, 0).unwrap();
+        let extra = crane.disk.partitions.len() as u64;
+        crane.disk.get_partition_with_id(extra).unwrap().write().unwrap().write_sectors(1, 0, &[3u8; 8]).unwrap();
+
+        let target: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let report = crane.compact_to(target.clone()).unwrap();
+
+        assert_eq!(report.partitions_before, 4);
+        assert_eq!(report.partitions_after, 4);
+        assert_eq!(target.read().unwrap().len().unwrap(), report.bytes_after);
+
+        let mut compacted = Crane::from_disk(load_disk(target)).unwrap();
+        assert_eq!(get_value(&mut compacted, 50).unwrap()[0], DataValue::UInt64(49));
+
+        let copied = compacted.disk.get_partition_by_type(0)[0].clone();
+        assert_eq!(copied.write().unwrap().read_sectors(1, 2).unwrap()[..8], [3u8; 8]);
+    }
+
+    #[test]
+    fn test_varchar_values() {
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Varchar(String::new())]);
+        schema.names = vec!["Id".to_owned(), "Notes".to_owned()];
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(schema).unwrap();
+
+        let notes = ["", "short", &"long ".repeat(40), &"x".repeat(20000)];
+        for (i, note) in notes.iter().enumerate() {
+            let mut command = InsertValueCommand::new(vec![DataValue::UInt64(i as u64), DataValue::Varchar(note.to_string())]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+
+        // A longer value doesn't fit where the old one was and mustn't spill into the next row
+        let mut command = UpdateValueCommand::new(2, vec![DataValue::UInt64(1), DataValue::Varchar("no longer short".to_owned())]);
+        crane.execute(slot, &mut command).unwrap();
+        crane.save().unwrap();
+
+        let mut crane = Crane::from_disk(load_disk(crane.disk.storage())).unwrap();
+        assert!(crane.check().is_clean());
+        for (i, note) in notes.iter().enumerate().skip(2) {
+            assert_eq!(get_value(&mut crane, i as u64 + 1).unwrap()[1], DataValue::Varchar(note.to_string()));
+        }
+
+        crane.compact().unwrap();
+        assert_eq!(get_value(&mut crane, 4).unwrap()[1], DataValue::Varchar("x".repeat(20000)));
+        assert_eq!(get_value(&mut crane, 3).unwrap()[1], DataValue::Varchar("long ".repeat(40)));
+    }
+
+    #[test]
+    fn test_row_too_long() {
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Varchar(String::new())]);
+        schema.names = vec!["Id".to_owned(), "Notes".to_owned()];
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(schema).unwrap();
+
+        // The row wouldn't fit in the write ahead log
+        let long = vec![DataValue::UInt64(0), DataValue::Varchar("x".repeat(100000))];
+        let mut command = InsertValueCommand::new(long.clone());
+        assert!(matches!(crane.execute(slot, &mut command), Err(DataError::SchemaMismatch(_))));
+
+        let mut command = InsertValueCommand::new(vec![DataValue::UInt64(1), DataValue::Varchar("short".to_owned())]);
+        crane.execute(slot, &mut command).unwrap();
+        let mut command = UpdateValueCommand::new(1, long);
+        assert!(matches!(crane.execute(slot, &mut command), Err(DataError::SchemaMismatch(_))));
+        crane.save().unwrap();
+
+        let mut crane = Crane::from_disk(load_disk(crane.disk.storage())).unwrap();
+        assert!(crane.check().is_clean());
+        assert_eq!(get_value(&mut crane, 1).unwrap()[1], DataValue::Varchar("short".to_owned()));
+    }
+
+    /// Streams a blob into a schema's overflow partition.
+    fn write_blob(crane: &mut Crane, slot: u64, bytes: &[u8]) -> BlobRef {
+        let mut writer = crane.blob_writer(slot).unwrap();
+        std::io::copy(&mut &bytes[..], &mut writer).unwrap();
+        writer.finish().unwrap()
+    }
+
+    /// Streams a blob of a schema out of its overflow partition.
+    fn read_blob(crane: &Crane, slot: u64, blob: &BlobRef) -> Vec<u8> {
+        let mut bytes = vec![];
+        crane.blob_reader(slot, blob).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_blob_values() {
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Blob(Blob::Inline(vec![]))]);
+        schema.names = vec!["Id".to_owned(), "Thumbnail".to_owned()];
+        let mut crane = Crane::new(generate_disk());
+        let slot = crane.add_schema(schema).unwrap();
+
+        let photo: Vec<u8> = (0..(3*1024*1024u32)).map(|i| (i % 253) as u8).collect();
+        let large = write_blob(&mut crane, slot, &photo);
+        let unused = write_blob(&mut crane, slot, &[1u8; 5000]);
+        assert_eq!(large.len, photo.len() as u64);
+
+        let rows = [Blob::Inline(vec![]), Blob::Inline(vec![9; 100]), Blob::Overflow(large)];
+        for (i, blob) in rows.iter().enumerate() {
+            let mut command = InsertValueCommand::new(vec![DataValue::UInt64(i as u64), DataValue::Blob(blob.clone())]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+        let mut command = InsertValueCommand::new(vec![DataValue::UInt64(9), DataValue::Blob(Blob::Inline(photo[..2000].to_vec()))]);
+        assert!(matches!(crane.execute(slot, &mut command), Err(DataError::SchemaMismatch(_))));
+
+        let mut crane = Crane::from_disk(load_disk(crane.disk.storage())).unwrap();
+        assert!(crane.check().is_clean());
+        assert_eq!(get_value(&mut crane, 2).unwrap()[1], DataValue::Blob(Blob::Inline(vec![9; 100])));
+        assert_eq!(get_value(&mut crane, 3).unwrap()[1], DataValue::Blob(Blob::Overflow(large)));
+        assert_eq!(read_blob(&crane, slot, &large), photo);
+        assert!(matches!(crane.blob_reader(slot, &BlobRef { partition: 1, len: 0 }), Err(DataError::UnknownBlob(1))));
+
+        // Only the blob a row refers to survives compaction
+        let report = crane.compact().unwrap();
+        assert_eq!(report.partitions_after, report.partitions_before - 1);
+        let moved = match &get_value(&mut crane, 3).unwrap()[1] {
+            DataValue::Blob(Blob::Overflow(blob)) => *blob,
+            value => panic!("expected a blob reference, got {:?}", value),
+        };
+        assert_eq!(moved.len, large.len);
+        assert_eq!(read_blob(&crane, slot, &moved), photo);
+        assert!(crane.disk.partitions.iter().all(|p| p.read().unwrap().id() != unused.partition
+            || p.read().unwrap().partition_type == FREE_PARTITION_TYPE));
+        assert!(crane.check().is_clean());
+    }
+
+    #[test]
+    fn test_many_blobs() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut crane = Crane::new(CraneDisk::init_storage_with_options(storage.clone(), DiskOptions {
+            sector_length: 128,
+            ..DiskOptions::default()
+        }).unwrap());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+        assert!(matches!(crane.blob_writer(slot + 1), Err(DataError::UnknownSchema(s)) if s == slot + 1));
+
+        // The write ahead log of a disk with 128 byte sectors holds about a thousand map entries
+        let blobs: Vec<BlobRef> = (0..1100u64).map(|i| write_blob(&mut crane, slot, &i.to_be_bytes())).collect();
+
+        let crane = Crane::from_disk(load_disk(storage)).unwrap();
+        for (i, blob) in blobs.iter().enumerate().step_by(97) {
+            assert_eq!(read_blob(&crane, slot, blob), (i as u64).to_be_bytes());
+        }
+        assert!(matches!(crane.blob_reader(slot + 1, &blobs[0]), Err(DataError::UnknownSchema(_))));
+    }
+
+    #[test]
+    fn test_large_sectors() {
+        let storage: Arc<RwLock<dyn Storage>> = Arc::new(RwLock::new(MemoryStorage::new()));
+        let mut crane = Crane::new(CraneDisk::init_storage_with_options(storage.clone(), DiskOptions {
+            sector_length: 512,
+            ..DiskOptions::default()
+        }).unwrap());
+        let slot = crane.add_schema(gen_schema()).unwrap();
+
+        for i in 0..10 {
+            let mut command = InsertValueCommand::new(vec![
+                DataValue::UInt64(i),
+                DataValue::Int16(-5),
+                DataValue::Fixchar("Hello world".to_owned(), 64),
+            ]);
+            crane.execute(slot, &mut command).unwrap();
+        }
+
+        let disk = load_disk(storage);
+        assert_eq!(disk.sector_length(), 512);
+
+        let mut crane = Crane::from_disk(disk).unwrap();
+        for key in 1..=10 {
+            let mut command = GetKeyCommand::new(key);
+            crane.execute(0, &mut command).unwrap();
+
+            assert_eq!(command.get_result().unwrap()[0], DataValue::UInt64(key - 1));
+        }
+    }
+}
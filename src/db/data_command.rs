@@ -1,21 +1,120 @@
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, RwLock};
 
-use crate::{SECTOR_LENGTH, cfs::{Buffer, CranePartition, CraneSchema, DataValue, Reader, Writer}};
+use crate::{cfs::{Blob, Buffer, CranePartition, CraneSchema, DataValue, MAX_INLINE_BLOB_LEN, ROW_PREFIX_LEN, Reader, Writer, max_row_len}};
 
 use super::{DataError, item_tree::{ItemTree, Position}};
 
-type Partition = Rc<RefCell<CranePartition>>;
+type Partition = Arc<RwLock<CranePartition>>;
 
 pub struct DataState<'a> {
     pub data_partitions: Vec<&'a Partition>,
     pub schema: &'a CraneSchema,
-    pub tree: &'a Rc<RefCell<ItemTree>>
+    pub tree: &'a Arc<RwLock<ItemTree>>
 }
 
 pub trait DataCommand {
     fn execute(&mut self, state: &mut DataState) -> Result<(), DataError>;
 }
 
+/// Checks that values have the types of the schema they are about to be stored with.
+/// # Arguments
+/// * `schema` - The schema of the values.
+/// * `values` - The values to check.
+fn check_schema(schema: &CraneSchema, values: &[DataValue]) -> Result<(), DataError> {
+    if values.len() != schema.types.len() {
+        return Err(DataError::SchemaMismatch(format!("{} values given for {} columns", values.len(), schema.types.len())));
+    }
+
+    for (i, (value, column)) in values.iter().zip(schema.types.iter()).enumerate() {
+        if value.id() != column.id() || value.len() != column.len() {
+            return Err(DataError::SchemaMismatch(format!("column {} holds type {} but was given type {}", i, column.id(), value.id())));
+        }
+        // Fixed strings would be cut off at their capacity when stored
+        if let DataValue::Fixchar(s, capacity) = value {
+            if s.len() as u64 > *capacity {
+                return Err(DataError::SchemaMismatch(format!("column {} holds at most {} bytes but was given {}", i, capacity, s.len())));
+            }
+        }
+        // Longer blobs have to be written to an overflow partition first
+        if let DataValue::Blob(Blob::Inline(bytes)) = value {
+            if bytes.len() as u64 > MAX_INLINE_BLOB_LEN {
+                return Err(DataError::SchemaMismatch(format!("column {} was given a {} byte blob, blobs over {} bytes are stored with a blob writer", i, bytes.len(), MAX_INLINE_BLOB_LEN)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a row is short enough to be written in one batch of the write ahead log.
+/// # Arguments
+/// * `state` - The state of the schema the row is about to be stored with.
+/// * `row` - The bytes of the row.
+fn check_row_len(state: &DataState, row: &[u8]) -> Result<(), DataError> {
+    let max = match state.data_partitions.first() {
+        Some(p) => max_row_len(p.read().unwrap().sector_length()),
+        None => return Ok(()),
+    };
+    if row.len() as u64 > max {
+        return Err(DataError::SchemaMismatch(format!("a row of {} bytes is longer than the {} bytes a row can hold", row.len(), max)));
+    }
+    Ok(())
+}
+
+/// Reads bytes of a partition, they can straddle sector boundaries.
+/// # Arguments
+/// * `partition` - The partition to read from.
+/// * `offset` - The first byte to read.
+/// * `len` - The number of bytes to read.
+fn read_range(partition: &RwLock<CranePartition>, offset: u64, len: u64) -> Result<Vec<u8>, DataError> {
+    let s = partition.read().unwrap().sector_length();
+    let bytes = partition.write().unwrap().read_sectors(offset / s, (offset + len).div_ceil(s))?;
+    let from = (offset % s) as usize;
+    if bytes.len() < from + len as usize {
+        return Err(DataError::CorruptMetadata(format!("bytes {}..{} are past the end of partition {}", offset, offset + len, partition.read().unwrap().id())));
+    }
+
+    Ok(bytes[from..(from + len as usize)].to_vec())
+}
+
+/// Gets the byte length of the row stored at an offset of a partition, rows of a schema with
+/// variable length columns are read to find out.
+/// # Arguments
+/// * `partition` - The partition holding the row.
+/// * `offset` - The byte the row starts at.
+/// * `schema` - The schema of the row.
+pub(super) fn row_len_at(partition: &RwLock<CranePartition>, offset: u64, schema: &CraneSchema) -> Result<u64, DataError> {
+    match schema.fixed_len() {
+        Some(len) => Ok(len),
+        None => Ok(schema.row_len(&read_range(partition, offset, ROW_PREFIX_LEN)?)?),
+    }
+}
+
+/// Reads the bytes of the row stored at an offset of a partition.
+/// # Arguments
+/// * `partition` - The partition holding the row.
+/// * `offset` - The byte the row starts at.
+/// * `schema` - The schema of the row.
+pub(super) fn read_row(partition: &RwLock<CranePartition>, offset: u64, schema: &CraneSchema) -> Result<Vec<u8>, DataError> {
+    let len = row_len_at(partition, offset, schema)?;
+    read_range(partition, offset, len)
+}
+
+/// Finds the end of a data partition with room for a row, returning the index of the partition
+/// and the offset to write the row at.
+/// # Arguments
+/// * `state` - The partitions of the schema.
+/// * `len` - The byte length of the row.
+fn find_fresh_slot(state: &DataState, len: u64) -> Result<(usize, u64), DataError> {
+    state.data_partitions.iter()
+        .position(|p| {
+            let p = p.read().unwrap();
+            p.total_bytes().saturating_sub(p.initialized_len) >= len
+        })
+        .map(|i| (i, state.data_partitions[i].read().unwrap().initialized_len))
+        .ok_or(DataError::OutOfStorage)
+}
+
 pub struct GetKeyCommand {
     key: u64,
     res: Option<Vec<DataValue>>,
@@ -36,20 +135,12 @@ impl GetKeyCommand {
 
 impl DataCommand for GetKeyCommand {
     fn execute(&mut self, state: &mut DataState) -> Result<(), DataError> {
-        let rdiff: f64 = (state.schema.len() as f64)/(SECTOR_LENGTH as f64);
-        let diff = f64::ceil(rdiff) as u64;
-        if let Some(position) = state.tree.borrow().get(self.key) {
-            let value = state.data_partitions.iter().filter(|p| p.borrow().id() == position.partition).next().unwrap();
+        if let Some(position) = state.tree.read().unwrap().get(self.key) {
+            let value = state.data_partitions.iter().find(|p| p.read().unwrap().id() == position.partition)
+                .ok_or_else(|| DataError::CorruptMetadata(format!("key {} points into unknown partition {}", self.key, position.partition)))?;
             
-            let s = SECTOR_LENGTH as u64;
-
-            let start_sector =  position.offset / s;
-            let start_offset = position.offset % s;
-
-            let mut buf = Buffer::new(value.borrow_mut().read_sectors(start_sector, start_sector+diff).unwrap());
-
-            buf.consume(start_offset);
-            self.res = Some(state.schema.parse_bytes(&mut buf));
+            let mut buf = Buffer::new(read_row(value, position.offset, state.schema)?);
+            self.res = Some(state.schema.parse_row(&mut buf)?);
 
             return Ok(());
         }
@@ -69,43 +160,30 @@ impl InsertValueCommand {
         }
     }
 
-    fn get_position_for_new(&self, state: &mut DataState) -> Result<(usize, u64), DataError> {
+    fn get_position_for_new(&self, state: &mut DataState, len: u64) -> Result<(usize, u64), DataError> {
         if let Some(res) = self.find_replace_slot(state) {
             return Ok(res);
         }
-        let i = self.find_fresh_slot(state)?;
-        let off = state.data_partitions[i].borrow().initialized_len;
-        Ok((i, off))
-    }
-
-    fn find_fresh_slot(&self, state: &mut DataState) -> Result<usize, DataError> {
-        let mut i: usize = 0;
-        // dbg!(self.partitions[i as usize].borrow().total_len() , self.partitions[i as usize].borrow().initialized_len);
-        while (state.data_partitions[i as usize].borrow().total_len()*(SECTOR_LENGTH as u64) - state.data_partitions[i as usize].borrow().initialized_len)*(SECTOR_LENGTH as u64) < state.schema.len() {
-            i += 1;
-            if i >= state.data_partitions.len() {
-                return Err(DataError::OutOfStorage);
-            }
-        }
-        Ok(i)
+        find_fresh_slot(state, len)
     }
 
+    /// Finds a slot no key points to, only rows of a fixed length are laid out in slots.
     fn find_replace_slot(&self, state: &mut DataState) -> Option<(usize, u64)> {
-        let ids: Vec<_> = state.data_partitions.iter().map(|v| v.borrow().id()).collect();
-        let tree = state.tree.borrow();
+        let jump = state.schema.fixed_len()?;
+        let ids: Vec<_> = state.data_partitions.iter().map(|v| v.read().unwrap().id()).collect();
+        let tree = state.tree.read().unwrap();
         let positions = tree.position_set();
-        let jump = state.schema.len();
         for (i, id) in ids.iter().enumerate() {
-            let mut curr_offset = 0u64;
-
-            while curr_offset + state.schema.len() < state.data_partitions[i].borrow().total_bytes() {
-                curr_offset += jump;
+            let mut curr_offset = jump;
 
+            // Only slots the whole value fits in can be reused
+            while curr_offset + jump <= state.data_partitions[i].read().unwrap().total_bytes() {
                 let pos = Position::new(*id, curr_offset);
 
                 if !positions.contains(&pos) {
                     return Some((i, curr_offset));
                 }
+                curr_offset += jump;
             }
         }
         None
@@ -114,12 +192,14 @@ impl InsertValueCommand {
 
 impl DataCommand for InsertValueCommand {
     fn execute(&mut self, state: &mut DataState) -> Result<(), DataError> {
-        let (i, off) = self.get_position_for_new(state)?;
-        let m = state.tree.borrow().max_key();
-        state.tree.borrow_mut().insert(m+1,         state.data_partitions[i].borrow().id(), off);
-        state.data_partitions[i].borrow_mut().write_sectors(0, off, &state.schema.produce_bytes(&self.value))
-            .unwrap();
-        return Ok(());
+        check_schema(state.schema, &self.value)?;
+        let row = state.schema.produce_row(&self.value);
+        check_row_len(state, &row)?;
+        let (i, off) = self.get_position_for_new(state, row.len() as u64)?;
+        state.data_partitions[i].write().unwrap().write_sectors(0, off, &row)?;
+        let m = state.tree.read().unwrap().max_key();
+        state.tree.write().unwrap().insert(m+1,         state.data_partitions[i].read().unwrap().id(), off);
+        Ok(())
     }
 }
 
@@ -139,17 +219,24 @@ impl UpdateValueCommand {
 
 impl DataCommand for UpdateValueCommand {
     fn execute(&mut self, state: &mut DataState) -> Result<(), DataError> {
-        let pos = state.tree.borrow().get(self.key).ok_or(DataError::UnknownKey)?;
-        let off = pos.offset;
-        let p = state.data_partitions.iter()
-            .filter(|v| v.borrow().id() == pos.partition)
-            .next()
+        check_schema(state.schema, &self.value)?;
+        let row = state.schema.produce_row(&self.value);
+        check_row_len(state, &row)?;
+        let pos = state.tree.read().unwrap().get(self.key).ok_or(DataError::UnknownKey)?;
+        let i = state.data_partitions.iter().position(|v| v.read().unwrap().id() == pos.partition)
             .ok_or(DataError::UnknownKey)?;
-        let m = state.tree.borrow().max_key();
-        state.tree.borrow_mut().insert(m+1,         p.borrow().id(), off);
-        p.borrow_mut().write_sectors(0, off, &state.schema.produce_bytes(&self.value))
-            .unwrap();
-        return Ok(());
+
+        // A row that grew no longer fits where the old one was, so it is moved to the end
+        let (i, off) = if row.len() as u64 <= row_len_at(state.data_partitions[i], pos.offset, state.schema)? {
+            (i, pos.offset)
+        } else {
+            find_fresh_slot(state, row.len() as u64)?
+        };
+        let p = state.data_partitions[i];
+        p.write().unwrap().write_sectors(0, off, &row)?;
+        let m = state.tree.read().unwrap().max_key();
+        state.tree.write().unwrap().insert(m+1,         p.read().unwrap().id(), off);
+        Ok(())
     }
 }
 
@@ -167,7 +254,7 @@ impl RemoveValueCommand {
 
 impl DataCommand for RemoveValueCommand {
     fn execute(&mut self, state: &mut DataState) -> Result<(), DataError> {
-        state.tree.borrow_mut().remove(self.key);
+        state.tree.write().unwrap().remove(self.key);
         Ok(())
     }
 }
\ No newline at end of file
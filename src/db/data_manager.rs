@@ -1,111 +1,118 @@
-use std::borrow::Borrow;
-use std::convert::TryInto;
-use std::rc::{Rc};
-use std::cell::{RefCell};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
-use crate::cfs::{Buffer, CraneDisk, CranePartition, CraneSchema, DataValue, Reader, Writer};
+use crate::cfs::{Blob, BlobRef, Buffer, CraneDisk, CranePartition, CraneSchema, DataValue, PartitionKind, PartitionType, Reader, Writer};
 
 use super::DataError;
-use super::data_command::{DataCommand, DataState};
-use super::item_tree::ItemTree;
+use super::data_command::{DataCommand, DataState, read_row, row_len_at};
+use super::item_tree::{ItemTree, Position};
 
+/// The number of sectors given to a new schema partition
+const SCHEMA_SECTORS: u64 = 32;
+/// The number of sectors given to a new tree partition
+const TREE_SECTORS: u64 = 8;
+/// The number of sectors given to a new data partition
+const DATA_SECTORS: u64 = 16;
 
-pub const OFFSET: u64 = 0;
-
-type Partition = Rc<RefCell<CranePartition>>;
+type Partition = Arc<RwLock<CranePartition>>;
 pub struct DataManager {
     schema: CraneSchema,
     data_partitions: Vec<Partition>,
     tree_partition: Partition,
     schema_partition: Partition,
-    tree: Rc<RefCell<ItemTree>>,
+    tree: Arc<RwLock<ItemTree>>,
+    /// The schema bytes last written to the schema partition, they are only written again if they change
+    saved_schema: Vec<u8>,
     pub name: String,
 }
 
 impl DataManager {
-    pub fn new(schema: CraneSchema, data_partitions: Vec<Partition>, schema_partition: Partition, tree_partition: Partition) -> Self {
-        let tree = Rc::new(RefCell::new(ItemTree::from_partition(&mut *tree_partition.borrow_mut(), None)));
-        Self {
+    pub fn new(schema: CraneSchema, data_partitions: Vec<Partition>, schema_partition: Partition, tree_partition: Partition) -> Result<Self, DataError> {
+        let tree = Arc::new(RwLock::new(ItemTree::from_partition(&mut tree_partition.write().unwrap(), None)?));
+        Ok(Self {
             schema,
             data_partitions,
             tree_partition,
             tree,
             schema_partition,
+            saved_schema: vec![],
             name: "".to_owned(),
-        }
+        })
     }
 
-    pub fn create_to_disk(disk: &mut CraneDisk, schema_slot: u64, schema: CraneSchema) -> Self {
-        let schema_type = schema_slot*3 + 1;
-        let tree_type = schema_slot*3 + 2;
-        let data_type = schema_slot*3 + 3;
-
-        let schema_id = disk.append_partition(32, schema_type);
-        let tree_id = disk.append_partition(8, tree_type);
-        let data_id = disk.append_partition(16, data_type);
+    pub fn create_to_disk(disk: &mut CraneDisk, schema_slot: u64, schema: CraneSchema) -> Result<Self, DataError> {
+        Self::create_sized(disk, schema_slot, schema, TREE_SECTORS, DATA_SECTORS)
+    }
 
+    /// Creates the partitions of a schema with room for a tree and data of the given sizes.
+    fn create_sized(disk: &mut CraneDisk, schema_slot: u64, schema: CraneSchema, tree_sectors: u64, data_sectors: u64) -> Result<Self, DataError> {
+        let schema_type = PartitionType::new(PartitionKind::Schema, schema_slot).raw();
+        let tree_type = PartitionType::new(PartitionKind::Index, schema_slot).raw();
+        let data_type = PartitionType::new(PartitionKind::Data, schema_slot).raw();
 
-        // Code here is almost entirely copy and pasted from `from_disk`
-        //TODO: Make this code segment not copy and pasted and dry
+        disk.append_partition(SCHEMA_SECTORS, schema_type)?;
+        disk.append_partition(tree_sectors, tree_type)?;
+        disk.append_partition(data_sectors, data_type)?;
 
-        let spartitions = disk.get_partition_by_type(schema_type);
-        let tpartitions = disk.get_partition_by_type(tree_type);
-        let dpartitions = disk.get_partition_by_type(data_type);
+        let (schema_partition, tree_partition, data_partitions) = Self::find_partitions(disk, schema_slot)?;
 
-        let schema_partition = spartitions.get(0).expect("Missing schema partition");
-        let tree_partition = tpartitions.get(0).expect("Missing btree partition");
-        let data_partitions: Vec<Partition> = dpartitions.iter()
-            .map(|v| (*v).clone())
-            .collect();
+        Self::new(schema, data_partitions, schema_partition, tree_partition)
+    }
 
-        let tree = Rc::new(RefCell::new(ItemTree::from_partition(&mut *tree_partition.borrow_mut(), None)));
+    pub fn from_disk(disk: &CraneDisk, schema_slot: u64) -> Result<Self, DataError> {
+        let (schema_partition, tree_partition, data_partitions) = Self::find_partitions(disk, schema_slot)?;
+        let (schema_name, schema) = Self::load_schema(&schema_partition)?;
 
+        let mut manager = Self::new(schema, data_partitions, schema_partition, tree_partition)?;
+        manager.name = schema_name;
+        manager.saved_schema = manager.schema_bytes()?;
 
-        Self {
-            schema,
-            data_partitions,
-            schema_partition: (*schema_partition).clone(),
-            tree_partition: (*tree_partition).clone(),
-            tree,
-            name: "".to_owned(),
-        }
+        Ok(manager)
     }
 
-    pub fn from_disk(disk: &CraneDisk, schema_slot: u64) -> Self {
-        let schema_type = schema_slot*3 + 1;
-        let tree_type = schema_slot*3 + 2;
-        let data_type = schema_slot*3 + 3;
-        
+    /// Finds the schema, tree and data partitions of a schema slot.
+    /// # Arguments
+    /// * `disk` - The disk holding the partitions.
+    /// * `schema_slot` - The slot of the schema.
+    fn find_partitions(disk: &CraneDisk, schema_slot: u64) -> Result<(Partition, Partition, Vec<Partition>), DataError> {
+        let schema_type = PartitionType::new(PartitionKind::Schema, schema_slot).raw();
+        let tree_type = PartitionType::new(PartitionKind::Index, schema_slot).raw();
+        let data_type = PartitionType::new(PartitionKind::Data, schema_slot).raw();
+
         let spartitions = disk.get_partition_by_type(schema_type);
         let tpartitions = disk.get_partition_by_type(tree_type);
         let dpartitions = disk.get_partition_by_type(data_type);
 
-        let schema_partition = spartitions.get(0).expect("Missing schema partition");
-        let tree_partition = tpartitions.get(0).expect("Missing btree partition");
+        let schema_partition = spartitions.first()
+            .ok_or_else(|| DataError::CorruptMetadata(format!("schema {} has no schema partition", schema_slot)))?;
+        let tree_partition = tpartitions.first()
+            .ok_or_else(|| DataError::CorruptMetadata(format!("schema {} has no tree partition", schema_slot)))?;
         let data_partitions: Vec<Partition> = dpartitions.iter()
             .map(|v| (*v).clone())
             .collect();
-        let (schema_name, schema) = Self::load_schema(*schema_partition);
-        let tree = Rc::new(RefCell::new(ItemTree::from_partition(&mut *tree_partition.borrow_mut(), None)));
 
+        Ok(((*schema_partition).clone(), (*tree_partition).clone(), data_partitions))
+    }
 
-        Self {
-            schema,
-            data_partitions,
-            schema_partition: (*schema_partition).clone(),
-            tree_partition: (*tree_partition).clone(),
-            tree,
-            name: schema_name,
+    pub fn save_schema(&mut self) -> Result<(), DataError> {
+        let bytes = self.schema_bytes()?;
+        if bytes != self.saved_schema {
+            self.schema_partition.write().unwrap().write_sectors(0, 0, &bytes)?;
+            self.saved_schema = bytes;
         }
+        Ok(())
     }
 
-    pub fn save_schema(&mut self) {
-        assert_eq!(self.schema.names.len(), self.schema.types.len());
+    /// The bytes the schema and its name are stored as.
+    fn schema_bytes(&self) -> Result<Vec<u8>, DataError> {
+        if self.schema.names.len() != self.schema.types.len() {
+            return Err(DataError::SchemaMismatch(format!("{} names given for {} types", self.schema.names.len(), self.schema.types.len())));
+        }
 
         let mut name_bytes = DataValue::Fixchar(self.name.clone(), 100).to_bytes();
 
         let ids = self.schema.types.iter().map(|t| t.id()).collect::<Vec<u16>>();
-        let mut vals = ids.iter().enumerate().map(|(i, id)| {
+        let mut vals = ids.iter().enumerate().flat_map(|(i, id)| {
             let mut v = DataValue::Fixchar(self.schema.names[i].clone(), 100).to_bytes();
             v.append(&mut id.to_be_bytes().to_vec());
 
@@ -114,64 +121,168 @@ impl DataManager {
             }
 
             v
-        }).flatten().collect::<Vec<u8>>();
+        }).collect::<Vec<u8>>();
 
         name_bytes.append(&mut vals);
-        
-        self.schema_partition.borrow_mut().write_sectors(0, 0, &name_bytes[..]).expect("Error writing schema to disk");
+        Ok(name_bytes)
     }
 
-    fn load_schema(schema_partition: &RefCell<CranePartition>) -> (String, CraneSchema) {
-        let len = schema_partition.borrow().total_len();
-        let bytes = schema_partition.borrow_mut().read_sectors(0, len).unwrap();
+    fn load_schema(schema_partition: &RwLock<CranePartition>) -> Result<(String, CraneSchema), DataError> {
+        let len = schema_partition.read().unwrap().total_len();
+        let bytes = schema_partition.write().unwrap().read_sectors(0, len)?;
         let mut buffer = Buffer::new(bytes);
 
         let mut name_dv = DataValue::Fixchar(String::new(), 100);
+        let name_len = name_dv.len().unwrap_or(0);
+        let truncated = |_| DataError::CorruptMetadata("the schema partition is truncated".to_owned());
 
-        DataValue::from_bytes(buffer.consume(name_dv.len().unwrap()), &mut name_dv);
-        let schema_name = if let DataValue::Fixchar(value, _) = &name_dv {
-            value.clone()
-        } else {
-            panic!()
-        };
+        DataValue::from_bytes(buffer.read_bytes(name_len).map_err(truncated)?, &mut name_dv)?;
+        let schema_name = Self::fixchar_value(&name_dv);
 
-        DataValue::from_bytes(buffer.consume(name_dv.len().unwrap()), &mut name_dv);
-        let mut curr = buffer.consume(2);
-        let mut value = u16::from_be_bytes(curr.try_into().unwrap());
+        let mut name_bytes = buffer.read_bytes(name_len).map_err(truncated)?.to_vec();
+        let mut value = buffer.read_u16().map_err(truncated)?;
         let mut ids = Vec::new();
         let mut names = Vec::new();
-        while value != 0 && !buffer.empty() {
+        // The columns end at a zero type id, the name before it is only padding
+        while value != 0 && !buffer.is_empty() {
             let mut meta_data: u64 = 0;
             if value == 6 {
-                let c = buffer.consume(8);
-                let v = u64::from_be_bytes(c.try_into().unwrap());
-                meta_data = v;
+                meta_data = buffer.read_u64().map_err(truncated)?;
             }
-            ids.push(DataValue::from_id(value, meta_data));
-            if let DataValue::Fixchar(value, _) = &name_dv {
-                names.push(value.clone());
-            } else {
-                panic!()
-            }
-            DataValue::from_bytes(buffer.consume(name_dv.len().unwrap()), &mut name_dv);
-            curr = buffer.consume(2);
-            value = u16::from_be_bytes(curr.try_into().unwrap());
+            ids.push(DataValue::from_id(value, meta_data).ok_or(DataError::UnknownTypeId(value))?);
+            DataValue::from_bytes(&name_bytes, &mut name_dv)?;
+            names.push(Self::fixchar_value(&name_dv));
+
+            name_bytes = buffer.read_bytes(name_len).map_err(truncated)?.to_vec();
+            value = buffer.read_u16().map_err(truncated)?;
         }
 
         let mut schema = CraneSchema::new(ids);
         schema.names = names;
-        (schema_name, schema)
+        Ok((schema_name, schema))
+    }
+
+    fn fixchar_value(value: &DataValue) -> String {
+        match value {
+            DataValue::Fixchar(value, _) => value.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn save_tree(&self) -> Result<(), DataError> {
+        self.tree.write().unwrap().to_partition(&mut self.tree_partition.write().unwrap())?;
+        Ok(())
+    }
+
+    /// Grows the tree partition in place if it has no room for one more key, doubling it so that
+    /// large tables only grow it now and then. Done before a command, as growing it can save.
+    /// # Arguments
+    /// * `disk` - The disk the schema is on.
+    pub fn grow_tree(&self, disk: &mut CraneDisk) -> Result<(), DataError> {
+        let (id, total_len, total_bytes) = {
+            let p = self.tree_partition.read().unwrap();
+            (p.id(), p.total_len(), p.total_bytes())
+        };
+        if !self.tree.read().unwrap().has_room(total_bytes) {
+            disk.grow_partition(id, u64::max(TREE_SECTORS, total_len))?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<(), DataError> {
+        self.save_schema()?;
+        self.save_tree()
     }
 
-    fn save_tree(&self) {
-        // dbg!(self.tree.borrow().to_bytes());
-        self.tree.borrow_mut().to_partition(&mut *self.tree_partition.borrow_mut());
+    /// Copies the schema and its live values to another disk, packing the values into a single
+    /// data partition in key order. Keys stay the same, positions are rewritten. The blobs the
+    /// values refer to are copied along with them, blobs no value refers to are dropped.
+    /// Returns the manager of the copy and how many values were copied.
+    /// # Arguments
+    /// * `source` - The disk the schema is on.
+    /// * `disk` - The disk to copy to.
+    /// * `schema_slot` - The slot of the schema on the disk copied to.
+    pub fn compact_into(&self, source: &CraneDisk, disk: &mut CraneDisk, schema_slot: u64) -> Result<(Self, u64), DataError> {
+        let tree = self.tree.read().unwrap();
+        let sl = disk.sector_length();
+
+        // Lay the rows out first so the copy's data partition can be sized to fit them,
+        // keys updated in place share a position and keep sharing it
+        let mut moved: HashMap<Position, u64> = HashMap::new();
+        let mut order: Vec<(u64, Position, u64)> = vec![];
+        let mut next = 0u64;
+        for (key, position) in tree.tree.iter() {
+            if moved.contains_key(position) {
+                continue;
+            }
+            let source = self.data_partition(*key, position)?;
+            moved.insert(*position, next);
+            order.push((*key, *position, next));
+            next += row_len_at(source, position.offset, &self.schema)?;
+        }
+
+        let tree_sectors = u64::max(TREE_SECTORS, self.tree_partition.read().unwrap().total_len());
+        let data_sectors = u64::max(DATA_SECTORS, next.div_ceil(sl));
+        let mut manager = Self::create_sized(disk, schema_slot, self.schema.clone(), tree_sectors, data_sectors)?;
+        manager.name = self.name.clone();
+
+        let target = manager.data_partitions[0].clone();
+        let target_id = target.read().unwrap().id();
+        let has_blobs = self.schema.types.iter().any(|t| matches!(t, DataValue::Blob(_)));
+        // Rows sharing a blob keep sharing its copy
+        let mut blobs: HashMap<u64, u64> = HashMap::new();
+        for (key, position, offset) in order.iter() {
+            let partition = self.data_partition(*key, position)?;
+            let mut row = read_row(partition, position.offset, &self.schema)?;
+            if has_blobs {
+                let mut values = self.schema.parse_row(&mut Buffer::new(row))?;
+                for value in values.iter_mut() {
+                    if let DataValue::Blob(Blob::Overflow(blob)) = value {
+                        let copy = match blobs.get(&blob.partition) {
+                            Some(copy) => *copy,
+                            None => {
+                                let copy = disk.copy_partition(&mut Self::blob_partition(source, blob)?.write().unwrap())?;
+                                blobs.insert(blob.partition, copy);
+                                copy
+                            }
+                        };
+                        blob.partition = copy;
+                    }
+                }
+                // References are all the same length, so the row still fits where it was laid out
+                row = self.schema.produce_row(&values);
+            }
+            target.write().unwrap().write_sectors(0, *offset, &row)?;
+            disk.checkpoint()?;
+        }
+
+        // The tree is saved as it goes too, it can be larger than the write ahead log
+        manager.save_schema()?;
+        for (key, position) in tree.tree.iter() {
+            manager.tree.write().unwrap().insert(*key, target_id, moved[position]);
+            manager.save_tree()?;
+            disk.checkpoint()?;
+        }
+        Ok((manager, moved.len() as u64))
     }
 
+    /// Finds the overflow partition holding a blob.
+    /// # Arguments
+    /// * `disk` - The disk the blob is on.
+    /// * `blob` - The blob to find.
+    fn blob_partition<'a>(disk: &'a CraneDisk, blob: &BlobRef) -> Result<&'a Partition, DataError> {
+        disk.partitions.iter()
+            .find(|p| {
+                let p = p.read().unwrap();
+                p.id() == blob.partition && PartitionType::from_raw(p.partition_type).kind == PartitionKind::Overflow
+            })
+            .ok_or(DataError::UnknownBlob(blob.partition))
+    }
 
-    pub fn save(&mut self) {
-        self.save_schema();
-        self.save_tree();
+    /// Finds the data partition a key's position points into.
+    fn data_partition(&self, key: u64, position: &Position) -> Result<&Partition, DataError> {
+        self.data_partitions.iter().find(|p| p.read().unwrap().id() == position.partition)
+            .ok_or_else(|| DataError::CorruptMetadata(format!("key {} points into unknown partition {}", key, position.partition)))
     }
 
     pub fn get_schema(&self) -> &CraneSchema {
@@ -186,11 +297,15 @@ impl DataManager {
         &self.tree_partition
     }
 
+    pub fn get_tree(&self) -> &Arc<RwLock<ItemTree>> {
+        &self.tree
+    }
+
     pub fn execute(&mut self, command: &mut dyn DataCommand) -> Result<(), DataError> {
         let mut state = DataState {
             schema: &self.schema,
             tree: &self.tree,
-            data_partitions: self.data_partitions.iter().map(|p| p).collect(),
+            data_partitions: self.data_partitions.iter().collect(),
         };
 
         command.execute(&mut state)
@@ -199,28 +314,17 @@ impl DataManager {
 
 #[cfg(test)]
 mod test {
-    use std::{ascii::AsciiExt, borrow::Borrow, fs::{File, OpenOptions}};
-
+    use crate::cfs::{Date, Interval, Storage, Time, Timestamp};
     use crate::db::data_command::{GetKeyCommand, InsertValueCommand};
 
     use super::*;
 
     fn generate_disk() -> CraneDisk {
-        let write = File::create("test/data/db.cdb").unwrap();
-        let read = File::open("test/data/db.cdb").unwrap();
-
-        let crane = CraneDisk::init_file(read, write);
-
-        crane
+        CraneDisk::in_memory()
     }
 
-    fn load_disk() -> CraneDisk {
-        let read = File::open("test/data/db.cdb").unwrap();
-        let write = OpenOptions::new().write(true).open("test/data/db.cdb").unwrap();
-
-        let crane = CraneDisk::from_file(read, write);
-
-        crane
+    fn load_disk(storage: Arc<RwLock<dyn Storage>>) -> CraneDisk {
+        CraneDisk::from_storage(storage).unwrap()
     }
 
     fn get_schema() -> CraneSchema {
@@ -231,18 +335,16 @@ mod test {
             DataValue::Fixchar(String::new(), 32)
         ]);
 
-        v.names = vec!["birthday".to_owned(), "id".to_owned(), "type".to_owned(), "name".to_ascii_lowercase()];
+        v.names = vec!["birthday".to_owned(), "id".to_owned(), "type".to_owned(), "name".to_owned()];
 
         v
     }
 
-    #[test]
-    pub fn test_create_manager() {
-
+    fn create_manager() -> Arc<RwLock<dyn Storage>> {
         let mut disk = generate_disk();
         
         let schema = get_schema();
-        let mut manager = DataManager::create_to_disk(&mut disk, 1, schema);
+        let mut manager = DataManager::create_to_disk(&mut disk, 1, schema).unwrap();
         manager.name = "Employee".to_owned();
 
         let values = vec![
@@ -263,17 +365,21 @@ mod test {
         // manager.data_writer.write_value(values.clone()).unwrap();
         // manager.data_writer.write_value(values.clone()).unwrap();
 
-        manager.save();
-        disk.save();
+        manager.save().unwrap();
+        disk.save().unwrap();
+        disk.storage()
     }
 
     #[test]
-    pub fn test_load_manager() {
-        test_create_manager();
+    pub fn test_create_manager() {
+        create_manager();
+    }
 
-        let disk = load_disk();
+    #[test]
+    pub fn test_load_manager() {
+        let disk = load_disk(create_manager());
 
-        let mut manager = DataManager::from_disk(&disk, 1);
+        let mut manager = DataManager::from_disk(&disk, 1).unwrap();
 
         let mut command = GetKeyCommand::new(3);
         manager.execute(&mut command).expect("Error running command");
@@ -284,9 +390,100 @@ mod test {
 
         let stuff = value.unwrap();
         assert_eq!(&manager.name, "Employee");
-        assert_eq!(*stuff.get(0).unwrap(), DataValue::UInt64(1));
+        assert_eq!(*stuff.first().unwrap(), DataValue::UInt64(1));
         assert_eq!(*stuff.get(1).unwrap(), DataValue::UInt64(5));
         assert_eq!(*stuff.get(2).unwrap(), DataValue::UInt64(2));
         assert_eq!(*stuff.get(3).unwrap(), DataValue::Fixchar("hello world".to_owned(), 32));
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_numeric_columns() {
+        let mut disk = generate_disk();
+        let mut schema = CraneSchema::new(vec![
+            DataValue::Float32(0.0),
+            DataValue::Float64(0.0),
+            DataValue::UInt8(0),
+            DataValue::UInt16(0),
+            DataValue::UInt32(0),
+        ]);
+        schema.names = vec!["load".to_owned(), "latency".to_owned(), "cpu".to_owned(), "port".to_owned(), "requests".to_owned()];
+        let values = vec![
+            DataValue::Float32(0.75),
+            DataValue::Float64(-12.125),
+            DataValue::UInt8(7),
+            DataValue::UInt16(8080),
+            DataValue::UInt32(4_000_000_000),
+        ];
+
+        let mut manager = DataManager::create_to_disk(&mut disk, 0, schema).unwrap();
+        manager.execute(&mut InsertValueCommand::new(values.clone())).unwrap();
+        manager.save().unwrap();
+        disk.save().unwrap();
+
+        let disk = load_disk(disk.storage());
+        let mut manager = DataManager::from_disk(&disk, 0).unwrap();
+        assert_eq!(manager.get_schema().types.iter().map(|t| t.id()).collect::<Vec<_>>(), vec![9, 10, 11, 12, 13]);
+        assert_eq!(manager.get_schema().names[4], "requests");
+
+        let mut command = GetKeyCommand::new(1);
+        manager.execute(&mut command).unwrap();
+        assert_eq!(command.get_result().unwrap(), values);
+    }
+
+    #[test]
+    pub fn test_datetime_columns() {
+        let mut disk = generate_disk();
+        let mut schema = CraneSchema::new(vec![
+            DataValue::Date(Date::default()),
+            DataValue::Time(Time::default()),
+            DataValue::Timestamp(Timestamp::default()),
+        ]);
+        schema.names = vec!["born".to_owned(), "alarm".to_owned(), "joined".to_owned()];
+        let joined: Timestamp = "2021-06-01T08:15:00+02:00".parse().unwrap();
+        let values = vec![
+            DataValue::Date("1990-07-14".parse().unwrap()),
+            DataValue::Time("06:30:00".parse().unwrap()),
+            DataValue::Timestamp(joined + Interval::from_days(30)),
+        ];
+
+        let mut manager = DataManager::create_to_disk(&mut disk, 0, schema).unwrap();
+        manager.execute(&mut InsertValueCommand::new(values.clone())).unwrap();
+        manager.save().unwrap();
+        disk.save().unwrap();
+
+        let disk = load_disk(disk.storage());
+        let mut manager = DataManager::from_disk(&disk, 0).unwrap();
+        assert_eq!(manager.get_schema().types.iter().map(|t| t.id()).collect::<Vec<_>>(), vec![14, 15, 16]);
+
+        let mut command = GetKeyCommand::new(1);
+        manager.execute(&mut command).unwrap();
+        let row = command.get_result().unwrap();
+        assert_eq!(row, values);
+        assert!(matches!(&row[2], DataValue::Timestamp(t) if t.to_string() == "2021-07-01T08:15:00+02:00"));
+    }
+
+    #[test]
+    pub fn test_schema_mismatch() {
+        let mut disk = generate_disk();
+        let mut manager = DataManager::create_to_disk(&mut disk, 1, get_schema()).unwrap();
+
+        let mut command = InsertValueCommand::new(vec![
+            DataValue::UInt64(1),
+            DataValue::Int16(5),
+            DataValue::UInt64(2),
+            DataValue::Fixchar("hello world".to_owned(), 32),
+        ]);
+        assert!(matches!(manager.execute(&mut command), Err(DataError::SchemaMismatch(_))));
+
+        let mut command = InsertValueCommand::new(vec![DataValue::UInt64(1)]);
+        assert!(matches!(manager.execute(&mut command), Err(DataError::SchemaMismatch(_))));
+
+        let mut command = InsertValueCommand::new(vec![
+            DataValue::UInt64(1),
+            DataValue::UInt64(5),
+            DataValue::UInt64(2),
+            DataValue::Fixchar("x".repeat(33), 32),
+        ]);
+        assert!(matches!(manager.execute(&mut command), Err(DataError::SchemaMismatch(_))));
+    }
+}
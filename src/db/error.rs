@@ -0,0 +1,57 @@
+use std::{error::Error, fmt};
+
+use crate::cfs::FSError;
+
+/// Everything that can go wrong storing or querying values
+#[derive(Debug)]
+pub enum DataError {
+    /// None of the schema's data partitions have room for another value
+    OutOfStorage,
+    /// No value is stored under the key
+    UnknownKey,
+    /// No schema is stored in the slot
+    UnknownSchema(u64),
+    /// The disk holding the values failed
+    Disk(FSError),
+    /// A stored schema uses a type id this version of crane doesn't know about
+    UnknownTypeId(u16),
+    /// The values given don't match the types of the schema they are stored with
+    SchemaMismatch(String),
+    /// The partitions of a schema are missing or can't be parsed
+    CorruptMetadata(String),
+    /// A thread panicked while holding a shared database, its state can't be trusted
+    Poisoned,
+    /// A blob points to a partition that isn't one of its schema's overflow partitions
+    UnknownBlob(u64),
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::OutOfStorage => write!(f, "out of storage for values"),
+            DataError::UnknownKey => write!(f, "unknown key"),
+            DataError::UnknownSchema(slot) => write!(f, "no schema in slot {}", slot),
+            DataError::Disk(err) => write!(f, "disk error: {}", err),
+            DataError::UnknownTypeId(id) => write!(f, "unknown type id {}", id),
+            DataError::SchemaMismatch(what) => write!(f, "values don't match the schema: {}", what),
+            DataError::CorruptMetadata(what) => write!(f, "corrupt schema metadata: {}", what),
+            DataError::Poisoned => write!(f, "a thread panicked while holding the database"),
+            DataError::UnknownBlob(partition) => write!(f, "partition {} doesn't hold a blob of the schema", partition),
+        }
+    }
+}
+
+impl Error for DataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataError::Disk(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<FSError> for DataError {
+    fn from(err: FSError) -> Self {
+        DataError::Disk(err)
+    }
+}
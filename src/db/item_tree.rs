@@ -1,5 +1,8 @@
-use std::{collections::{BTreeMap, HashSet}, convert::TryInto};
-use crate::cfs::{Buffer, CranePartition, Reader, Writer};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::cfs::{Buffer, CranePartition, FSError, Reader, Writer};
+
+/// The bytes an entry of the tree takes up on disk, its key and position
+pub const ENTRY_LEN: u64 = 24;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 
@@ -28,18 +31,47 @@ impl Position {
     /// Creates a position from bytes.
     /// # Arguments
     /// * `bytes` - The bytes to create the position from.
-    pub fn from_bytes(bytes: &mut Buffer) -> Self {
-        let partition = u64::from_be_bytes(bytes.consume(8)[..].try_into().unwrap());
-        let offset = u64::from_be_bytes(bytes.consume(8)[..].try_into().unwrap());
+    pub fn from_bytes(bytes: &mut Buffer) -> Result<Self, FSError> {
+        let truncated = |_| FSError::CorruptData("item position is truncated".to_owned());
+        let partition = bytes.read_u64().map_err(truncated)?;
+        let offset = bytes.read_u64().map_err(truncated)?;
 
-        Self::new(partition, offset)
+        Ok(Self::new(partition, offset))
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// The keys of a schema and where their values are, kept on disk as a table of fixed size slots.
+///
+/// Every key keeps the slot it was first written to, removed keys leave an empty slot that a later
+/// key takes over, so saving the tree only writes the slots that changed since it was last saved.
+#[derive(Debug, Clone)]
 pub struct ItemTree {
     pub tree: BTreeMap<u64, Position>,
     max_key: u64,
+    /// The slot each key is stored in
+    slots: HashMap<u64, u64>,
+    /// The key stored in each slot in use
+    keys: HashMap<u64, u64>,
+    /// Empty slots before the last one in use
+    free: BTreeSet<u64>,
+    /// The number of slots up to and including the last one in use
+    slot_count: u64,
+    /// The slots changed since the tree was last written to its partition
+    dirty: BTreeSet<u64>,
+}
+
+impl PartialEq for ItemTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.tree == other.tree && self.max_key == other.max_key
+    }
+}
+
+impl Eq for ItemTree {}
+
+impl Default for ItemTree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ItemTree {
@@ -47,6 +79,11 @@ impl ItemTree {
         ItemTree {
             tree: BTreeMap::new(),
             max_key: 0,
+            slots: HashMap::new(),
+            keys: HashMap::new(),
+            free: BTreeSet::new(),
+            slot_count: 0,
+            dirty: BTreeSet::new(),
         }
     }
 
@@ -54,47 +91,78 @@ impl ItemTree {
         self.max_key
     }
 
+    /// The bytes of a key's entry, zero for no key.
+    fn entry_bytes(&self, key: Option<u64>) -> Vec<u8> {
+        match key.and_then(|key| self.tree.get(&key).map(|position| (key, position))) {
+            Some((key, position)) => {
+                let mut key_bytes = key.to_be_bytes().to_vec();
+                key_bytes.append(&mut position.to_bytes());
+                key_bytes
+            },
+            None => vec![0u8; ENTRY_LEN as usize],
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        self.tree.iter().map(|(key, value)| {
-            let mut key_bytes = key.to_be_bytes().to_vec();
-            let mut value_bytes = value.to_bytes().to_vec();
-
-            key_bytes.append(&mut value_bytes);
-            key_bytes
-        }).flatten().collect()
-    }
-
-    pub fn from_bytes(bytes: &mut Buffer) -> Self {
-        let mut tree = BTreeMap::new();
-        let mut m = 0u64;
-        while !bytes.empty() {
-            let key = u64::from_be_bytes(bytes.consume(8)[..].try_into().unwrap());
-            let value = Position::from_bytes(bytes);
-            if key == 0 {
-                break;
-            }
+        (0..self.slot_count).flat_map(|slot| self.entry_bytes(self.keys.get(&slot).cloned())).collect()
+    }
 
-            tree.insert(key, value);
-            m = u64::max(m, key);
+    pub fn from_bytes(bytes: &mut Buffer) -> Result<Self, FSError> {
+        let mut tree = Self::new();
+        let mut slot = 0u64;
+        // Anything too short to hold an entry is padding at the end of the partition
+        while bytes.remaining() >= ENTRY_LEN {
+            let key = bytes.read_u64()?;
+            let value = Position::from_bytes(bytes)?;
+            // Empty slots are zero, a key already seen is a stale copy left by older versions
+            if key != 0 && !tree.slots.contains_key(&key) {
+                tree.tree.insert(key, value);
+                tree.slots.insert(key, slot);
+                tree.keys.insert(slot, key);
+                tree.max_key = u64::max(tree.max_key, key);
+                tree.slot_count = slot + 1;
+            }
+            slot += 1;
         }
 
-        Self {
-            tree,
-            max_key: m,
-        }
+        tree.free = (0..tree.slot_count).filter(|s| !tree.keys.contains_key(s)).collect();
+        Ok(tree)
+    }
+
+    /// Whether one more key fits in a partition of some bytes.
+    /// # Arguments
+    /// * `len` - The byte length of the partition.
+    pub fn has_room(&self, len: u64) -> bool {
+        !self.free.is_empty() || (self.slot_count + 1)*ENTRY_LEN <= len
     }
 
     pub fn position_set(&self) -> HashSet<Position> {
         self.tree.values().cloned().collect()
     }
 
-    pub fn to_partition(&self, partition: &mut CranePartition) {
-        partition.write_sectors(0, 0, self.to_bytes()[..].try_into().unwrap()).unwrap();
+    /// Writes the slots changed since the tree was last written, each run of neighbouring slots
+    /// in one go.
+    /// # Arguments
+    /// * `partition` - The tree partition.
+    pub fn to_partition(&mut self, partition: &mut CranePartition) -> Result<(), FSError> {
+        let dirty: Vec<u64> = self.dirty.iter().cloned().collect();
+        let mut i = 0;
+        while i < dirty.len() {
+            let first = dirty[i];
+            let mut bytes = vec![];
+            while i < dirty.len() && dirty[i] == first + (bytes.len() as u64)/ENTRY_LEN {
+                bytes.append(&mut self.entry_bytes(self.keys.get(&dirty[i]).cloned()));
+                i += 1;
+            }
+            partition.write_sectors(0, first*ENTRY_LEN, &bytes)?;
+        }
+        self.dirty.clear();
+        Ok(())
     }
 
-    pub fn from_partition(partition: &mut CranePartition, offset: Option<u64>) -> Self {
+    pub fn from_partition(partition: &mut CranePartition, offset: Option<u64>) -> Result<Self, FSError> {
         let o = offset.unwrap_or(0);
-        let mut buffer = Buffer::new(partition.read_sectors(o, partition.total_len()+o).unwrap());
+        let mut buffer = Buffer::new(partition.read_sectors(o, partition.total_len()+o)?);
 
         Self::from_bytes(&mut buffer)
     }
@@ -105,11 +173,33 @@ impl ItemTree {
 
     pub fn remove(&mut self, key: u64) {
         self.tree.remove(&key);
+        if let Some(slot) = self.slots.remove(&key) {
+            self.keys.remove(&slot);
+            self.free.insert(slot);
+            self.dirty.insert(slot);
+        }
     }
 
     pub fn insert(&mut self, key: u64, partition: u64, offset: u64) {
         self.max_key = u64::max(self.max_key, key);
         self.tree.insert(key, Position::new(partition, offset));
+
+        let slot = match self.slots.get(&key) {
+            Some(slot) => *slot,
+            None => {
+                let slot = match self.free.pop_first() {
+                    Some(slot) => slot,
+                    None => {
+                        self.slot_count += 1;
+                        self.slot_count - 1
+                    }
+                };
+                self.slots.insert(key, slot);
+                self.keys.insert(slot, key);
+                slot
+            }
+        };
+        self.dirty.insert(slot);
     }
 }
 
@@ -125,8 +215,31 @@ mod tests {
         tree.insert(1, 1, 8);
 
         let bytes = tree.to_bytes();
-        let new_tree = ItemTree::from_bytes(&mut Buffer::new(bytes));
+        let new_tree = ItemTree::from_bytes(&mut Buffer::new(bytes)).unwrap();
 
         assert_eq!(tree, new_tree);
     }
+
+    #[test]
+    fn test_tree_slots() {
+        let mut tree = ItemTree::new();
+        for key in 1..=4 {
+            tree.insert(key, 1, key*8);
+        }
+        // A new key takes over the slot of a removed one
+        tree.remove(2);
+        tree.insert(5, 1, 40);
+        let bytes = tree.to_bytes();
+        assert_eq!(bytes.len() as u64, 4*ENTRY_LEN);
+        assert_eq!(bytes[ENTRY_LEN as usize..][..8], 5u64.to_be_bytes());
+
+        tree.remove(3);
+        let mut new_tree = ItemTree::from_bytes(&mut Buffer::new(tree.to_bytes())).unwrap();
+        assert_eq!(new_tree, tree);
+        assert_eq!(new_tree.max_key(), 5);
+        assert!(new_tree.has_room(4*ENTRY_LEN));
+        new_tree.insert(6, 1, 48);
+        assert!(!new_tree.has_room(4*ENTRY_LEN));
+        assert_eq!(new_tree.to_bytes()[2*ENTRY_LEN as usize..][..8], 6u64.to_be_bytes());
+    }
 }
\ No newline at end of file
@@ -2,14 +2,14 @@ mod item_tree;
 mod data_manager;
 mod data_command;
 mod crane;
+mod shared_crane;
+mod error;
+mod check;
 
 pub use item_tree::*;
 pub use data_manager::DataManager;
-pub use crane::Crane;
+pub use crane::{Crane, CompactionReport};
+pub use shared_crane::SharedCrane;
 pub use data_command::*;
-
-#[derive(Debug, PartialEq)]
-pub enum DataError {
-    OutOfStorage,
-    UnknownKey,
-}
\ No newline at end of file
+pub use error::DataError;
+pub use check::{CheckReport, Issue, check_disk, check_storage, check_file};
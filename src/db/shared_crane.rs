@@ -0,0 +1,117 @@
+use std::{path::Path, sync::{Arc, Mutex, MutexGuard}};
+
+use crate::cfs::CraneSchema;
+
+use super::{Crane, DataError, data_command::DataCommand};
+
+/// A handle to a database that can be cloned and shared between threads. Every call locks the
+/// database while it runs, so commands from different threads never interleave.
+#[derive(Clone)]
+pub struct SharedCrane {
+    crane: Arc<Mutex<Crane>>,
+}
+
+impl SharedCrane {
+    /// Creates a handle owning a database.
+    /// # Arguments
+    /// * `crane` - The database to share.
+    pub fn new(crane: Crane) -> Self {
+        SharedCrane {
+            crane: Arc::new(Mutex::new(crane)),
+        }
+    }
+
+    /// Opens the database at a path, see `Crane::open`.
+    /// # Arguments
+    /// * `path` - The path of the database file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DataError> {
+        Ok(Self::new(Crane::open(path)?))
+    }
+
+    /// Locks the database for a run of calls that must not interleave with other threads.
+    /// Fails if another thread panicked while holding the lock.
+    pub fn lock(&self) -> Result<MutexGuard<'_, Crane>, DataError> {
+        self.crane.lock().map_err(|_| DataError::Poisoned)
+    }
+
+    /// Executes a command against a schema, see `Crane::execute`.
+    /// # Arguments
+    /// * `schema_slot` - The slot of the schema.
+    /// * `command` - The command to execute.
+    pub fn execute(&self, schema_slot: u64, command: &mut dyn DataCommand) -> Result<(), DataError> {
+        self.lock()?.execute(schema_slot, command)
+    }
+
+    /// Adds a schema, returns its slot.
+    /// # Arguments
+    /// * `schema` - The schema to add.
+    pub fn add_schema(&self, schema: CraneSchema) -> Result<u64, DataError> {
+        self.lock()?.add_schema(schema)
+    }
+
+    /// Saves the database.
+    pub fn save(&self) -> Result<(), DataError> {
+        self.lock()?.save()
+    }
+
+    /// Forces every saved value through to stable storage, see `Crane::sync`.
+    pub fn sync(&self) -> Result<(), DataError> {
+        self.lock()?.sync()
+    }
+
+    /// Returns the number of schemas in the database.
+    pub fn schema_count(&self) -> Result<u64, DataError> {
+        Ok(self.lock()?.schema_count())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use crate::{cfs::{CraneDisk, DataValue}, db::data_command::{GetKeyCommand, InsertValueCommand}};
+
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_crane() {
+        assert_send_sync::<Crane>();
+        assert_send_sync::<SharedCrane>();
+
+        let crane = SharedCrane::new(Crane::new(CraneDisk::in_memory()));
+        let mut schema = CraneSchema::new(vec![DataValue::UInt64(0), DataValue::Int32(0)]);
+        schema.names = vec!["Worker".to_owned(), "Count".to_owned()];
+        let slot = crane.add_schema(schema).unwrap();
+
+        let workers: Vec<_> = (0..4).map(|worker| {
+            let crane = crane.clone();
+            thread::spawn(move || {
+                for i in 0..10 {
+                    let mut command = InsertValueCommand::new(vec![DataValue::UInt64(worker), DataValue::Int32(i)]);
+                    crane.execute(slot, &mut command).unwrap();
+                }
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let readers: Vec<_> = (0..4).map(|worker| {
+            let crane = crane.clone();
+            thread::spawn(move || {
+                for key in (worker*10 + 1)..=(worker*10 + 10) {
+                    let mut command = GetKeyCommand::new(key);
+                    crane.execute(slot, &mut command).unwrap();
+                    assert_ne!(command.get_result(), None);
+                }
+            })
+        }).collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(crane.schema_count().unwrap(), 1);
+    }
+}
@@ -1,7 +1,8 @@
 mod cfs;
 mod db;
 
-pub use cfs::{Buffer, CraneDisk, CranePartition, Writer, Reader, DataValue, CraneSchema};
+pub use cfs::{Buffer, CraneDisk, DiskOptions, Durability, OpenOptions, ChecksumKind, EncryptionKey, EncryptionKind, FSError, CranePartition, Writer, Reader, DataValue, CraneSchema, Date, Time, Timestamp, Interval, ParseDateTimeError, MAX_OFFSET_MINUTES, Blob, BlobRef, BlobReader, BlobWriter, MAX_INLINE_BLOB_LEN, Storage, FileStorage, MemoryStorage, MmapStorage, CacheStats, PartitionKind, PartitionType, MAX_PARTITION_OWNER, FREE_PARTITION_TYPE, DiskHeader, HeaderError, FORMAT_VERSION, MIN_SECTOR_LENGTH, MAX_SECTOR_LENGTH};
 pub use db::*;
 
+/// The sector length disks are initialized with unless another one is chosen
 pub const SECTOR_LENGTH: usize = 256;
\ No newline at end of file